@@ -64,6 +64,13 @@ pub struct Material {
         skip_serializing_if = "Option::is_none"
     )]
     pub ior: Option<Ior>,
+
+    #[serde(
+        default,
+        rename = "VRMC_materials_mtoon",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub vrmc_materials_mtoon: Option<vrmc_materials_mtoon::MToon>,
 }
 
 /// A set of parameter values that are used to define the metallic-roughness
@@ -125,11 +132,27 @@ pub struct PbrSpecularGlossiness {
 
 /// Defines the normal texture of a material.
 #[derive(Clone, Debug, Default, Deserialize, Serialize, Validate)]
-pub struct NormalTexture {}
+pub struct NormalTexture {
+    #[cfg(feature = "KHR_texture_transform")]
+    #[serde(
+        default,
+        rename = "KHR_texture_transform",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub texture_transform: Option<crate::extensions::texture::TextureTransform>,
+}
 
 /// Defines the occlusion texture of a material.
 #[derive(Clone, Debug, Default, Deserialize, Serialize, Validate)]
-pub struct OcclusionTexture {}
+pub struct OcclusionTexture {
+    #[cfg(feature = "KHR_texture_transform")]
+    #[serde(
+        default,
+        rename = "KHR_texture_transform",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub texture_transform: Option<crate::extensions::texture::TextureTransform>,
+}
 
 /// The diffuse factor of a material.
 #[cfg(feature = "KHR_materials_pbrSpecularGlossiness")]
@@ -373,3 +396,115 @@ pub struct Specular {
     #[cfg_attr(not(feature = "extras"), serde(skip_serializing))]
     pub extras: Extras,
 }
+
+pub mod vrmc_materials_mtoon {
+    use crate::texture;
+    use gltf_derive::Validate;
+    use serde_derive::{Deserialize, Serialize};
+
+    /// The VRM toon shader's parameters. Textures left out contribute nothing.
+    #[derive(Clone, Debug, Deserialize, Serialize, Validate)]
+    pub struct MToon {
+        #[serde(default, rename = "specVersion")]
+        pub spec_version: String,
+
+        #[serde(default, rename = "transparentWithZWrite")]
+        pub transparent_with_z_write: bool,
+
+        #[serde(default, rename = "renderQueueOffsetNumber")]
+        pub render_queue_offset_number: i32,
+
+        #[serde(default, rename = "shadeColorFactor")]
+        pub shade_color_factor: [f32; 3],
+
+        #[serde(default, rename = "shadeMultiplyTexture")]
+        pub shade_multiply_texture: Option<texture::Info>,
+
+        #[serde(default, rename = "shadingShiftFactor")]
+        pub shading_shift_factor: f32,
+
+        #[serde(default, rename = "shadingShiftTexture")]
+        pub shading_shift_texture: Option<ShadingShiftTexture>,
+
+        #[serde(default = "default_toony", rename = "shadingToonyFactor")]
+        pub shading_toony_factor: f32,
+
+        #[serde(default = "default_gi_equalization", rename = "giEqualizationFactor")]
+        pub gi_equalization_factor: f32,
+
+        #[serde(default = "default_one3", rename = "matcapFactor")]
+        pub matcap_factor: [f32; 3],
+
+        #[serde(default, rename = "matcapTexture")]
+        pub matcap_texture: Option<texture::Info>,
+
+        #[serde(default, rename = "parametricRimColorFactor")]
+        pub parametric_rim_color_factor: [f32; 3],
+
+        #[serde(default, rename = "rimMultiplyTexture")]
+        pub rim_multiply_texture: Option<texture::Info>,
+
+        #[serde(default = "default_one", rename = "rimLightingMixFactor")]
+        pub rim_lighting_mix_factor: f32,
+
+        #[serde(default = "default_fresnel_power", rename = "parametricRimFresnelPowerFactor")]
+        pub parametric_rim_fresnel_power_factor: f32,
+
+        #[serde(default, rename = "parametricRimLiftFactor")]
+        pub parametric_rim_lift_factor: f32,
+
+        /// "none", "worldCoordinates" or "screenCoordinates".
+        #[serde(default = "default_outline_width_mode", rename = "outlineWidthMode")]
+        pub outline_width_mode: String,
+
+        #[serde(default, rename = "outlineWidthFactor")]
+        pub outline_width_factor: f32,
+
+        #[serde(default, rename = "outlineWidthMultiplyTexture")]
+        pub outline_width_multiply_texture: Option<texture::Info>,
+
+        #[serde(default, rename = "outlineColorFactor")]
+        pub outline_color_factor: [f32; 3],
+
+        #[serde(default = "default_one", rename = "outlineLightingMixFactor")]
+        pub outline_lighting_mix_factor: f32,
+    }
+
+    #[derive(Clone, Debug, Deserialize, Serialize, Validate)]
+    pub struct ShadingShiftTexture {
+        pub index: crate::Index<crate::Texture>,
+
+        #[serde(default, rename = "texCoord")]
+        pub tex_coord: u32,
+
+        #[serde(default = "default_one")]
+        pub scale: f32,
+
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub extensions: Option<crate::extensions::texture::Info>,
+    }
+
+    fn default_one() -> f32 {
+        1.0
+    }
+
+    fn default_one3() -> [f32; 3] {
+        [1.0, 1.0, 1.0]
+    }
+
+    fn default_toony() -> f32 {
+        0.9
+    }
+
+    fn default_gi_equalization() -> f32 {
+        0.9
+    }
+
+    fn default_fresnel_power() -> f32 {
+        5.0
+    }
+
+    fn default_outline_width_mode() -> String {
+        "none".to_owned()
+    }
+}
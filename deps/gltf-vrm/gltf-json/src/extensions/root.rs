@@ -28,6 +28,21 @@ pub struct Root {
         skip_serializing_if = "Option::is_none"
     )]
     pub vrmc_vrm: Option<VrmcVrm>,
+
+    #[serde(
+        default,
+        rename = "VRMC_springBone",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub vrmc_spring_bone: Option<VrmcSpringBone>,
+
+    /// The VRM 0.x extension, superseded by `VRMC_vrm` and `VRMC_springBone`.
+    #[serde(
+        default,
+        rename = "VRM",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub vrm0: Option<Vrm0>,
 }
 
 #[cfg(feature = "KHR_lights_punctual")]
@@ -85,11 +100,21 @@ pub struct VrmcVrm {
     pub spec_version: String,
     pub expressions: VrmExpressions,
     pub humanoid: VrmHumanoid,
+
+    #[serde(
+        default,
+        rename = "lookAt",
+    )]
+    pub look_at: Option<VrmLookAt>,
 }
 
 #[derive(Clone, Debug, Default, Deserialize, Serialize, Validate)]
 pub struct VrmExpressions {
+    #[serde(default)]
     pub preset: HashMap<String, VrmExpression>,
+
+    #[serde(default)]
+    pub custom: HashMap<String, VrmExpression>,
 }
 
 #[derive(Clone, Debug, Default, Deserialize, Serialize, Validate)]
@@ -173,3 +198,472 @@ pub struct VrmHumanoid {
 pub struct HumanBone {
     pub node: u32,
 }
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Validate)]
+pub struct VrmLookAt {
+    #[serde(
+        default,
+        rename = "offsetFromHeadBone",
+    )]
+    pub offset_from_head_bone: [f32; 3],
+
+    #[serde(rename = "type")]
+    pub type_: String,
+
+    #[serde(
+        default,
+        rename = "rangeMapHorizontalInner",
+        skip_serializing_if = "Option::is_none",
+    )]
+    pub range_map_horizontal_inner: Option<VrmLookAtRangeMap>,
+
+    #[serde(
+        default,
+        rename = "rangeMapHorizontalOuter",
+        skip_serializing_if = "Option::is_none",
+    )]
+    pub range_map_horizontal_outer: Option<VrmLookAtRangeMap>,
+
+    #[serde(
+        default,
+        rename = "rangeMapVerticalDown",
+        skip_serializing_if = "Option::is_none",
+    )]
+    pub range_map_vertical_down: Option<VrmLookAtRangeMap>,
+
+    #[serde(
+        default,
+        rename = "rangeMapVerticalUp",
+        skip_serializing_if = "Option::is_none",
+    )]
+    pub range_map_vertical_up: Option<VrmLookAtRangeMap>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Validate)]
+pub struct VrmLookAtRangeMap {
+    #[serde(rename = "inputMaxValue")]
+    pub input_max_value: f32,
+    #[serde(rename = "outputScale")]
+    pub output_scale: f32,
+}
+
+impl VrmLookAt {
+    /// The range map to use where one is missing. The spec's default output is 10 degrees of eye
+    /// rotation for bone look-at, but a full weight of 1 for expression look-at.
+    pub fn default_range_map(&self) -> VrmLookAtRangeMap {
+        VrmLookAtRangeMap {
+            input_max_value: 90.0,
+            output_scale: if self.type_ == "expression" { 1.0 } else { 10.0 },
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Validate)]
+pub struct VrmcSpringBone {
+    #[serde(
+        default,
+        rename = "specVersion",
+    )]
+    pub spec_version: String,
+
+    #[serde(default)]
+    pub colliders: Vec<SpringCollider>,
+
+    #[serde(
+        default,
+        rename = "colliderGroups",
+    )]
+    pub collider_groups: Vec<SpringColliderGroup>,
+
+    #[serde(default)]
+    pub springs: Vec<Spring>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Validate)]
+pub struct SpringCollider {
+    pub node: u32,
+    pub shape: SpringColliderShape,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Validate)]
+pub struct SpringColliderShape {
+    #[serde(default)]
+    pub sphere: Option<SpringSphere>,
+
+    #[serde(default)]
+    pub capsule: Option<SpringCapsule>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Validate)]
+pub struct SpringSphere {
+    #[serde(default)]
+    pub offset: [f32; 3],
+
+    #[serde(default)]
+    pub radius: f32,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Validate)]
+pub struct SpringCapsule {
+    #[serde(default)]
+    pub offset: [f32; 3],
+
+    #[serde(default)]
+    pub radius: f32,
+
+    #[serde(default)]
+    pub tail: [f32; 3],
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Validate)]
+pub struct SpringColliderGroup {
+    #[serde(default)]
+    pub name: String,
+
+    pub colliders: Vec<u32>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Validate)]
+pub struct Spring {
+    #[serde(default)]
+    pub name: String,
+
+    pub joints: Vec<SpringJoint>,
+
+    #[serde(
+        default,
+        rename = "colliderGroups",
+    )]
+    pub collider_groups: Vec<u32>,
+
+    #[serde(default)]
+    pub center: Option<u32>,
+
+    /// Not part of VRMC_springBone: springs converted from VRM 0.x also swing their last joint,
+    /// toward a tail this far beyond it along the bone.
+    #[serde(skip)]
+    pub leaf_tail: Option<f32>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Validate)]
+pub struct SpringJoint {
+    pub node: u32,
+
+    #[serde(
+        default,
+        rename = "hitRadius",
+    )]
+    pub hit_radius: f32,
+
+    #[serde(default = "SpringJoint::default_stiffness")]
+    pub stiffness: f32,
+
+    #[serde(
+        default,
+        rename = "gravityPower",
+    )]
+    pub gravity_power: f32,
+
+    #[serde(
+        default = "SpringJoint::default_gravity_dir",
+        rename = "gravityDir",
+    )]
+    pub gravity_dir: [f32; 3],
+
+    #[serde(
+        default = "SpringJoint::default_drag_force",
+        rename = "dragForce",
+    )]
+    pub drag_force: f32,
+}
+
+impl SpringJoint {
+    fn default_stiffness() -> f32 {
+        1.0
+    }
+
+    fn default_gravity_dir() -> [f32; 3] {
+        [0.0, -1.0, 0.0]
+    }
+
+    fn default_drag_force() -> f32 {
+        0.5
+    }
+}
+
+/// A vector in Unity's left-handed coordinates, as VRM 0.x writes them.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, Validate)]
+pub struct Vrm0Vec3 {
+    #[serde(default)]
+    pub x: f32,
+    #[serde(default)]
+    pub y: f32,
+    #[serde(default)]
+    pub z: f32,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Validate)]
+pub struct Vrm0 {
+    #[serde(
+        default,
+        rename = "specVersion",
+    )]
+    pub spec_version: String,
+
+    #[serde(default)]
+    pub humanoid: Vrm0Humanoid,
+
+    #[serde(
+        default,
+        rename = "firstPerson",
+    )]
+    pub first_person: Vrm0FirstPerson,
+
+    #[serde(
+        default,
+        rename = "blendShapeMaster",
+    )]
+    pub blend_shape_master: Vrm0BlendShapeMaster,
+
+    #[serde(
+        default,
+        rename = "secondaryAnimation",
+    )]
+    pub secondary_animation: Vrm0SecondaryAnimation,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Validate)]
+pub struct Vrm0Humanoid {
+    #[serde(
+        default,
+        rename = "humanBones",
+    )]
+    pub human_bones: Vec<Vrm0HumanBone>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Validate)]
+pub struct Vrm0HumanBone {
+    pub bone: String,
+    pub node: u32,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Validate)]
+pub struct Vrm0FirstPerson {
+    #[serde(
+        default,
+        rename = "firstPersonBoneOffset",
+    )]
+    pub first_person_bone_offset: Vrm0Vec3,
+
+    /// "Bone" or "BlendShape".
+    #[serde(
+        default,
+        rename = "lookAtTypeName",
+    )]
+    pub look_at_type_name: String,
+
+    #[serde(
+        default,
+        rename = "lookAtHorizontalInner",
+    )]
+    pub look_at_horizontal_inner: Vrm0DegreeMap,
+
+    #[serde(
+        default,
+        rename = "lookAtHorizontalOuter",
+    )]
+    pub look_at_horizontal_outer: Vrm0DegreeMap,
+
+    #[serde(
+        default,
+        rename = "lookAtVerticalDown",
+    )]
+    pub look_at_vertical_down: Vrm0DegreeMap,
+
+    #[serde(
+        default,
+        rename = "lookAtVerticalUp",
+    )]
+    pub look_at_vertical_up: Vrm0DegreeMap,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Validate)]
+pub struct Vrm0DegreeMap {
+    #[serde(
+        default = "Vrm0DegreeMap::default_x_range",
+        rename = "xRange",
+    )]
+    pub x_range: f32,
+
+    #[serde(
+        default = "Vrm0DegreeMap::default_y_range",
+        rename = "yRange",
+    )]
+    pub y_range: f32,
+}
+
+impl Vrm0DegreeMap {
+    fn default_x_range() -> f32 {
+        90.0
+    }
+
+    fn default_y_range() -> f32 {
+        10.0
+    }
+}
+
+impl Default for Vrm0DegreeMap {
+    fn default() -> Self {
+        Self {
+            x_range: Self::default_x_range(),
+            y_range: Self::default_y_range(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Validate)]
+pub struct Vrm0BlendShapeMaster {
+    #[serde(
+        default,
+        rename = "blendShapeGroups",
+    )]
+    pub blend_shape_groups: Vec<Vrm0BlendShapeGroup>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Validate)]
+pub struct Vrm0BlendShapeGroup {
+    #[serde(default)]
+    pub name: String,
+
+    #[serde(
+        default,
+        rename = "presetName",
+    )]
+    pub preset_name: String,
+
+    #[serde(default)]
+    pub binds: Vec<Vrm0BlendShapeBind>,
+
+    #[serde(
+        default,
+        rename = "materialValues",
+    )]
+    pub material_values: Vec<Vrm0MaterialValueBind>,
+
+    #[serde(
+        default,
+        rename = "isBinary",
+    )]
+    pub is_binary: bool,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Validate)]
+pub struct Vrm0BlendShapeBind {
+    pub mesh: u32,
+    pub index: u32,
+    /// 0 to 100.
+    #[serde(default)]
+    pub weight: f32,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Validate)]
+pub struct Vrm0MaterialValueBind {
+    #[serde(rename = "materialName")]
+    pub material_name: String,
+    #[serde(rename = "propertyName")]
+    pub property_name: String,
+    #[serde(
+        default,
+        rename = "targetValue",
+    )]
+    pub target_value: Vec<f32>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Validate)]
+pub struct Vrm0SecondaryAnimation {
+    #[serde(
+        default,
+        rename = "boneGroups",
+    )]
+    pub bone_groups: Vec<Vrm0BoneGroup>,
+
+    #[serde(
+        default,
+        rename = "colliderGroups",
+    )]
+    pub collider_groups: Vec<Vrm0ColliderGroup>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Validate)]
+pub struct Vrm0BoneGroup {
+    #[serde(default)]
+    pub comment: String,
+
+    /// Misspelled in the specification itself.
+    #[serde(
+        default,
+        rename = "stiffiness",
+    )]
+    pub stiffness: f32,
+
+    #[serde(
+        default,
+        rename = "gravityPower",
+    )]
+    pub gravity_power: f32,
+
+    #[serde(
+        default,
+        rename = "gravityDir",
+    )]
+    pub gravity_dir: Vrm0Vec3,
+
+    #[serde(
+        default,
+        rename = "dragForce",
+    )]
+    pub drag_force: f32,
+
+    /// A node index, or -1 for none.
+    #[serde(default = "Vrm0BoneGroup::default_center")]
+    pub center: i32,
+
+    #[serde(
+        default,
+        rename = "hitRadius",
+    )]
+    pub hit_radius: f32,
+
+    /// Roots of the simulated hierarchies.
+    #[serde(default)]
+    pub bones: Vec<u32>,
+
+    #[serde(
+        default,
+        rename = "colliderGroups",
+    )]
+    pub collider_groups: Vec<u32>,
+}
+
+impl Vrm0BoneGroup {
+    fn default_center() -> i32 {
+        -1
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Validate)]
+pub struct Vrm0ColliderGroup {
+    pub node: u32,
+
+    #[serde(default)]
+    pub colliders: Vec<Vrm0Collider>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Validate)]
+pub struct Vrm0Collider {
+    #[serde(default)]
+    pub offset: Vrm0Vec3,
+
+    #[serde(default)]
+    pub radius: f32,
+}
@@ -20,6 +20,13 @@ pub struct Node {
         skip_serializing_if = "Option::is_none"
     )]
     pub khr_lights_punctual: Option<khr_lights_punctual::KhrLightsPunctual>,
+
+    #[serde(
+        default,
+        rename = "VRMC_node_constraint",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub vrmc_node_constraint: Option<vrmc_node_constraint::VrmcNodeConstraint>,
 }
 
 #[cfg(feature = "KHR_lights_punctual")]
@@ -222,6 +229,71 @@ pub mod khr_materials_variants {
     }
 }
 
+pub mod vrmc_node_constraint {
+    use gltf_derive::Validate;
+    use serde_derive::{Deserialize, Serialize};
+
+    #[derive(Clone, Debug, Default, Deserialize, Serialize, Validate)]
+    pub struct VrmcNodeConstraint {
+        #[serde(
+            default,
+            rename = "specVersion",
+        )]
+        pub spec_version: String,
+
+        pub constraint: Constraint,
+    }
+
+    /// Exactly one of the fields is expected to be set.
+    #[derive(Clone, Debug, Default, Deserialize, Serialize, Validate)]
+    pub struct Constraint {
+        #[serde(default)]
+        pub roll: Option<RollConstraint>,
+
+        #[serde(default)]
+        pub aim: Option<AimConstraint>,
+
+        #[serde(default)]
+        pub rotation: Option<RotationConstraint>,
+    }
+
+    #[derive(Clone, Debug, Deserialize, Serialize, Validate)]
+    pub struct RollConstraint {
+        pub source: u32,
+
+        /// "X", "Y" or "Z".
+        #[serde(rename = "rollAxis")]
+        pub roll_axis: String,
+
+        #[serde(default = "default_weight")]
+        pub weight: f32,
+    }
+
+    #[derive(Clone, Debug, Deserialize, Serialize, Validate)]
+    pub struct AimConstraint {
+        pub source: u32,
+
+        /// "PositiveX", "NegativeX", "PositiveY", "NegativeY", "PositiveZ" or "NegativeZ".
+        #[serde(rename = "aimAxis")]
+        pub aim_axis: String,
+
+        #[serde(default = "default_weight")]
+        pub weight: f32,
+    }
+
+    #[derive(Clone, Debug, Deserialize, Serialize, Validate)]
+    pub struct RotationConstraint {
+        pub source: u32,
+
+        #[serde(default = "default_weight")]
+        pub weight: f32,
+    }
+
+    fn default_weight() -> f32 {
+        1.0
+    }
+}
+
 /// The root `Node`s of a scene.
 #[derive(Clone, Debug, Default, Deserialize, Serialize, Validate)]
 pub struct Scene {}
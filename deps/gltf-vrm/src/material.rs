@@ -219,6 +219,11 @@ impl<'a> Material<'a> {
             .map_or(false, |extensions| extensions.unlit.is_some())
     }
 
+    /// The toon shading parameters defined by the `VRMC_materials_mtoon` extension.
+    pub fn mtoon(&self) -> Option<&'a json::extensions::material::vrmc_materials_mtoon::MToon> {
+        self.json.extensions.as_ref()?.vrmc_materials_mtoon.as_ref()
+    }
+
     /// Optional application specific data.
     pub fn extras(&self) -> &'a json::Extras {
         &self.json.extras
@@ -564,6 +569,18 @@ impl<'a> NormalTexture<'a> {
         self.texture.clone()
     }
 
+    /// Returns texture transform information
+    #[cfg(feature = "KHR_texture_transform")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "KHR_texture_transform")))]
+    pub fn texture_transform(&self) -> Option<texture::TextureTransform<'a>> {
+        self.json
+            .extensions
+            .as_ref()?
+            .texture_transform
+            .as_ref()
+            .map(texture::TextureTransform::new)
+    }
+
     /// Optional application specific data.
     pub fn extras(&self) -> &'a json::Extras {
         &self.json.extras
@@ -603,6 +620,18 @@ impl<'a> OcclusionTexture<'a> {
         self.texture.clone()
     }
 
+    /// Returns texture transform information
+    #[cfg(feature = "KHR_texture_transform")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "KHR_texture_transform")))]
+    pub fn texture_transform(&self) -> Option<texture::TextureTransform<'a>> {
+        self.json
+            .extensions
+            .as_ref()?
+            .texture_transform
+            .as_ref()
+            .map(texture::TextureTransform::new)
+    }
+
     /// Optional application specific data.
     pub fn extras(&self) -> &'a json::Extras {
         &self.json.extras
@@ -163,6 +163,16 @@ impl<'a> Node<'a> {
         }
     }
 
+    /// Returns the constraint on this node as defined by the `VRMC_node_constraint` extension.
+    pub fn constraint(&self) -> Option<&'a json::extensions::scene::vrmc_node_constraint::Constraint> {
+        self.json
+            .extensions
+            .as_ref()?
+            .vrmc_node_constraint
+            .as_ref()
+            .map(|c| &c.constraint)
+    }
+
     /// Returns the mesh referenced by this node.
     pub fn mesh(&self) -> Option<Mesh<'a>> {
         self.json
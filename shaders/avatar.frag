@@ -0,0 +1,163 @@
+#version 460 core
+
+in vec3 frag_position;
+in vec3 frag_normal;
+in vec2 frag_texcoord;
+in vec2 frag_texcoord1;
+in vec4 frag_tangent;
+
+out vec4 color;
+
+// Texture units, matching utils::TEXUNIT_*.
+const int TEXUNIT_BASE_COLOR = 0;
+const int TEXUNIT_EMISSIVE = 1;
+const int TEXUNIT_SHADE_MULTIPLY = 2;
+const int TEXUNIT_SHADING_SHIFT = 3;
+const int TEXUNIT_MATCAP = 4;
+const int TEXUNIT_RIM_MULTIPLY = 5;
+const int TEXUNIT_METALLIC_ROUGHNESS = 7;
+const int TEXUNIT_NORMAL = 8;
+const int TEXUNIT_OCCLUSION = 9;
+
+uniform sampler2D base_color_texture;
+uniform sampler2D emissive_texture;
+uniform sampler2D shade_multiply_texture;
+uniform sampler2D shading_shift_texture;
+uniform sampler2D matcap_texture;
+uniform sampler2D rim_multiply_texture;
+uniform sampler2D metallic_roughness_texture;
+uniform sampler2D normal_texture;
+uniform sampler2D occlusion_texture;
+
+uniform mat4 view;
+uniform vec3 camera_pos;
+
+// Per texture unit: which TEXCOORD set to sample with and its KHR_texture_transform.
+uniform int texcoord_sets[10];
+uniform mat3 texture_transforms[10];
+
+// Material values with expression binds applied.
+uniform vec4 base_color;
+uniform vec3 emission;
+uniform vec3 shade_color;
+uniform vec2 uv_scale;
+uniform vec2 uv_offset;
+uniform vec3 matcap_color;
+uniform vec3 rim_color;
+uniform vec3 outline_color;
+
+// glTF metallic-roughness; alpha_mode is 0 opaque, 1 mask, 2 blend.
+uniform float metallic;
+uniform float roughness;
+// zero without a normal texture
+uniform float normal_scale;
+uniform float occlusion_strength;
+uniform int alpha_mode;
+uniform float alpha_cutoff;
+
+// MToon, when mtoon is 1.
+uniform int mtoon;
+uniform float shading_shift;
+uniform float shading_shift_scale;
+uniform float shading_toony;
+uniform float gi_equalization;
+uniform float rim_lighting_mix;
+uniform float rim_fresnel_power;
+uniform float rim_lift;
+uniform int outline;
+uniform float outline_lighting_mix;
+
+const vec3 light_direction = vec3(0.3, 0.8, 0.6);
+const vec3 light_color = vec3(1.0);
+const vec3 ambient = vec3(0.3);
+
+vec2 uv(int unit) {
+    vec2 t = texcoord_sets[unit] == 1 ? frag_texcoord1 : frag_texcoord;
+    return (texture_transforms[unit] * vec3(t, 1.0)).xy * uv_scale + uv_offset;
+}
+
+// Apply the normal texture in the mesh's tangent frame, or, for meshes without tangents, in one
+// built from screen-space derivatives of the position and normal texture UV.
+vec3 perturb(vec3 n) {
+    vec2 st = uv(TEXUNIT_NORMAL);
+    vec3 m = texture(normal_texture, st).xyz * 2.0 - 1.0;
+    m.xy *= normal_scale;
+    vec3 dp1 = dFdx(frag_position);
+    vec3 dp2 = dFdy(frag_position);
+    vec2 duv1 = dFdx(st);
+    vec2 duv2 = dFdy(st);
+
+    vec3 t;
+    vec3 b;
+    if (dot(frag_tangent.xyz, frag_tangent.xyz) > 0.0) {
+        t = normalize(frag_tangent.xyz - n * dot(n, frag_tangent.xyz));
+        b = cross(n, t) * frag_tangent.w;
+    } else {
+        vec3 dp2perp = cross(dp2, n);
+        vec3 dp1perp = cross(n, dp1);
+        t = dp2perp * duv1.x + dp1perp * duv2.x;
+        b = dp2perp * duv1.y + dp1perp * duv2.y;
+        float scale = inversesqrt(max(max(dot(t, t), dot(b, b)), 1e-12));
+        t *= scale;
+        b *= scale;
+    }
+    return normalize(mat3(t, b, n) * m);
+}
+
+float linearstep(float a, float b, float t) {
+    return clamp((t - a) / (b - a), 0.0, 1.0);
+}
+
+void main() {
+    vec4 albedo = base_color * texture(base_color_texture, uv(TEXUNIT_BASE_COLOR));
+
+    vec3 n = normalize(frag_normal);
+    if (!gl_FrontFacing) {
+        n = -n;
+    }
+    n = perturb(n);
+    vec3 l = normalize(light_direction);
+    vec3 v = normalize(camera_pos - frag_position);
+    float occlusion = mix(1.0, texture(occlusion_texture, uv(TEXUNIT_OCCLUSION)).r, occlusion_strength);
+    vec3 emissive = emission * texture(emissive_texture, uv(TEXUNIT_EMISSIVE)).rgb;
+
+    vec3 lit;
+    if (mtoon == 1) {
+        float shading = dot(n, l) + shading_shift
+            + texture(shading_shift_texture, uv(TEXUNIT_SHADING_SHIFT)).r * shading_shift_scale;
+        shading = linearstep(-1.0 + shading_toony, 1.0 - shading_toony, shading);
+        vec3 shade = shade_color * texture(shade_multiply_texture, uv(TEXUNIT_SHADE_MULTIPLY)).rgb;
+        vec3 direct = mix(shade, albedo.rgb, shading) * light_color;
+        // with a single uniform ambient term, equalization has nothing to even out
+        vec3 gi = albedo.rgb * ambient * occlusion;
+
+        vec3 view_normal = normalize(mat3(view) * n);
+        vec3 rim = matcap_color * texture(matcap_texture, view_normal.xy * 0.5 + 0.5).rgb;
+        float fresnel = clamp(1.0 - dot(n, v) + rim_lift, 0.0, 1.0);
+        rim += rim_color * pow(fresnel, max(rim_fresnel_power, 0.00001));
+        rim *= texture(rim_multiply_texture, uv(TEXUNIT_RIM_MULTIPLY)).rgb;
+        rim *= mix(vec3(1.0), direct + gi, rim_lighting_mix);
+
+        lit = direct + gi + rim;
+        if (outline == 1) {
+            lit = outline_color * mix(vec3(1.0), lit, outline_lighting_mix);
+            emissive = vec3(0.0);
+        }
+    } else {
+        vec4 mr = texture(metallic_roughness_texture, uv(TEXUNIT_METALLIC_ROUGHNESS));
+        float m = metallic * mr.b;
+        float r = clamp(roughness * mr.g, 0.04, 1.0);
+        float diffuse = max(dot(n, l), 0.0);
+        vec3 h = normalize(l + v);
+        float shininess = 2.0 / (r * r * r * r) - 2.0;
+        vec3 f0 = mix(vec3(0.04), albedo.rgb, m);
+        vec3 specular = f0 * pow(max(dot(n, h), 0.0), shininess) * diffuse;
+        lit = albedo.rgb * (1.0 - m) * (ambient * occlusion + diffuse * light_color) + specular * light_color;
+    }
+
+    // discard last, so every texture above is sampled in uniform control flow
+    if (alpha_mode == 1 && albedo.a < alpha_cutoff) {
+        discard;
+    }
+    color = vec4(lit + emissive, alpha_mode == 2 ? albedo.a : 1.0);
+}
@@ -0,0 +1,101 @@
+#version 460 core
+
+// Avatar vertex shader. Attribute locations are bound by shader.rs from the utils::ATTRIB_* constants.
+
+in vec3 vertex;
+in vec3 normal;
+in vec2 texcoord;
+in vec4 joint;
+in vec4 weight;
+in vec2 texcoord1;
+in vec4 joint1;
+// zero for meshes without WEIGHTS_1
+in vec4 weight1;
+// zero for meshes without TANGENT
+in vec4 tangent;
+
+uniform mat4 view;
+uniform mat4 projection;
+// The node's global transform; identity for skinned nodes, whose joint matrices already place them.
+uniform mat4 position;
+uniform mat4 joint_matrices[256];
+
+// Morph target deltas packed per primitive by vrm.rs: target t's position delta for vertex v is
+// morph_deltas[(t * morph_vertex_count + v) * 2] and its normal delta the element after.
+layout(std430, binding = 0) buffer Morphs {
+    vec4 morph_deltas[];
+};
+uniform int morph_count;
+uniform int morph_vertex_count;
+uniform int morph_indices[32];
+uniform float morph_weights[32];
+
+uniform int texcoord_sets[10];
+uniform mat3 texture_transforms[10];
+uniform vec2 uv_scale;
+uniform vec2 uv_offset;
+
+// MToon outline pass: push the hull out along the normal, in world units (mode 1) or as a
+// fraction of the screen height (mode 2), scaled by the outline width texture's green channel.
+uniform int outline;
+uniform int outline_width_mode;
+uniform float outline_width;
+uniform sampler2D outline_width_texture;
+const int TEXUNIT_OUTLINE_WIDTH = 6;
+
+out vec3 frag_position;
+out vec3 frag_normal;
+out vec2 frag_texcoord;
+out vec2 frag_texcoord1;
+out vec4 frag_tangent;
+
+void main() {
+    vec3 v = vertex;
+    vec3 n = normal;
+    for (int i = 0; i < morph_count; ++i) {
+        int base = (morph_indices[i] * morph_vertex_count + gl_VertexID) * 2;
+        v += morph_weights[i] * morph_deltas[base].xyz;
+        n += morph_weights[i] * morph_deltas[base + 1].xyz;
+    }
+
+    // unskinned meshes read the default weight (0, 0, 0, 1) against identity joint matrices
+    mat4 skin = weight.x * joint_matrices[int(joint.x)]
+        + weight.y * joint_matrices[int(joint.y)]
+        + weight.z * joint_matrices[int(joint.z)]
+        + weight.w * joint_matrices[int(joint.w)]
+        + weight1.x * joint_matrices[int(joint1.x)]
+        + weight1.y * joint_matrices[int(joint1.y)]
+        + weight1.z * joint_matrices[int(joint1.z)]
+        + weight1.w * joint_matrices[int(joint1.w)];
+    mat4 model = position * skin;
+
+    vec4 world = model * vec4(v, 1.0);
+    vec3 world_normal = normalize(mat3(model) * n);
+
+    float width = 0.0;
+    if (outline == 1) {
+        vec2 uv = texcoord_sets[TEXUNIT_OUTLINE_WIDTH] == 1 ? texcoord1 : texcoord;
+        uv = (texture_transforms[TEXUNIT_OUTLINE_WIDTH] * vec3(uv, 1.0)).xy * uv_scale + uv_offset;
+        width = outline_width * textureLod(outline_width_texture, uv, 0.0).g;
+        if (outline_width_mode == 1) {
+            world.xyz += world_normal * width;
+        }
+    }
+
+    frag_position = world.xyz;
+    frag_normal = world_normal;
+    frag_texcoord = texcoord;
+    frag_texcoord1 = texcoord1;
+    frag_tangent = vec4(mat3(model) * tangent.xyz, tangent.w);
+    gl_Position = projection * view * world;
+
+    if (outline == 1 && outline_width_mode == 2) {
+        // projection[1][1] / projection[0][0] is the aspect ratio
+        vec2 aspect = vec2(projection[1][1] / projection[0][0], 1.0);
+        vec2 dir = (projection * view * vec4(world_normal, 0.0)).xy * aspect;
+        if (dot(dir, dir) > 0.0) {
+            // NDC spans 2 units of screen height
+            gl_Position.xy += normalize(dir) / aspect * width * 2.0 * gl_Position.w;
+        }
+    }
+}
@@ -1,11 +1,14 @@
 pub mod hat;
+pub mod spring;
 
-use crate::{vrm, context, shader, gl, fig};
+use crate::{vrm, context, shader, gl, config};
 
 use std::collections::HashMap;
 
 use image::{AnimationDecoder, ImageDecoder};
 
+use gltf::json::extensions::root::{VrmLookAt, VrmLookAtRangeMap};
+
 type Color = (u8, u8, u8);
 
 #[derive(Clone)]
@@ -84,7 +87,7 @@ impl PaletteEntry {
 }
 
 pub struct PalettePlayers {
-    pub players: HashMap<context::PaletteType, context::VideoPlayer<'static>>,
+    pub players: HashMap<context::PaletteType, context::VideoPlayer>,
 }
 
 impl PalettePlayers {
@@ -147,24 +150,87 @@ impl Palette {
     }
 }
 
+/// Split a tracked head pose into the hips offset and a local rotation for each configured bone.
+fn tracking_pose(
+    orientation: glam::Quat,
+    translation: glam::Vec3,
+    weights: &config::Tracking,
+) -> (glam::Vec3, Vec<(&str, glam::Quat)>) {
+    let offset = translation * weights.translation;
+    let rotation = orientation.inverse();
+    let lean = glam::Quat::from_euler(
+        glam::EulerRot::XYZ,
+        offset.z * weights.lean,
+        0.0,
+        -offset.x * weights.lean,
+    );
+    let bones = weights.bones.iter().map(|w| {
+        let q = glam::Quat::IDENTITY.slerp(rotation, w.rotation)
+            .mul_quat(glam::Quat::IDENTITY.slerp(lean, w.lean));
+        (w.bone.as_str(), q)
+    }).collect();
+    (offset * weights.hips, bones)
+}
+
+/// Scale a gaze angle by a lookAt range map, saturating at its input maximum.
+fn look_at_range(m: &VrmLookAtRangeMap, v: f32) -> f32 {
+    if m.input_max_value <= 0.0 { return 0.0; }
+    v.abs().min(m.input_max_value) / m.input_max_value * m.output_scale
+}
+
+/// Signed vertical eye output for a pitch, positive looking up.
+fn look_at_vertical(look_at: &VrmLookAt, pitch: f32) -> f32 {
+    let default = look_at.default_range_map();
+    if pitch > 0.0 {
+        look_at_range(look_at.range_map_vertical_up.as_ref().unwrap_or(&default), pitch)
+    } else {
+        -look_at_range(look_at.range_map_vertical_down.as_ref().unwrap_or(&default), pitch)
+    }
+}
+
+/// lookLeft/Right/Up/Down weights for an expression-driven lookAt.
+fn look_weights(look_at: &VrmLookAt, yaw: f32, pitch: f32) -> HashMap<String, f32> {
+    let default = look_at.default_range_map();
+    let horizontal = look_at_range(look_at.range_map_horizontal_outer.as_ref().unwrap_or(&default), yaw);
+    let vertical = look_at_vertical(look_at, pitch);
+    let (left, right) = if yaw > 0.0 { (horizontal, 0.0) } else { (0.0, horizontal) };
+    HashMap::from([
+        ("lookLeft".to_owned(), left.clamp(0.0, 1.0)),
+        ("lookRight".to_owned(), right.clamp(0.0, 1.0)),
+        ("lookUp".to_owned(), vertical.clamp(0.0, 1.0)),
+        ("lookDown".to_owned(), (-vertical).clamp(0.0, 1.0)),
+    ])
+}
+
 pub struct Avatar {
     pub scene: vrm::Scene,
     pub nodes: Vec<vrm::Node>,
-    pub position: Box<dyn Fn(&fig::Control) -> glam::Mat4>,
+    pub yaw: f32,
+    /// Weights for the lookUp/lookDown/lookLeft/lookRight expressions, for models with expression-based lookAt.
+    pub look_weights: HashMap<String, f32>,
+    pub springs: spring::Springs,
     // pub hats: Vec<hat::Hat>,
     // pub pumpkin: hat::Pumpkin,
 }
 
 impl Avatar {
-    pub fn new<F>(ctx: &context::Context, path: &str, position: F) -> Self
-        where F: Fn(&fig::Control) -> glam::Mat4 + 'static
-    {
-        let scene = vrm::Scene::new(&ctx, path);
+    pub fn new(ctx: &context::Context, path: &str) -> Self {
+        Self::from_scene(vrm::Scene::new(&ctx, path))
+    }
+
+    pub fn from_document(ctx: &context::Context, doc: &vrm::Document) -> Self {
+        Self::from_scene(vrm::Scene::upload(ctx, doc))
+    }
+
+    fn from_scene(scene: vrm::Scene) -> Self {
         let nodes = scene.nodes.clone();
+        let springs = spring::Springs::new(&scene);
         Self {
             scene,
             nodes,
-            position: Box::new(position),
+            yaw: 0.0,
+            look_weights: HashMap::new(),
+            springs,
             // hats: Vec::new(),
             // pumpkin: hat::Pumpkin::new(&ctx),
         }
@@ -175,21 +241,183 @@ impl Avatar {
     // }
     
     pub fn transform_bone(&mut self, nm: &str, t: &glam::Mat4) {
-        let ni = self.scene.bone_node_indices.get(nm).unwrap();
-        self.nodes[*ni].transform = self.scene.nodes[*ni].transform.mul_mat4(t);
+        if let Some(ni) = self.scene.bone_node_indices.get(nm) {
+            self.nodes[*ni].transform = self.scene.nodes[*ni].transform.mul_mat4(t);
+        }
+    }
+
+    /// Apply a transform on top of the bone's current (posed) transform.
+    pub fn transform_posed_bone(&mut self, nm: &str, t: &glam::Mat4) {
+        if let Some(ni) = self.scene.bone_node_indices.get(nm) {
+            self.nodes[*ni].transform = self.nodes[*ni].transform.mul_mat4(t);
+        }
+    }
+
+    /// Set bones to tracked local rotations, replacing their pose.
+    pub fn apply_bones(&mut self, bones: &HashMap<String, glam::Quat>) {
+        for (nm, q) in bones {
+            self.transform_bone(nm, &glam::Mat4::from_quat(*q));
+        }
+    }
+
+    /// Spread the tracked head rotation and translation over the body according to the configured weights.
+    pub fn apply_tracking(&mut self, orientation: glam::Quat, translation: glam::Vec3, weights: &config::Tracking) {
+        let (hips, bones) = tracking_pose(orientation, translation, weights);
+        self.transform_posed_bone("hips", &glam::Mat4::from_translation(hips));
+        for (bone, q) in bones {
+            self.transform_posed_bone(bone, &glam::Mat4::from_quat(q));
+        }
+    }
+
+    /// Point the eyes along a head-relative gaze (degrees, positive yaw looks left, positive pitch looks up),
+    /// clamped and scaled by the model's lookAt range maps.
+    pub fn apply_look_at(&mut self, yaw: f32, pitch: f32, expression_weights: &HashMap<String, f32>) {
+        let look_at = &self.scene.look_at;
+        if look_at.type_ == "expression" {
+            self.look_weights = look_weights(look_at, yaw, pitch);
+        } else {
+            let default = look_at.default_range_map();
+            let map = |m: &Option<VrmLookAtRangeMap>, v: f32| look_at_range(m.as_ref().unwrap_or(&default), v);
+            let vertical = look_at_vertical(look_at, pitch);
+            // an eye looking toward its own side turns outward, the other eye turns inward
+            let (left_yaw, right_yaw) = if yaw > 0.0 {
+                (map(&look_at.range_map_horizontal_outer, yaw), map(&look_at.range_map_horizontal_inner, yaw))
+            } else {
+                (-map(&look_at.range_map_horizontal_inner, yaw), -map(&look_at.range_map_horizontal_outer, yaw))
+            };
+            // lookAt expressions are overridden at render time; eye bones have to be scaled here
+            let m = self.scene.override_weights(expression_weights).look_at;
+            let eye = |y: f32| glam::Mat4::from_quat(glam::Quat::from_euler(
+                glam::EulerRot::YXZ,
+                (y * m).to_radians(),
+                -(vertical * m).to_radians(),
+                0.0,
+            ));
+            self.transform_posed_bone("leftEye", &eye(left_yaw));
+            self.transform_posed_bone("rightEye", &eye(right_yaw));
+        }
+    }
+
+    /// Step the spring bones. Call after everything else has posed the avatar this frame.
+    pub fn update_springs(&mut self, dt: f32) {
+        self.springs.update(&self.scene, &mut self.nodes, dt);
+    }
+
+    /// Return every node to its rest transform, then pose the listed bones on top of it.
+    pub fn apply_pose(&mut self, pose: &config::Pose) {
+        for (node, rest) in self.nodes.iter_mut().zip(self.scene.nodes.iter()) {
+            node.transform = rest.transform;
+        }
+        self.yaw = pose.yaw;
+        for (bone, rot) in &pose.bones {
+            self.transform_bone(bone, &glam::Mat4::from_quat(*rot));
+        }
     }
 
-    pub fn render(&self, ctx: &context::Context, shader: &shader::Shader, view: &glam::Mat4, projection: &glam::Mat4, sts: &fig::Control) {
-        let position = (*self.position)(sts);
+    pub fn render(
+        &self,
+        ctx: &context::Context,
+        shader: &shader::Shader,
+        transform: &glam::Mat4,
+        expression_weights: &HashMap<String, f32>,
+        look_weights: &HashMap<String, f32>,
+        camera_pos: &glam::Vec3,
+    ) {
+        // models face +X, so turn them a quarter to face the camera before applying the pose yaw
+        let position = transform.mul_mat4(&glam::Mat4::from_rotation_y(std::f32::consts::PI / 2.0 + self.yaw));
         let normal_matrix = position.inverse().transpose();
         unsafe {
             gl::UniformMatrix4fv(shader.uniform_normal, 1, false as u8, normal_matrix.to_cols_array().as_ptr());
         }
         let global_transforms = self.scene.compute_global_transforms(&self.nodes, &position);
-        self.scene.render(ctx, shader, &global_transforms);
+        self.scene.render(ctx, shader, &global_transforms, expression_weights, look_weights, camera_pos);
         // for h in self.hats.iter() {
         //     h.render(ctx, view, projection, &self, &global_transforms);
         // }
         // self.pumpkin.render(ctx, view, projection, &self, &global_transforms);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    #[test]
+    fn head_rotation_is_split_by_shares() {
+        let weights = config::Tracking::new();
+        let orientation = glam::Quat::from_rotation_y(0.8);
+        let (hips, bones) = tracking_pose(orientation, glam::Vec3::ZERO, &weights);
+        assert_eq!(hips, glam::Vec3::ZERO);
+        assert_eq!(bones.iter().map(|(b, _)| *b).collect::<Vec<_>>(), vec!["head", "neck", "chest", "spine"]);
+        // each bone turns its share of the way back against the tracked head
+        for ((_, q), w) in bones.iter().zip(&weights.bones) {
+            let (axis, angle) = q.to_axis_angle();
+            assert!(axis.abs_diff_eq(-glam::Vec3::Y, 1e-4));
+            assert!(close(angle, 0.8 * w.rotation), "{} != {}", angle, 0.8 * w.rotation);
+        }
+        // the default shares sum to one, so the chain undoes the whole head rotation
+        let total = bones.iter().fold(glam::Quat::IDENTITY, |acc, (_, q)| acc.mul_quat(*q));
+        assert!(total.mul_quat(orientation).abs_diff_eq(glam::Quat::IDENTITY, 1e-5));
+    }
+
+    #[test]
+    fn translation_moves_hips_and_leans_by_share() {
+        let mut weights = config::Tracking::new();
+        weights.translation = glam::Vec3::ONE;
+        weights.lean = 1.0;
+        let (hips, bones) = tracking_pose(glam::Quat::IDENTITY, glam::Vec3::new(0.0, 0.2, 0.4), &weights);
+        assert!(hips.abs_diff_eq(glam::Vec3::new(0.0, 0.1, 0.2), 1e-6));
+        // head and neck have no lean share; chest and spine each take half of the forward lean
+        assert!(bones[0].1.abs_diff_eq(glam::Quat::IDENTITY, 1e-6));
+        assert!(bones[1].1.abs_diff_eq(glam::Quat::IDENTITY, 1e-6));
+        for (_, q) in &bones[2..] {
+            assert!(q.abs_diff_eq(glam::Quat::from_rotation_x(0.2), 1e-5));
+        }
+    }
+
+    fn range(input_max_value: f32, output_scale: f32) -> Option<VrmLookAtRangeMap> {
+        Some(VrmLookAtRangeMap { input_max_value, output_scale })
+    }
+
+    #[test]
+    fn look_at_range_clamps_at_input_max() {
+        let m = range(20.0, 8.0).unwrap();
+        assert!(close(look_at_range(&m, 5.0), 2.0));
+        assert!(close(look_at_range(&m, -10.0), 4.0));
+        assert!(close(look_at_range(&m, 20.0), 8.0));
+        assert!(close(look_at_range(&m, 45.0), 8.0));
+        assert!(close(look_at_range(&m, -90.0), 8.0));
+        assert_eq!(look_at_range(&range(0.0, 8.0).unwrap(), 10.0), 0.0);
+    }
+
+    #[test]
+    fn look_weights_follow_range_maps() {
+        let look_at = VrmLookAt {
+            type_: "expression".to_owned(),
+            range_map_horizontal_outer: range(30.0, 0.5),
+            range_map_vertical_up: range(10.0, 1.0),
+            ..Default::default()
+        };
+        let w = look_weights(&look_at, 60.0, 5.0);
+        assert!(close(w["lookLeft"], 0.5));
+        assert_eq!(w["lookRight"], 0.0);
+        assert!(close(w["lookUp"], 0.5));
+        assert_eq!(w["lookDown"], 0.0);
+        // missing maps fall back to the default of 90 degrees to a full weight
+        let w = look_weights(&look_at, -15.0, -200.0);
+        assert_eq!(w["lookLeft"], 0.0);
+        assert!(close(w["lookRight"], 0.25));
+        assert!(close(w["lookDown"], 1.0));
+    }
+
+    #[test]
+    fn bone_look_at_defaults_to_ten_degrees() {
+        let look_at = VrmLookAt { type_: "bone".to_owned(), ..Default::default() };
+        assert!(close(look_at_vertical(&look_at, 45.0), 5.0));
+        assert!(close(look_at_vertical(&look_at, -180.0), -10.0));
+    }
+}
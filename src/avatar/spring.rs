@@ -0,0 +1,259 @@
+// VRMC_springBone secondary animation: verlet-integrated joint chains for hair, ears and clothing.
+//
+// Each joint but the last in a spring owns the segment to the next joint; springs converted from VRM 0.x
+// give the last joint a segment of its own, continuing the bone from its parent. A tail is integrated in
+// model space (or the spring's center node space), pulled back toward the rest direction by stiffness,
+// pushed by gravity, damped by drag, kept at its rest length and pushed out of the colliders; the
+// joint is then rotated to point at it.
+
+use crate::vrm;
+
+enum Shape {
+    Sphere { offset: glam::Vec3, radius: f32 },
+    Capsule { offset: glam::Vec3, tail: glam::Vec3, radius: f32 },
+}
+
+struct Collider {
+    node: usize,
+    shape: Shape,
+}
+
+impl Collider {
+    /// Push `point` out of the collider, treating it as a sphere of radius `hit_radius`.
+    fn push_out(&self, global: &glam::Mat4, point: glam::Vec3, hit_radius: f32) -> glam::Vec3 {
+        let (nearest, radius) = match self.shape {
+            Shape::Sphere { offset, radius } => (global.transform_point3(offset), radius),
+            Shape::Capsule { offset, tail, radius } => {
+                let a = global.transform_point3(offset);
+                let b = global.transform_point3(tail);
+                let ab = b - a;
+                let t = if ab.length_squared() > 0.0 { ((point - a).dot(ab) / ab.length_squared()).clamp(0.0, 1.0) } else { 0.0 };
+                (a + ab * t, radius)
+            },
+        };
+        let r = radius + hit_radius;
+        let d = point - nearest;
+        if d.length_squared() < r * r {
+            nearest + d.normalize_or_zero() * r
+        } else {
+            point
+        }
+    }
+}
+
+struct Joint {
+    node: usize,
+    rest_rotation: glam::Quat,
+    /// Direction to the tail in the joint's local space.
+    axis: glam::Vec3,
+    length: f32,
+    hit_radius: f32,
+    stiffness: f32,
+    gravity: glam::Vec3,
+    drag: f32,
+    prev_tail: glam::Vec3,
+    current_tail: glam::Vec3,
+}
+
+struct Chain {
+    joints: Vec<Joint>,
+    colliders: Vec<usize>,
+    center: Option<usize>,
+}
+
+pub struct Springs {
+    colliders: Vec<Collider>,
+    chains: Vec<Chain>,
+}
+
+impl Springs {
+    pub fn new(scene: &vrm::Scene) -> Self {
+        let sb = &scene.spring_bone;
+        let rest = scene.compute_global_transforms(&scene.nodes, &glam::Mat4::IDENTITY);
+        let colliders = sb.colliders.iter().map(|c| Collider {
+            node: c.node as _,
+            shape: match (&c.shape.sphere, &c.shape.capsule) {
+                (_, Some(cap)) => Shape::Capsule {
+                    offset: glam::Vec3::from(cap.offset),
+                    tail: glam::Vec3::from(cap.tail),
+                    radius: cap.radius,
+                },
+                (Some(sph), None) => Shape::Sphere { offset: glam::Vec3::from(sph.offset), radius: sph.radius },
+                (None, None) => Shape::Sphere { offset: glam::Vec3::ZERO, radius: 0.0 },
+            },
+        }).collect();
+        let chains = sb.springs.iter().map(|sp| {
+            let center = sp.center.map(|c| c as usize);
+            let to_center = center.map_or(glam::Mat4::IDENTITY, |c| rest[c].inverse());
+            let segments = sp.joints.windows(2).map(|w| (&w[0], rest[w[1].node as usize].w_axis.truncate()));
+            let leaf = sp.leaf_tail.and_then(|len| {
+                let j = sp.joints.last()?;
+                let pos = rest[j.node as usize].w_axis.truncate();
+                let dir = scene.parent_indices[j.node as usize]
+                    .and_then(|p| (pos - rest[p].w_axis.truncate()).try_normalize())
+                    .unwrap_or(glam::Vec3::Y);
+                Some((j, pos + dir * len))
+            });
+            let joints = segments.chain(leaf).map(|(j, tail_pos)| {
+                let node = j.node as usize;
+                let head_pos = rest[node].w_axis.truncate();
+                let (_, rest_rotation, _) = scene.nodes[node].transform.to_scale_rotation_translation();
+                let tail_local = to_center.transform_point3(tail_pos);
+                Joint {
+                    node,
+                    rest_rotation,
+                    axis: rest[node].inverse().transform_point3(tail_pos).normalize_or_zero(),
+                    length: (tail_pos - head_pos).length(),
+                    hit_radius: j.hit_radius,
+                    stiffness: j.stiffness,
+                    gravity: glam::Vec3::from(j.gravity_dir).normalize_or_zero() * j.gravity_power,
+                    drag: j.drag_force.clamp(0.0, 1.0),
+                    prev_tail: tail_local,
+                    current_tail: tail_local,
+                }
+            }).collect();
+            let colliders = sp.collider_groups.iter()
+                .flat_map(|gi| sb.collider_groups[*gi as usize].colliders.iter().map(|ci| *ci as usize))
+                .collect();
+            Chain { joints, colliders, center }
+        }).collect();
+        Self { colliders, chains }
+    }
+
+    /// Advance every spring by `dt` seconds and write the joint rotations into `nodes`.
+    pub fn update(&mut self, scene: &vrm::Scene, nodes: &mut Vec<vrm::Node>, dt: f32) {
+        if self.chains.is_empty() {
+            return;
+        }
+        let mut globals = scene.compute_global_transforms(nodes, &glam::Mat4::IDENTITY);
+        let Self { colliders, chains } = self;
+        for chain in chains.iter_mut() {
+            let center = chain.center.map_or(glam::Mat4::IDENTITY, |c| globals[c]);
+            let from_center = center.inverse();
+            for j in chain.joints.iter_mut() {
+                let parent = scene.parent_indices[j.node].map_or(glam::Mat4::IDENTITY, |p| globals[p]);
+                let (scale, _, translation) = nodes[j.node].transform.to_scale_rotation_translation();
+                let (_, parent_rotation, _) = parent.to_scale_rotation_translation();
+                let head = parent.transform_point3(translation);
+                let rest_rotation = parent_rotation * j.rest_rotation;
+                let rest_dir = rest_rotation * j.axis;
+
+                let current = center.transform_point3(j.current_tail);
+                let prev = center.transform_point3(j.prev_tail);
+                let mut next = current
+                    + (current - prev) * (1.0 - j.drag)
+                    + rest_dir * j.stiffness * dt
+                    + j.gravity * dt;
+                next = head + (next - head).normalize_or_zero() * j.length;
+                for ci in &chain.colliders {
+                    let c = &colliders[*ci];
+                    next = c.push_out(&globals[c.node], next, j.hit_radius);
+                    next = head + (next - head).normalize_or_zero() * j.length;
+                }
+                j.prev_tail = j.current_tail;
+                j.current_tail = from_center.transform_point3(next);
+
+                let to = (next - head).normalize_or_zero();
+                let rotation = if to == glam::Vec3::ZERO {
+                    rest_rotation
+                } else {
+                    glam::Quat::from_rotation_arc(rest_dir.normalize_or_zero(), to) * rest_rotation
+                };
+                let local = glam::Mat4::from_scale_rotation_translation(
+                    scale,
+                    (parent_rotation.inverse() * rotation).normalize(),
+                    translation,
+                );
+                nodes[j.node].transform = local;
+                globals[j.node] = parent.mul_mat4(&local);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use gltf::json::extensions::root::{Spring, SpringCollider, SpringColliderGroup, SpringColliderShape, SpringJoint, SpringSphere};
+
+    const DT: f32 = 1.0 / 60.0;
+
+    /// Three nodes one unit apart along +X, the first at the origin, plus a free node for colliders.
+    fn chain() -> std::mem::ManuallyDrop<vrm::Scene> {
+        let node = |child_indices: Vec<usize>, x: f32| vrm::Node {
+            child_indices,
+            mesh_index: None,
+            skin_index: None,
+            transform: glam::Mat4::from_translation(glam::Vec3::new(x, 0.0, 0.0)),
+        };
+        vrm::Scene::skeleton(vec![node(vec![1], 0.0), node(vec![2], 1.0), node(vec![], 1.0), node(vec![], 0.0)])
+    }
+
+    fn joint(node: u32, gravity_power: f32) -> SpringJoint {
+        SpringJoint {
+            node,
+            hit_radius: 0.1,
+            stiffness: 0.0,
+            gravity_power,
+            gravity_dir: [0.0, -1.0, 0.0],
+            drag_force: 0.5,
+        }
+    }
+
+    fn spring(joints: Vec<SpringJoint>) -> Spring {
+        Spring { joints, ..Default::default() }
+    }
+
+    /// Run the springs for `steps` frames and return the final global positions of the nodes.
+    fn simulate(scene: &vrm::Scene, steps: usize) -> Vec<glam::Vec3> {
+        let mut springs = Springs::new(scene);
+        let mut nodes = scene.nodes.clone();
+        for _ in 0..steps {
+            springs.update(scene, &mut nodes, DT);
+        }
+        scene.compute_global_transforms(&nodes, &glam::Mat4::IDENTITY).iter().map(|m| m.w_axis.truncate()).collect()
+    }
+
+    #[test]
+    fn chain_without_forces_stays_at_rest() {
+        let mut scene = chain();
+        let mut joints = vec![joint(0, 0.0), joint(1, 0.0), joint(2, 0.0)];
+        joints[0].stiffness = 1.0;
+        scene.spring_bone.springs = vec![spring(joints)];
+        let rest = simulate(&scene, 0);
+        for (p, r) in simulate(&scene, 120).iter().zip(&rest) {
+            assert!(p.abs_diff_eq(*r, 1e-5), "{} moved from {}", p, r);
+        }
+    }
+
+    #[test]
+    fn gravity_pulls_chain_down() {
+        let mut scene = chain();
+        scene.spring_bone.springs = vec![spring(vec![joint(0, 1.0), joint(1, 1.0), joint(2, 1.0)])];
+        let p = simulate(&scene, 600);
+        assert_eq!(p[0], glam::Vec3::ZERO);
+        // segments keep their length while the chain swings down to hang from its root
+        assert!(((p[1] - p[0]).length() - 1.0).abs() < 1e-4);
+        assert!(((p[2] - p[1]).length() - 1.0).abs() < 1e-4);
+        assert!(p[1].y < -0.9 && p[2].y < -1.8, "{:?}", p);
+    }
+
+    #[test]
+    fn sphere_collider_pushes_joint_out() {
+        let mut scene = chain();
+        let center = glam::Vec3::new(1.0, 0.2, 0.0);
+        scene.spring_bone.colliders = vec![SpringCollider {
+            node: 3,
+            shape: SpringColliderShape { sphere: Some(SpringSphere { offset: center.into(), radius: 0.5 }), capsule: None },
+        }];
+        scene.spring_bone.collider_groups = vec![SpringColliderGroup { name: "ball".to_owned(), colliders: vec![0] }];
+        scene.spring_bone.springs = vec![Spring { collider_groups: vec![0], ..spring(vec![joint(0, 0.0), joint(1, 0.0)]) }];
+        // the first tail starts inside the ball, and is pushed down and out of it
+        assert!(simulate(&scene, 0)[1].distance(center) < 0.6);
+        let p = simulate(&scene, 60);
+        assert!(p[1].y < 0.0, "{:?}", p);
+        assert!(p[1].distance(center) > 0.55, "{:?}", p);
+        assert!((p[1].length() - 1.0).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,282 @@
+use std::f32::consts::PI;
+
+use crate::fig;
+
+/// How far in front of the camera the orbit pivot sits for shots built from a position and a direction.
+const PIVOT_DISTANCE: f32 = 0.4;
+
+/// Rate at which orbit/dolly/roll offsets chase their targets (per second).
+const OFFSET_RATE: f32 = 6.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Shot {
+    pub target: glam::Vec3,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub distance: f32,
+    pub fov: f32,
+}
+
+impl Shot {
+    pub fn from_string(nm: &str) -> Option<Self> {
+        match nm {
+            "bust" => Some(Self::looking(
+                glam::Vec3::new(0.0, 1.5, -0.4),
+                glam::Vec3::new(0.0, 0.0, 1.0),
+                PI / 4.0,
+            )),
+            "low" => Some(Self::looking(
+                glam::Vec3::new(0.0, 1.35, -0.4),
+                glam::Vec3::new(0.0, 0.2, 1.0),
+                PI / 4.0,
+            )),
+            "full-body" => Some(Self {
+                target: glam::Vec3::new(0.0, 0.85, 0.0),
+                yaw: 0.0,
+                pitch: 0.0,
+                distance: 2.3,
+                fov: PI / 4.0,
+            }),
+            "close-up" => Some(Self {
+                target: glam::Vec3::new(0.0, 1.55, 0.0),
+                yaw: 0.0,
+                pitch: 0.0,
+                distance: 0.3,
+                fov: PI / 5.0,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Build a shot from a camera position and look direction, pivoting around a point just ahead of it.
+    pub fn looking(position: glam::Vec3, forward: glam::Vec3, fov: f32) -> Self {
+        let dir = forward.normalize();
+        Self {
+            target: position + dir * PIVOT_DISTANCE,
+            yaw: dir.x.atan2(dir.z),
+            pitch: dir.y.asin(),
+            distance: PIVOT_DISTANCE,
+            fov,
+        }
+    }
+
+    pub fn direction(&self) -> glam::Vec3 {
+        glam::Vec3::new(
+            self.pitch.cos() * self.yaw.sin(),
+            self.pitch.sin(),
+            self.pitch.cos() * self.yaw.cos(),
+        )
+    }
+
+    pub fn position(&self) -> glam::Vec3 {
+        self.target - self.direction() * self.distance
+    }
+
+    pub fn lerp(&self, other: &Self, t: f32) -> Self {
+        let l = |a: f32, b: f32| a + (b - a) * t;
+        Self {
+            target: self.target.lerp(other.target, t),
+            yaw: l(self.yaw, other.yaw),
+            pitch: l(self.pitch, other.pitch),
+            distance: l(self.distance, other.distance),
+            fov: l(self.fov, other.fov),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+struct Offsets {
+    yaw: f32,
+    pitch: f32,
+    dolly: f32,
+    roll: f32,
+}
+
+pub struct Rig {
+    from: Shot,
+    to: Shot,
+    progress: f32,
+    duration: f32,
+    offsets: Offsets,
+    offsets_target: Offsets,
+}
+
+impl Rig {
+    pub fn new() -> Self {
+        let shot = Shot::from_string("bust").unwrap();
+        Self {
+            from: shot,
+            to: shot,
+            progress: 1.0,
+            duration: 0.0,
+            offsets: Offsets::default(),
+            offsets_target: Offsets::default(),
+        }
+    }
+
+    /// Ease from wherever the camera currently is to `shot` over `duration` seconds.
+    pub fn transition(&mut self, shot: Shot, duration: f32) {
+        self.from = self.current();
+        self.to = shot;
+        self.progress = 0.0;
+        self.duration = duration.max(0.0);
+    }
+
+    pub fn orbit(&mut self, yaw: f32, pitch: f32) {
+        self.offsets_target.yaw = yaw;
+        self.offsets_target.pitch = pitch.clamp(-PI / 2.0 + 0.01, PI / 2.0 - 0.01);
+    }
+
+    pub fn dolly(&mut self, dolly: f32) {
+        self.offsets_target.dolly = dolly;
+    }
+
+    pub fn roll(&mut self, roll: f32) {
+        self.offsets_target.roll = roll;
+    }
+
+    pub fn reset(&mut self) {
+        self.transition(Shot::from_string("bust").unwrap(), 0.0);
+        self.offsets_target = Offsets::default();
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        if self.duration > 0.0 {
+            self.progress = (self.progress + dt / self.duration).min(1.0);
+        } else {
+            self.progress = 1.0;
+        }
+        let k = 1.0 - (-OFFSET_RATE * dt).exp();
+        let approach = |a: f32, b: f32| a + (b - a) * k;
+        self.offsets = Offsets {
+            yaw: approach(self.offsets.yaw, self.offsets_target.yaw),
+            pitch: approach(self.offsets.pitch, self.offsets_target.pitch),
+            dolly: approach(self.offsets.dolly, self.offsets_target.dolly),
+            roll: approach(self.offsets.roll, self.offsets_target.roll),
+        };
+    }
+
+    /// The preset shot as it stands mid-transition, before orbit/dolly/roll are applied.
+    fn current(&self) -> Shot {
+        let t = self.progress * self.progress * (3.0 - 2.0 * self.progress);
+        self.from.lerp(&self.to, t)
+    }
+
+    /// The shot actually rendered this frame, including offsets and the `zoom_wave` toggle.
+    pub fn shot(&self, control: &fig::Control, time: f32) -> Shot {
+        let mut shot = self.current();
+        shot.yaw += self.offsets.yaw;
+        shot.pitch = (shot.pitch + self.offsets.pitch).clamp(-PI / 2.0 + 0.01, PI / 2.0 - 0.01);
+        shot.distance = (shot.distance + self.offsets.dolly).max(0.05);
+        if control.is("zoom_wave") {
+            shot.distance -= time.sin() * 0.1;
+        }
+        shot
+    }
+
+    /// Camera position and view matrix for this frame, with the `spin` toggle applied as extra roll.
+    pub fn view(&self, control: &fig::Control, time: f32) -> (glam::Vec3, glam::Mat4) {
+        let shot = self.shot(control, time);
+        let position = shot.position();
+        let spin = if control.is("spin") {
+            if control.is("spin_direction") {
+                -(time / 4.0)
+            } else {
+                time / 4.0
+            }
+        } else {
+            0.0
+        };
+        let angle = PI / 2.0 + self.offsets.roll + spin;
+        let up = glam::Quat::from_rotation_y(shot.yaw)
+            .mul_quat(glam::Quat::from_rotation_x(-shot.pitch))
+            .mul_vec3(glam::Vec3::new(angle.cos(), angle.sin(), 0.0));
+        (position, glam::Mat4::look_at_lh(position, shot.target, up))
+    }
+
+    pub fn projection(&self, aspect: f32) -> glam::Mat4 {
+        glam::Mat4::perspective_lh(self.current().fov, aspect, 0.1, 10.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn full_body() -> Shot {
+        Shot::from_string("full-body").unwrap()
+    }
+
+    fn close(a: &Shot, b: &Shot) -> bool {
+        a.target.abs_diff_eq(b.target, 1e-5)
+            && (a.yaw - b.yaw).abs() < 1e-5
+            && (a.pitch - b.pitch).abs() < 1e-5
+            && (a.distance - b.distance).abs() < 1e-5
+            && (a.fov - b.fov).abs() < 1e-5
+    }
+
+    #[test]
+    fn lerp_interpolates_every_field() {
+        let a = full_body();
+        let b = Shot::from_string("close-up").unwrap();
+        assert_eq!(a.lerp(&b, 0.0), a);
+        assert!(close(&a.lerp(&b, 1.0), &b));
+        let mid = a.lerp(&b, 0.5);
+        assert_eq!(mid.target, (a.target + b.target) / 2.0);
+        assert_eq!(mid.distance, (a.distance + b.distance) / 2.0);
+        assert_eq!(mid.fov, (a.fov + b.fov) / 2.0);
+    }
+
+    #[test]
+    fn transition_eases_and_finishes_at_duration() {
+        let control = fig::Control::new();
+        let mut rig = Rig::new();
+        let from = rig.shot(&control, 0.0);
+        rig.transition(full_body(), 1.0);
+        rig.update(0.25);
+        let quarter = rig.shot(&control, 0.0);
+        // smoothstep is slower than linear in the first half
+        let eased = (quarter.distance - from.distance) / (full_body().distance - from.distance);
+        assert!(eased > 0.0 && eased < 0.25);
+        rig.update(0.75);
+        assert!(close(&rig.shot(&control, 0.0), &full_body()));
+        rig.update(1.0);
+        assert!(close(&rig.shot(&control, 0.0), &full_body()));
+    }
+
+    #[test]
+    fn zero_duration_transition_cuts() {
+        let control = fig::Control::new();
+        let mut rig = Rig::new();
+        rig.transition(full_body(), 0.0);
+        rig.update(0.0);
+        let shot = rig.shot(&control, 0.0);
+        assert!(close(&shot, &full_body()));
+        assert!(!rig.projection(1.0).is_nan());
+    }
+
+    #[test]
+    fn orbit_and_dolly_are_clamped() {
+        let control = fig::Control::new();
+        let mut rig = Rig::new();
+        // tilted, so the preset pitch plus the clamped orbit still overshoots
+        rig.transition(Shot { pitch: 0.5, ..full_body() }, 0.0);
+        rig.orbit(1.0, 10.0);
+        assert_eq!(rig.offsets_target.pitch, PI / 2.0 - 0.01);
+        rig.dolly(-100.0);
+        for _ in 0..100 {
+            rig.update(0.1);
+        }
+        let shot = rig.shot(&control, 0.0);
+        assert!((shot.yaw - 1.0).abs() < 1e-3);
+        assert_eq!(shot.pitch, PI / 2.0 - 0.01);
+        assert_eq!(shot.distance, 0.05);
+        rig.transition(Shot { pitch: -0.5, ..full_body() }, 0.0);
+        rig.orbit(0.0, -10.0);
+        assert_eq!(rig.offsets_target.pitch, -PI / 2.0 + 0.01);
+        for _ in 0..100 {
+            rig.update(0.1);
+        }
+        assert_eq!(rig.shot(&control, 0.0).pitch, -PI / 2.0 + 0.01);
+    }
+}
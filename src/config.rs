@@ -0,0 +1,679 @@
+// Renderer configuration, read from an s-expression file at startup.
+// The file is a sequence of top-level forms; each struct below documents the form it is parsed from.
+
+use std::{collections::HashMap, env};
+
+use colors_transform::{Rgb, Color};
+
+use crate::context;
+
+type Color3 = (u8, u8, u8);
+
+/// The `(pose ...)` inside a scene preset: `(yaw -0.30)` turns the whole avatar, and each
+/// `(bone "leftUpperArm" 0.0 0.0 -0.785)` rotates a bone by XYZ euler angles in radians.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pose {
+    pub yaw: f32,
+    pub bones: Vec<(String, glam::Quat)>,
+}
+
+impl Pose {
+    /// Arms down at the sides, facing the camera.
+    pub fn rest() -> Self {
+        Self {
+            yaw: 0.0,
+            bones: vec![
+                ("leftUpperArm".to_owned(), glam::Quat::from_rotation_z(-std::f32::consts::PI / 4.0)),
+                ("rightUpperArm".to_owned(), glam::Quat::from_rotation_z(std::f32::consts::PI / 4.0)),
+            ],
+        }
+    }
+}
+
+/// A `(scene <name> ...)` form. Presets named in the file replace the built-in ones:
+///
+/// ```text
+/// (scene forsen
+///   (background "#2c5239")
+///   (camera "low")
+///   (transition 0.0)
+///   (avatar "new")
+///   (pose (yaw -0.30)
+///         (bone "leftUpperArm" 0.0 0.0 -0.785)
+///         (bone "rightUpperArm" 0.0 0.0 0.785))
+///   (palette (hair "#ffffff")))
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScenePreset {
+    pub background: Option<Color3>,
+    pub camera: String,
+    pub transition: f32,
+    pub avatar: String,
+    pub pose: Pose,
+    pub palette: HashMap<context::PaletteType, Color3>,
+}
+
+impl ScenePreset {
+    pub fn new() -> Self {
+        Self {
+            background: None,
+            camera: "bust".to_owned(),
+            transition: 0.0,
+            avatar: "new".to_owned(),
+            pose: Pose::rest(),
+            palette: HashMap::new(),
+        }
+    }
+}
+
+/// `(bone "head" 0.6 0.0)` in the tracking form: the bone's share of the head rotation, then its share of the lean.
+/// Listing any bone replaces the default distribution.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BoneWeight {
+    pub bone: String,
+    pub rotation: f32,
+    pub lean: f32,
+}
+
+/// `(curve linear)`, `(curve smooth)` or `(curve 2.0)`, an exponent, shaping a mapping's 0 to 1 ramp.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Curve {
+    Linear,
+    Smooth,
+    Power(f32),
+}
+
+impl Curve {
+    pub fn apply(&self, t: f32) -> f32 {
+        match self {
+            Curve::Linear => t,
+            Curve::Smooth => t * t * (3.0 - 2.0 * t),
+            Curve::Power(e) => t.powf(*e),
+        }
+    }
+}
+
+/// A tracker feature driving an expression, from `(map "happy" "mouth-corner-updown-left" (range 0.0 0.6) (curve smooth) (gain 0.5))`.
+///
+/// The range is the raw feature value mapped to 0 and 1 (it may be reversed), and gain scales the result.
+/// Mappings onto the same expression take the largest weight. Listing any mapping replaces the defaults,
+/// which drive blink and oh.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeatureMapping {
+    pub expression: String,
+    pub feature: String,
+    pub range: (f32, f32),
+    pub curve: Curve,
+    pub gain: f32,
+}
+
+impl FeatureMapping {
+    pub fn new(expression: &str, feature: &str, range: (f32, f32)) -> Self {
+        Self {
+            expression: expression.to_owned(),
+            feature: feature.to_owned(),
+            range,
+            curve: Curve::Linear,
+            gain: 1.0,
+        }
+    }
+
+    /// Expression weight for a raw feature value.
+    pub fn evaluate(&self, v: f32) -> f32 {
+        let (lo, hi) = self.range;
+        if lo == hi { return 0.0; }
+        let t = ((v - lo) / (hi - lo)).clamp(0.0, 1.0);
+        (self.curve.apply(t) * self.gain).clamp(0.0, 1.0)
+    }
+}
+
+/// `(filter (min-cutoff 1.5) (beta 0.3) (timeout 1.0) (idle-rate 3.0))`: one-euro filter parameters in Hz,
+/// seconds without packets before easing to neutral, and how fast to ease.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    pub min_cutoff: f32,
+    pub beta: f32,
+    pub d_cutoff: f32,
+    pub timeout: f32,
+    pub idle_rate: f32,
+}
+
+impl Filter {
+    pub fn new() -> Self {
+        Self {
+            min_cutoff: 1.5,
+            beta: 0.3,
+            d_cutoff: 1.0,
+            timeout: 1.0,
+            idle_rate: 3.0,
+        }
+    }
+}
+
+/// Where tracking comes from: `(source openseeface "127.0.0.1:11573")`, `(source vmc "0.0.0.0:39539")`,
+/// or `(source playback "session.track" 2.0)` to replay a recording in a loop at the given speed.
+/// Listing any source replaces the default, which is OpenSeeFace on its usual port.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    OpenSeeFace(String),
+    Vmc(String),
+    Playback {
+        path: String,
+        speed: f32,
+    },
+}
+
+/// `(lipsync (input "pulse") (mode blend) (gain 8.0) (noise-floor 0.01))` drives the visemes from audio.
+/// Input is pulse, alsa or a 16-bit WAV file; mode is blend (the larger of audio and camera wins)
+/// or override (audio replaces the camera's mouth).
+#[derive(Debug, Clone, PartialEq)]
+pub struct LipSync {
+    pub input: String,
+    pub override_camera: bool,
+    pub gain: f32,
+    pub noise_floor: f32,
+}
+
+impl LipSync {
+    pub fn new() -> Self {
+        Self {
+            input: "pulse".to_owned(),
+            override_camera: false,
+            gain: 8.0,
+            noise_floor: 0.01,
+        }
+    }
+}
+
+/// The raw tracked pose that counts as looking straight ahead, stored as
+/// `(calibration "desk" (orientation 0.0 0.0 0.0 1.0) (translation 0.0 0.0 0.0))`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Calibration {
+    pub orientation: glam::Quat,
+    pub translation: glam::Vec3,
+}
+
+/// The `(tracking ...)` form: how tracked head motion spreads over the body, and every other tracking option.
+///
+/// ```text
+/// (tracking
+///   (translation 0.1 0.1 0.1)
+///   (hips 0.5)
+///   (lean 4.0)
+///   (bone "head" 0.6 0.0)
+///   (bone "spine" 0.1 0.5))
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tracking {
+    /// Meters of avatar motion per tracker unit, per axis.
+    pub translation: glam::Vec3,
+    /// Share of that motion applied to the hips.
+    pub hips: f32,
+    /// Radians of lean per meter of motion.
+    pub lean: f32,
+    pub bones: Vec<BoneWeight>,
+    pub mappings: Vec<FeatureMapping>,
+    pub filter: Filter,
+    /// `(profile "desk")` names the camera setup whose neutral-pose calibration is used.
+    pub profile: String,
+    pub sources: Vec<Source>,
+    /// `(send "127.0.0.1:39540")` publishes the primary avatar's pose over VMC to that address every step.
+    pub send: Option<String>,
+    /// `(record "session.track")` records every live packet from startup.
+    pub record: Option<String>,
+    pub lipsync: Option<LipSync>,
+}
+
+impl Tracking {
+    pub fn new() -> Self {
+        let bone = |bone: &str, rotation, lean| BoneWeight { bone: bone.to_owned(), rotation, lean };
+        Self {
+            translation: glam::Vec3::splat(0.1),
+            hips: 0.5,
+            lean: 4.0,
+            bones: vec![
+                bone("head", 0.6, 0.0),
+                bone("neck", 0.2, 0.0),
+                bone("chest", 0.1, 0.5),
+                bone("spine", 0.1, 0.5),
+            ],
+            mappings: vec![
+                FeatureMapping::new("blink", "eye-open", (1.0, 0.0)),
+                FeatureMapping::new("oh", "mouth-open", (0.0, 1.0)),
+            ],
+            filter: Filter::new(),
+            profile: "default".to_owned(),
+            sources: vec![Source::OpenSeeFace("127.0.0.1:11573".to_owned())],
+            send: None,
+            record: None,
+            lipsync: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub path: String,
+    pub scenes: HashMap<String, ScenePreset>,
+    pub tracking: Tracking,
+    /// Calibration forms written into the config by older versions; `Calibrations` overrides them.
+    pub calibrations: HashMap<String, Calibration>,
+}
+
+/// Captured calibrations per camera profile, kept apart from the hand-edited config in their own file
+/// ($COLONQ_CALIBRATIONS, ../assets/calibrations.sexp by default). The file is rewritten with one
+/// `(calibration ...)` form per profile whenever one is captured.
+#[derive(Debug, Clone)]
+pub struct Calibrations {
+    pub path: String,
+    pub profiles: HashMap<String, Calibration>,
+}
+
+fn name(v: &lexpr::Value) -> Option<&str> {
+    v.as_symbol().or_else(|| v.as_str())
+}
+
+fn color(v: &lexpr::Value) -> Option<Color3> {
+    let rgb = Rgb::from_hex_str(v.as_str()?).ok()?;
+    Some((rgb.get_red() as _, rgb.get_green() as _, rgb.get_blue() as _))
+}
+
+fn number(v: &lexpr::Value) -> Option<f32> {
+    v.as_f64().map(|f| f as f32)
+}
+
+fn parse_calibration(form: &lexpr::Value) -> Option<(String, Calibration)> {
+    let mut cal = Calibration { orientation: glam::Quat::IDENTITY, translation: glam::Vec3::ZERO };
+    for field in form.list_iter().into_iter().flatten().skip(2) {
+        let v: Vec<f32> = field.list_iter().into_iter().flatten().skip(1).filter_map(number).collect();
+        match (name(&field[0]), v.len()) {
+            (Some("orientation"), 4) => cal.orientation = glam::Quat::from_slice(&v).normalize(),
+            (Some("translation"), 3) => cal.translation = glam::Vec3::from_slice(&v),
+            _ => log::warn!("Ignoring malformed calibration field"),
+        }
+    }
+    Some((name(&form[1])?.to_owned(), cal))
+}
+
+impl Config {
+    pub fn builtin() -> Self {
+        let mut forsen = ScenePreset::new();
+        forsen.background = Some((0x2c, 0x52, 0x39));
+        forsen.camera = "low".to_owned();
+        forsen.pose.yaw = -0.30;
+        let mut old = ScenePreset::new();
+        old.avatar = "old".to_owned();
+        Self {
+            path: env::var("COLONQ_CONFIG").unwrap_or_else(|_| "../assets/config.sexp".to_owned()),
+            scenes: HashMap::from([
+                ("default".to_owned(), ScenePreset::new()),
+                ("forsen".to_owned(), forsen),
+                ("old".to_owned(), old),
+            ]),
+            tracking: Tracking::new(),
+            calibrations: HashMap::new(),
+        }
+    }
+
+    pub fn load() -> Self {
+        let mut config = Self::builtin();
+        let path = config.path.clone();
+        match std::fs::read_to_string(&path) {
+            Ok(src) => match config.parse(&src) {
+                Ok(()) => log::info!("Loaded config from {}", path),
+                Err(e) => log::error!("Failed to parse config {}: {}", path, e),
+            },
+            Err(_) => log::info!("No config at {}, using built-in presets", path),
+        }
+        config
+    }
+
+    /// Apply every top-level form in `src` over the current settings.
+    fn parse(&mut self, src: &str) -> Result<(), lexpr::parse::Error> {
+        let forms = lexpr::from_str(&format!("({})", src))?;
+        for form in forms.list_iter().into_iter().flatten() {
+            self.parse_form(form);
+        }
+        Ok(())
+    }
+
+    fn parse_form(&mut self, form: &lexpr::Value) {
+        match name(&form[0]) {
+            Some("scene") => {
+                if let Some(nm) = name(&form[1]) {
+                    self.scenes.insert(nm.to_owned(), Self::parse_scene(form));
+                } else {
+                    log::warn!("Ignoring unnamed scene preset in config");
+                }
+            },
+            Some("tracking") => self.tracking = Self::parse_tracking(form),
+            Some("calibration") => {
+                if let Some((nm, cal)) = parse_calibration(form) {
+                    self.calibrations.insert(nm, cal);
+                }
+            },
+            Some(other) => log::warn!("Ignoring unknown config form: {}", other),
+            None => {},
+        }
+    }
+
+    fn parse_scene(form: &lexpr::Value) -> ScenePreset {
+        let mut preset = ScenePreset::new();
+        for field in form.list_iter().into_iter().flatten().skip(2) {
+            match name(&field[0]) {
+                Some("background") => preset.background = color(&field[1]),
+                Some("camera") => if let Some(c) = name(&field[1]) { preset.camera = c.to_owned() },
+                Some("transition") => if let Some(t) = number(&field[1]) { preset.transition = t },
+                Some("avatar") => if let Some(a) = name(&field[1]) { preset.avatar = a.to_owned() },
+                Some("pose") => preset.pose = Self::parse_pose(field),
+                Some("palette") => {
+                    for entry in field.list_iter().into_iter().flatten().skip(1) {
+                        let pty = name(&entry[0]).and_then(context::PaletteType::from_string);
+                        if let (Some(pty), Some(col)) = (pty, color(&entry[1])) {
+                            preset.palette.insert(pty, col);
+                        }
+                    }
+                },
+                Some(other) => log::warn!("Ignoring unknown scene field: {}", other),
+                None => {},
+            }
+        }
+        preset
+    }
+
+    fn parse_pose(form: &lexpr::Value) -> Pose {
+        let mut pose = Pose { yaw: 0.0, bones: Vec::new() };
+        for field in form.list_iter().into_iter().flatten().skip(1) {
+            match name(&field[0]) {
+                Some("yaw") => pose.yaw = number(&field[1]).unwrap_or(0.0),
+                Some("bone") => {
+                    if let (Some(bone), Some(x), Some(y), Some(z)) =
+                        (name(&field[1]), number(&field[2]), number(&field[3]), number(&field[4]))
+                    {
+                        pose.bones.push((bone.to_owned(), glam::Quat::from_euler(glam::EulerRot::XYZ, x, y, z)));
+                    }
+                },
+                _ => {},
+            }
+        }
+        pose
+    }
+
+    fn parse_tracking(form: &lexpr::Value) -> Tracking {
+        let mut tracking = Tracking::new();
+        let mut bones = Vec::new();
+        let mut mappings = Vec::new();
+        let mut sources = Vec::new();
+        for field in form.list_iter().into_iter().flatten().skip(1) {
+            match name(&field[0]) {
+                Some("translation") => {
+                    if let (Some(x), Some(y), Some(z)) = (number(&field[1]), number(&field[2]), number(&field[3])) {
+                        tracking.translation = glam::Vec3::new(x, y, z);
+                    }
+                },
+                Some("hips") => if let Some(h) = number(&field[1]) { tracking.hips = h },
+                Some("lean") => if let Some(l) = number(&field[1]) { tracking.lean = l },
+                Some("bone") => {
+                    if let (Some(bone), Some(rotation), Some(lean)) = (name(&field[1]), number(&field[2]), number(&field[3])) {
+                        bones.push(BoneWeight { bone: bone.to_owned(), rotation, lean });
+                    }
+                },
+                Some("map") => match Self::parse_mapping(field) {
+                    Some(m) => mappings.push(m),
+                    None => log::warn!("Ignoring malformed feature mapping in config"),
+                },
+                Some("filter") => {
+                    for f in field.list_iter().into_iter().flatten().skip(1) {
+                        let v = number(&f[1]);
+                        match (name(&f[0]), v) {
+                            (Some("min-cutoff"), Some(v)) => tracking.filter.min_cutoff = v,
+                            (Some("beta"), Some(v)) => tracking.filter.beta = v,
+                            (Some("d-cutoff"), Some(v)) => tracking.filter.d_cutoff = v,
+                            (Some("timeout"), Some(v)) => tracking.filter.timeout = v,
+                            (Some("idle-rate"), Some(v)) => tracking.filter.idle_rate = v,
+                            _ => log::warn!("Ignoring malformed filter setting in config"),
+                        }
+                    }
+                },
+                Some("source") => match (name(&field[1]), name(&field[2])) {
+                    (Some("openseeface"), Some(addr)) => sources.push(Source::OpenSeeFace(addr.to_owned())),
+                    (Some("vmc"), Some(addr)) => sources.push(Source::Vmc(addr.to_owned())),
+                    (Some("playback"), Some(path)) => sources.push(Source::Playback {
+                        path: path.to_owned(),
+                        speed: number(&field[3]).unwrap_or(1.0),
+                    }),
+                    _ => log::warn!("Ignoring malformed tracking source in config"),
+                },
+                Some("lipsync") => {
+                    let mut ls = LipSync::new();
+                    for f in field.list_iter().into_iter().flatten().skip(1) {
+                        match (name(&f[0]), &f[1]) {
+                            (Some("input"), v) => if let Some(i) = name(v) { ls.input = i.to_owned() },
+                            (Some("mode"), v) => ls.override_camera = name(v) == Some("override"),
+                            (Some("gain"), v) => if let Some(g) = number(v) { ls.gain = g },
+                            (Some("noise-floor"), v) => if let Some(n) = number(v) { ls.noise_floor = n },
+                            _ => log::warn!("Ignoring malformed lipsync setting in config"),
+                        }
+                    }
+                    tracking.lipsync = Some(ls);
+                },
+                Some("record") => tracking.record = name(&field[1]).map(|p| p.to_owned()),
+                Some("send") => tracking.send = name(&field[1]).map(|a| a.to_owned()),
+                Some("profile") => if let Some(p) = name(&field[1]) { tracking.profile = p.to_owned() },
+                Some(other) => log::warn!("Ignoring unknown tracking field: {}", other),
+                None => {},
+            }
+        }
+        if !bones.is_empty() {
+            tracking.bones = bones;
+        }
+        if !mappings.is_empty() {
+            tracking.mappings = mappings;
+        }
+        if !sources.is_empty() {
+            tracking.sources = sources;
+        }
+        tracking
+    }
+
+    fn parse_mapping(form: &lexpr::Value) -> Option<FeatureMapping> {
+        let mut mapping = FeatureMapping::new(name(&form[1])?, name(&form[2])?, (0.0, 1.0));
+        for field in form.list_iter().into_iter().flatten().skip(3) {
+            match name(&field[0]) {
+                Some("range") => mapping.range = (number(&field[1])?, number(&field[2])?),
+                Some("curve") => {
+                    mapping.curve = match name(&field[1]) {
+                        Some("linear") => Curve::Linear,
+                        Some("smooth") => Curve::Smooth,
+                        _ => Curve::Power(number(&field[1])?),
+                    }
+                },
+                Some("gain") => mapping.gain = number(&field[1])?,
+                _ => return None,
+            }
+        }
+        Some(mapping)
+    }
+
+    pub fn scene(&self, nm: &str) -> &ScenePreset {
+        self.scenes.get(nm).or_else(|| self.scenes.get("default")).unwrap()
+    }
+}
+
+impl Calibrations {
+    /// Read the calibration file over the calibrations found in the config.
+    pub fn load(config: &Config) -> Self {
+        let mut cals = Self {
+            path: env::var("COLONQ_CALIBRATIONS").unwrap_or_else(|_| "../assets/calibrations.sexp".to_owned()),
+            profiles: config.calibrations.clone(),
+        };
+        match std::fs::read_to_string(&cals.path) {
+            Ok(src) => match lexpr::from_str(&format!("({})", src)) {
+                Ok(forms) => {
+                    for form in forms.list_iter().into_iter().flatten() {
+                        match name(&form[0]) {
+                            Some("calibration") => cals.profiles.extend(parse_calibration(form)),
+                            _ => log::warn!("Ignoring unknown form in calibration file"),
+                        }
+                    }
+                },
+                Err(e) => log::error!("Failed to parse calibrations {}: {}", cals.path, e),
+            },
+            Err(_) => log::info!("No calibrations at {}", cals.path),
+        }
+        cals
+    }
+
+    /// Calibration for a camera profile, if one has been captured.
+    pub fn get(&self, profile: &str) -> Option<Calibration> {
+        self.profiles.get(profile).copied()
+    }
+
+    /// Remember a calibration for a camera profile and rewrite the calibration file.
+    pub fn save(&mut self, profile: &str, cal: Calibration) {
+        self.profiles.insert(profile.to_owned(), cal);
+        let mut profiles: Vec<_> = self.profiles.iter().collect();
+        profiles.sort_by(|a, b| a.0.cmp(b.0));
+        let mut out = String::from(";; Written by colonq whenever a calibration is captured.\n");
+        for (nm, c) in profiles {
+            let (q, t) = (c.orientation, c.translation);
+            out += &format!(
+                "(calibration \"{}\" (orientation {} {} {} {}) (translation {} {} {}))\n",
+                nm, q.x, q.y, q.z, q.w, t.x, t.y, t.z,
+            );
+        }
+        // write beside the file and rename over it, so a crash never leaves half a file
+        let tmp = format!("{}.tmp", self.path);
+        let res = std::fs::write(&tmp, out).and_then(|()| std::fs::rename(&tmp, &self.path));
+        match res {
+            Ok(()) => log::info!("Saved calibration for profile {} to {}", profile, self.path),
+            Err(e) => log::error!("Failed to save calibration to {}: {}", self.path, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(src: &str) -> Config {
+        let mut config = Config::builtin();
+        config.parse(src).unwrap();
+        config
+    }
+
+    fn tracking(src: &str) -> Tracking {
+        parse(&format!("(tracking {})", src)).tracking
+    }
+
+    #[test]
+    fn parses_scene_presets() {
+        let config = parse(r##"
+            (scene forsen
+              (background "#102030")
+              (camera "full-body")
+              (transition 1.5)
+              (avatar "old")
+              (pose (yaw -0.5) (bone "leftUpperArm" 0.0 0.0 -1.0))
+              (palette (hair "#ffffff") (nonsense "#000000")))
+            (scene "extra" (camera close-up))
+        "##);
+        let forsen = config.scene("forsen");
+        assert_eq!(forsen.background, Some((0x10, 0x20, 0x30)));
+        assert_eq!(forsen.camera, "full-body");
+        assert_eq!(forsen.transition, 1.5);
+        assert_eq!(forsen.avatar, "old");
+        assert_eq!(forsen.pose.yaw, -0.5);
+        assert_eq!(forsen.pose.bones, vec![("leftUpperArm".to_owned(), glam::Quat::from_rotation_z(-1.0))]);
+        assert_eq!(forsen.palette, HashMap::from([(context::PaletteType::Hair, (0xff, 0xff, 0xff))]));
+        // unset fields keep their defaults, and presets not in the file stay built in
+        let extra = config.scene("extra");
+        assert_eq!(extra.camera, "close-up");
+        assert_eq!(extra.pose, Pose::rest());
+        assert_eq!(config.scene("old").avatar, "old");
+        assert_eq!(config.scene("missing"), config.scene("default"));
+    }
+
+    #[test]
+    fn parses_body_distribution() {
+        let t = tracking(r#"(translation 0.2 0.3 0.4) (hips 0.25) (lean 2.0) (bone "head" 1.0 0.0)"#);
+        assert_eq!(t.translation, glam::Vec3::new(0.2, 0.3, 0.4));
+        assert_eq!(t.hips, 0.25);
+        assert_eq!(t.lean, 2.0);
+        assert_eq!(t.bones, vec![BoneWeight { bone: "head".to_owned(), rotation: 1.0, lean: 0.0 }]);
+        assert_eq!(tracking("(hips 0.25)").bones, Tracking::new().bones);
+    }
+
+    #[test]
+    fn parses_mappings() {
+        let t = tracking(r#"
+            (map "happy" "mouth-corner-updown-left" (range 0.0 0.6) (curve smooth) (gain 0.5))
+            (map "angry" "eyebrow-steepness-left" (curve 2.0))
+        "#);
+        let mut happy = FeatureMapping::new("happy", "mouth-corner-updown-left", (0.0, 0.6));
+        happy.curve = Curve::Smooth;
+        happy.gain = 0.5;
+        let mut angry = FeatureMapping::new("angry", "eyebrow-steepness-left", (0.0, 1.0));
+        angry.curve = Curve::Power(2.0);
+        assert_eq!(t.mappings, vec![happy, angry]);
+        assert_eq!(tracking("(lean 1.0)").mappings, Tracking::new().mappings);
+    }
+
+    #[test]
+    fn parses_filter_profile_and_io() {
+        let t = tracking(r#"
+            (filter (min-cutoff 2.0) (beta 0.1) (d-cutoff 0.5) (timeout 3.0) (idle-rate 1.0))
+            (profile "desk")
+            (source vmc "0.0.0.0:39539")
+            (source playback "session.track")
+            (source playback "fast.track" 2.0)
+            (send "127.0.0.1:39540")
+            (record "out.track")
+        "#);
+        assert_eq!(t.filter, Filter { min_cutoff: 2.0, beta: 0.1, d_cutoff: 0.5, timeout: 3.0, idle_rate: 1.0 });
+        assert_eq!(t.profile, "desk");
+        assert_eq!(t.sources, vec![
+            Source::Vmc("0.0.0.0:39539".to_owned()),
+            Source::Playback { path: "session.track".to_owned(), speed: 1.0 },
+            Source::Playback { path: "fast.track".to_owned(), speed: 2.0 },
+        ]);
+        assert_eq!(t.send.as_deref(), Some("127.0.0.1:39540"));
+        assert_eq!(t.record.as_deref(), Some("out.track"));
+        assert_eq!(tracking("(hips 0.5)").sources, Tracking::new().sources);
+    }
+
+    #[test]
+    fn parses_lipsync() {
+        assert_eq!(tracking("").lipsync, None);
+        let ls = tracking(r#"(lipsync (input "talk.wav") (mode override) (gain 4.0) (noise-floor 0.02))"#).lipsync;
+        assert_eq!(ls, Some(LipSync { input: "talk.wav".to_owned(), override_camera: true, gain: 4.0, noise_floor: 0.02 }));
+        let ls = tracking("(lipsync (mode blend))").lipsync.unwrap();
+        assert_eq!(ls, LipSync::new());
+    }
+
+    #[test]
+    fn parses_calibrations() {
+        let config = parse(r#"(calibration "desk" (orientation 0.0 1.0 0.0 0.0) (translation 1.0 2.0 3.0))"#);
+        let cal = config.calibrations["desk"];
+        assert_eq!(cal.orientation, glam::Quat::from_xyzw(0.0, 1.0, 0.0, 0.0));
+        assert_eq!(cal.translation, glam::Vec3::new(1.0, 2.0, 3.0));
+    }
+
+    #[test]
+    fn rejects_malformed_forms() {
+        let t = tracking(r#"
+            (bone "head" 1.0)
+            (map "happy")
+            (map "happy" "mouth-open" (range 0.0))
+            (map "happy" "mouth-open" (wobble 1.0))
+            (source vmc)
+            (source carrier-pigeon "coop")
+            (filter (beta fast))
+            (hips "lots")
+        "#);
+        assert_eq!(t, Tracking::new());
+        let config = parse("(scene (camera low)) (weather rain)");
+        assert_eq!(config.scenes, Config::builtin().scenes);
+        assert!(Config::builtin().parse("(scene forsen (camera low)").is_err());
+    }
+}
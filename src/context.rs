@@ -1,6 +1,6 @@
 use crate::{gl, utils, framebuffer};
 
-use std::{collections::HashMap, env, cell::RefCell};
+use std::{env, cell::RefCell};
 
 use colors_transform::{Rgb, Color};
 
@@ -45,16 +45,16 @@ impl PaletteType {
     }
 }
 
-pub struct VideoPlayer<'a> {
+pub struct VideoPlayer {
     pub fb: framebuffer::Framebuffer,
-    pub mpv: &'a libmpv::Mpv,
+    // the render context has to be freed before the mpv handle it was created from, so keep it first
     pub mpv_render_context: libmpv::render::RenderContext,
-    pub mpv_event_context: libmpv::events::EventContext<'a>,
+    pub mpv: libmpv::Mpv,
 }
 
-impl<'a> VideoPlayer<'a> {
+impl VideoPlayer {
     pub fn new(ctx: &Context) -> Self {
-        let mpv = Box::leak(Box::new(libmpv::Mpv::new().unwrap()));
+        let mut mpv = libmpv::Mpv::new().unwrap();
         mpv.set_property("mute", "yes").unwrap();
         mpv.set_property("keepaspect", "no").unwrap();
         mpv.set_property("loop", "inf").unwrap();
@@ -70,14 +70,12 @@ impl<'a> VideoPlayer<'a> {
                 }),
             ],
         ).unwrap();
-        let mpv_event_context = mpv.create_event_context();
         let fb = framebuffer::Framebuffer::new(&ctx, false, (114, 64), (0, 0));
 
         Self {
             fb,
-            mpv,
             mpv_render_context,
-            mpv_event_context,
+            mpv,
         }
     }
 
@@ -122,7 +120,6 @@ pub struct Context {
     pub clearcolor: Rgb,
     pub bgcolor: (i32, i32, i32),
     pub dims: utils::Dimensions,
-    pub attrib_expressions: HashMap<String, gl::types::GLuint>,
 }
 
 pub fn get_proc_address(
@@ -133,6 +130,16 @@ pub fn get_proc_address(
 
 impl Context {
     pub fn new() -> Self {
+        Self::with_window(true)
+    }
+
+    /// A context whose window is never shown, for tests that need GL.
+    #[cfg(test)]
+    pub fn hidden() -> Self {
+        Self::with_window(false)
+    }
+
+    fn with_window(shown: bool) -> Self {
         let sdl2 = sdl2::init().unwrap();
         let video = sdl2.video().unwrap();
         let image = sdl2::image::init(sdl2::image::InitFlag::PNG).unwrap();
@@ -143,31 +150,18 @@ impl Context {
         // gl_attr.set_context_major_version(3);
         // gl_attr.set_context_minor_version(2);
 
-        let window = video
-            .window("colonq", 640 as _, 360 as _)
-            .opengl()
-        // .fullscreen_desktop()
-            .build()
-            .unwrap();
+        let mut builder = video.window("colonq", 640 as _, 360 as _);
+        builder.opengl();
+        // builder.fullscreen_desktop();
+        if !shown {
+            builder.hidden();
+        }
+        let window = builder.build().unwrap();
         let gl_context = window.gl_create_context().unwrap();
         gl::load_with(|s| video.gl_get_proc_address(s) as *const std::ffi::c_void);
 
         let (winw, winh) = window.size();
 
-        let attrib_expressions: HashMap<String, gl::types::GLuint> = HashMap::from([
-            ("happy".to_owned(), 5),
-            ("angry".to_owned(), 6),
-            ("sad".to_owned(), 7),
-            ("relaxed".to_owned(), 8),
-            ("surprised".to_owned(), 9),
-            ("aa".to_owned(), 10),
-            ("ih".to_owned(), 11),
-            ("ou".to_owned(), 12),
-            ("ee".to_owned(), 13),
-            ("oh".to_owned(), 14),
-            ("blink".to_owned(), 15),
-        ]);
-
         let rgb = match env::var("COLONQ_BGCOLOR") {
             Ok(colorstr) => {
                 Rgb::from_hex_str(&colorstr).unwrap()
@@ -203,7 +197,6 @@ impl Context {
                 w: winw as _,
                 h: winh as _,
             },
-            attrib_expressions,
         }
     }
 }
@@ -3,7 +3,7 @@ use std::{io::Write, io::BufRead, collections::HashMap, sync::mpsc::Sender};
 use colors_transform::{Rgb, Color};
 use lexpr::sexp;
 
-use crate::{avatar, context};
+use crate::{avatar, context, config, stage, vrm};
 
 pub enum ControlCommand {
     PlayVideo {
@@ -11,6 +11,41 @@ pub enum ControlCommand {
         url: String,
     },
     ReloadPumpkin,
+    CameraPreset {
+        name: String,
+        duration: f32,
+    },
+    CameraOrbit {
+        yaw: f32,
+        pitch: f32,
+    },
+    CameraDolly(f32),
+    CameraRoll(f32),
+    CameraReset,
+    LoadAvatar {
+        name: Option<String>,
+        document: Box<vrm::Document>,
+    },
+    LoadProp {
+        name: String,
+        path: String,
+        texture: Option<String>,
+    },
+    Unload {
+        name: String,
+    },
+    MoveEntry {
+        name: String,
+        transform: stage::Transform,
+    },
+    ShowEntry {
+        name: String,
+        visible: bool,
+    },
+    Calibrate,
+    Record {
+        path: Option<String>,
+    },
 }
 
 pub struct ControlState {
@@ -18,6 +53,8 @@ pub struct ControlState {
     // pub video_is_playing: bool,
     pub properties: HashMap<String, i64>,
     pub palette: avatar::Palette,
+    pub entry_palettes: HashMap<String, avatar::Palette>,
+    pub scene: String,
 }
 
 #[derive(Clone)]
@@ -30,8 +67,41 @@ impl ControlState {
             // video_is_playing: false,
             properties: HashMap::new(),
             palette: avatar::Palette::new(),
+            entry_palettes: HashMap::new(),
+            scene: "default".to_owned(),
         }
     }
+
+    /// The palette for a stage entry, or the shared palette if no entry is named.
+    pub fn palette_mut(&mut self, entry: Option<&str>) -> &mut avatar::Palette {
+        match entry {
+            Some(nm) => self.entry_palettes.entry(nm.to_owned()).or_insert_with(avatar::Palette::new),
+            None => &mut self.palette,
+        }
+    }
+}
+
+fn decode_string(v: &lexpr::Value) -> Option<String> {
+    let decoded = base64::decode(v.as_str()?).ok()?;
+    String::from_utf8(decoded).ok()
+}
+
+/// Parse and validate a VRM off the control thread, then hand it to the renderer to upload.
+/// A `None` name swaps the model of the primary avatar.
+fn load_avatar<F>(command_sender: &Sender<ControlCommand>, name: Option<String>, load: F)
+    where F: FnOnce() -> Result<vrm::Document, String> + Send + 'static
+{
+    let command_sender = command_sender.clone();
+    std::thread::spawn(move || {
+        match load() {
+            Ok(document) => {
+                if let Err(e) = command_sender.send(ControlCommand::LoadAvatar { name, document: Box::new(document) }) {
+                    log::warn!("Dropping loaded avatar, the renderer has exited: {}", e);
+                }
+            },
+            Err(e) => log::error!("Failed to load avatar {:?}: {}", name, e),
+        }
+    });
 }
 
 impl Control {
@@ -50,7 +120,23 @@ impl Control {
         if self.get(prop) == 0 { false } else { true }
     }
 
-    pub fn run(self, command_sender: Sender<ControlCommand>) {
+    pub fn scene(&self) -> String {
+        self.0.lock().unwrap().scene.clone()
+    }
+
+    /// Switch to a scene preset, swapping the old preset's palette overrides for the new one's under a single lock.
+    pub fn activate_scene(&self, config: &config::Config, nm: &str) {
+        let mut st = self.0.lock().unwrap();
+        for pty in config.scene(&st.scene).palette.keys() {
+            st.palette.color_mapping.remove(pty);
+        }
+        for (pty, col) in &config.scene(nm).palette {
+            st.palette.color_mapping.insert(pty.clone(), avatar::PaletteEntry::Color(*col));
+        }
+        st.scene = nm.to_owned();
+    }
+
+    pub fn run(self, command_sender: Sender<ControlCommand>, config: config::Config) {
         std::thread::spawn(move || {
             // let mut stream = std::net::TcpStream::connect("shiro:32050").unwrap();
             let mut stream = std::net::TcpStream::connect("localhost:32050").unwrap();
@@ -60,7 +146,23 @@ impl Control {
 (sub (avatar palette image))
 (sub (avatar palette video))
 (sub (avatar reset))
+(sub (avatar scene))
 (sub (avatar pumpkinreload))
+(sub (avatar camera preset))
+(sub (avatar camera orbit))
+(sub (avatar camera dolly))
+(sub (avatar camera roll))
+(sub (avatar stage load avatar))
+(sub (avatar model load))
+(sub (avatar model data))
+(sub (avatar tracking calibrate))
+(sub (avatar tracking record start))
+(sub (avatar tracking record stop))
+(sub (avatar stage load prop))
+(sub (avatar stage unload))
+(sub (avatar stage move))
+(sub (avatar stage show))
+(sub (avatar stage hide))
 ".as_bytes()).unwrap();
             let reader = std::io::BufReader::new(stream);
             for l in reader.lines() {
@@ -70,16 +172,31 @@ impl Control {
                     Ok(v) => {
                         if v[0] == sexp!((avatar toggle)) {
                             let tnm = v[1].as_str().unwrap();
-                            let old = self.get(tnm);
-                            let new = if old == 0 { 1 } else { 0 };
-                            self.0.lock().unwrap().properties.insert(tnm.to_owned(), new);
+                            if config.scenes.contains_key(tnm) {
+                                let nm = if self.scene() == tnm { "default" } else { tnm };
+                                self.activate_scene(&config, nm);
+                            } else {
+                                let old = self.get(tnm);
+                                let new = if old == 0 { 1 } else { 0 };
+                                self.0.lock().unwrap().properties.insert(tnm.to_owned(), new);
+                            }
+                        } else if v[0] == sexp!((avatar scene)) {
+                            if let Some(nm) = v[1].as_str() {
+                                if config.scenes.contains_key(nm) {
+                                    log::info!("Requested scene preset: {}", nm);
+                                    self.activate_scene(&config, nm);
+                                } else {
+                                    log::warn!("Unknown scene preset: {}", nm);
+                                }
+                            }
                         } else if v[0] == sexp!((avatar reset)) {
                             *self.0.lock().unwrap() = ControlState::new();
+                            command_sender.send(ControlCommand::CameraReset).unwrap();
                         } else if v[0] == sexp!((avatar palette word)) {
                             let pty = context::PaletteType::from_string(v[1].as_str().unwrap()).unwrap();
                             let encodedword = v[2].as_str().unwrap().to_owned();
                             let decodedword = base64::decode(encodedword).unwrap();
-                            self.0.lock().unwrap().palette.word_mapping.insert(
+                            self.0.lock().unwrap().palette_mut(v[3].as_str()).word_mapping.insert(
                                 pty,
                                 String::from_utf8(decodedword).unwrap(),
                             );
@@ -90,7 +207,7 @@ impl Control {
                             let col = String::from_utf8(decodedcol).unwrap();
                             log::info!("Requested color on {:?}: {}", pty, &col);
                             if let Ok(rgb) = Rgb::from_hex_str(&col) {
-                                self.0.lock().unwrap().palette.color_mapping.insert(
+                                self.0.lock().unwrap().palette_mut(v[3].as_str()).color_mapping.insert(
                                     pty,
                                     avatar::PaletteEntry::Color(
                                         (rgb.get_red() as _, rgb.get_green() as _, rgb.get_blue() as _),
@@ -104,7 +221,7 @@ impl Control {
                             let path = String::from_utf8(decodedpath.clone()).unwrap();
                             log::info!("Requested image on {:?}: {}", pty, &path);
                             if let Some(pal) = avatar::PaletteEntry::from_image(&path) {
-                                self.0.lock().unwrap().palette.color_mapping.insert(
+                                self.0.lock().unwrap().palette_mut(v[3].as_str()).color_mapping.insert(
                                     pty,
                                     pal,
                                 );
@@ -123,6 +240,75 @@ impl Control {
                         } else if v[0] == sexp!((avatar pumpkinreload)) {
                             log::info!("Requested pumpkin reload");
                             command_sender.send(ControlCommand::ReloadPumpkin).unwrap();
+                        } else if v[0] == sexp!((avatar camera preset)) {
+                            if let Some(name) = v[1].as_str() {
+                                let duration = v[2].as_f64().unwrap_or(1.0) as f32;
+                                log::info!("Requested camera preset: {} over {}s", name, duration);
+                                command_sender.send(ControlCommand::CameraPreset { name: name.to_owned(), duration }).unwrap();
+                            }
+                        } else if v[0] == sexp!((avatar camera orbit)) {
+                            if let (Some(yaw), Some(pitch)) = (v[1].as_f64(), v[2].as_f64()) {
+                                command_sender.send(ControlCommand::CameraOrbit { yaw: yaw as _, pitch: pitch as _ }).unwrap();
+                            }
+                        } else if v[0] == sexp!((avatar camera dolly)) {
+                            if let Some(dolly) = v[1].as_f64() {
+                                command_sender.send(ControlCommand::CameraDolly(dolly as _)).unwrap();
+                            }
+                        } else if v[0] == sexp!((avatar camera roll)) {
+                            if let Some(roll) = v[1].as_f64() {
+                                command_sender.send(ControlCommand::CameraRoll(roll as _)).unwrap();
+                            }
+                        } else if v[0] == sexp!((avatar stage load avatar)) {
+                            if let (Some(name), Some(path)) = (v[1].as_str(), decode_string(&v[2])) {
+                                log::info!("Requested avatar {} from {}", name, &path);
+                                load_avatar(&command_sender, Some(name.to_owned()), move || vrm::Document::open(&path));
+                            }
+                        } else if v[0] == sexp!((avatar model load)) {
+                            if let Some(path) = decode_string(&v[1]) {
+                                log::info!("Requested model swap from {}", &path);
+                                load_avatar(&command_sender, v[2].as_str().map(|s| s.to_owned()), move || vrm::Document::open(&path));
+                            }
+                        } else if v[0] == sexp!((avatar model data)) {
+                            if let Some(encoded) = v[1].as_str() {
+                                log::info!("Requested model swap from {} bytes of base64", encoded.len());
+                                let encoded = encoded.to_owned();
+                                load_avatar(&command_sender, v[2].as_str().map(|s| s.to_owned()), move || {
+                                    let bytes = base64::decode(encoded).map_err(|e| format!("invalid base64: {}", e))?;
+                                    vrm::Document::from_slice(&bytes)
+                                });
+                            }
+                        } else if v[0] == sexp!((avatar tracking calibrate)) {
+                            log::info!("Requested tracking calibration");
+                            command_sender.send(ControlCommand::Calibrate).unwrap();
+                        } else if v[0] == sexp!((avatar tracking record start)) {
+                            if let Some(path) = decode_string(&v[1]) {
+                                command_sender.send(ControlCommand::Record { path: Some(path) }).unwrap();
+                            }
+                        } else if v[0] == sexp!((avatar tracking record stop)) {
+                            command_sender.send(ControlCommand::Record { path: None }).unwrap();
+                        } else if v[0] == sexp!((avatar stage load prop)) {
+                            if let (Some(name), Some(path)) = (v[1].as_str(), decode_string(&v[2])) {
+                                let texture = decode_string(&v[3]);
+                                log::info!("Requested prop {} from {} (texture {:?})", name, &path, texture);
+                                command_sender.send(ControlCommand::LoadProp { name: name.to_owned(), path, texture }).unwrap();
+                            }
+                        } else if v[0] == sexp!((avatar stage unload)) {
+                            if let Some(name) = v[1].as_str() {
+                                self.0.lock().unwrap().entry_palettes.remove(name);
+                                command_sender.send(ControlCommand::Unload { name: name.to_owned() }).unwrap();
+                            }
+                        } else if v[0] == sexp!((avatar stage move)) {
+                            if let (Some(name), Some(x), Some(y), Some(z)) = (v[1].as_str(), v[2].as_f64(), v[3].as_f64(), v[4].as_f64()) {
+                                let mut transform = stage::Transform::at(glam::Vec3::new(x as _, y as _, z as _));
+                                transform.rotation = glam::Quat::from_rotation_y(v[5].as_f64().unwrap_or(0.0) as _);
+                                transform.scale = v[6].as_f64().unwrap_or(1.0) as _;
+                                command_sender.send(ControlCommand::MoveEntry { name: name.to_owned(), transform }).unwrap();
+                            }
+                        } else if v[0] == sexp!((avatar stage show)) || v[0] == sexp!((avatar stage hide)) {
+                            if let Some(name) = v[1].as_str() {
+                                let visible = v[0] == sexp!((avatar stage show));
+                                command_sender.send(ControlCommand::ShowEntry { name: name.to_owned(), visible }).unwrap();
+                            }
                         }
                     },
                 }
@@ -7,10 +7,23 @@ use crate::{context, utils, gl, avatar, term};
 pub struct Framebuffer {
     pub tex: gl::types::GLuint,
     pub fbo: gl::types::GLuint,
+    pub depth_buffer: gl::types::GLuint,
     pub dims: utils::Dimensions,
     pub offsets: (i32, i32),
     pub pixels_len: usize,
     pub pixels: Vec<u8>,
+    pub stencil: Vec<u8>,
+}
+
+impl Drop for Framebuffer {
+    fn drop(&mut self) {
+        // names of 0 (the default framebuffer, or no depth buffer) are silently ignored
+        unsafe {
+            gl::DeleteFramebuffers(1, &self.fbo);
+            gl::DeleteRenderbuffers(1, &self.depth_buffer);
+            gl::DeleteTextures(1, &self.tex);
+        }
+    }
 }
 
 impl Framebuffer {
@@ -25,11 +38,11 @@ impl Framebuffer {
             gl::BindFramebuffer(gl::FRAMEBUFFER, fbo);
 
             if depth {
-                // generate and attach depth buffer
+                // generate and attach depth buffer, with stencil for tagging stage entries
                 gl::GenRenderbuffers(1, &mut depth_buffer);
                 gl::BindRenderbuffer(gl::RENDERBUFFER, depth_buffer);
-                gl::RenderbufferStorage(gl::RENDERBUFFER, gl::DEPTH_COMPONENT32F, w, h);
-                gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, gl::RENDERBUFFER, depth_buffer);
+                gl::RenderbufferStorage(gl::RENDERBUFFER, gl::DEPTH32F_STENCIL8, w, h);
+                gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, gl::DEPTH_STENCIL_ATTACHMENT, gl::RENDERBUFFER, depth_buffer);
             }
 
             // generate and attach texture
@@ -60,13 +73,16 @@ impl Framebuffer {
         }
         let pixels_len = (w * h * 4) as usize;
         let pixels = vec![0; pixels_len];
+        let stencil = if depth { vec![0; (w * h) as usize] } else { Vec::new() };
         Self {
             tex,
             fbo,
+            depth_buffer,
             dims: utils::Dimensions { w: w as _, h: h as _ },
             offsets,
             pixels,
             pixels_len,
+            stencil,
         }
     }
 
@@ -114,19 +130,34 @@ impl Framebuffer {
         ret
     }
 
+    /// Stencil tag of a pixel, using the same orientation as `get_pixel`.
+    pub fn get_stencil(&self, x: i32, y: i32) -> u8 {
+        let w = self.dims.w as i32;
+        let h = self.dims.h as i32;
+        let invx = w - (x + 1);
+        *self.stencil.get((invx * h + y) as usize).unwrap_or(&0)
+    }
+
     pub fn populate_pixels(&mut self) {
         unsafe {
             gl::GetTextureImage(self.tex, 0, gl::RGBA, gl::UNSIGNED_BYTE, self.pixels_len as _, self.pixels.as_mut_ptr() as _);
+            if !self.stencil.is_empty() {
+                gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.fbo);
+                gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+                gl::ReadPixels(0, 0, self.dims.w as _, self.dims.h as _, gl::STENCIL_INDEX, gl::UNSIGNED_BYTE, self.stencil.as_mut_ptr() as _);
+                gl::PixelStorei(gl::PACK_ALIGNMENT, 4);
+            }
         }
     }
 
-    pub fn render_term(&self, ctx: &context::Context, framecount: i32, players: &avatar::PalettePlayers, palette: &avatar::Palette, t: &mut term::Term, c: usize) {
+    pub fn render_term(&self, ctx: &context::Context, framecount: i32, players: &avatar::PalettePlayers, palettes: &[&avatar::Palette], t: &mut term::Term, c: usize) {
         t.count = c;
         let mut idx = 0;
         for x in 0..(self.dims.w as _) {
             for y in 0..(self.dims.h as _) {
                 match self.get_pixel(ctx.bgcolor, x, y) {
                     Some(col) => {
+                        let palette = palettes.get(self.get_stencil(x, y) as usize).unwrap_or(&palettes[0]);
                         let (custom, wordref, pal) = palette.lookup(col);
                         let (r, g, b) = match pal {
                             avatar::PaletteEntry::Color(c) => c,
@@ -17,11 +17,25 @@ mod avatar;
 mod mesh;
 mod texture;
 mod term;
+mod camera;
+mod config;
+mod stage;
+
+/// Treat the current tracked pose as neutral and save it for the active camera profile.
+fn calibrate(tracking_state: &tracking::SharedTrackingState, calibrations: &mut config::Calibrations, profile: &str) {
+    match tracking_state.lock().unwrap().calibrate() {
+        Some(cal) => calibrations.save(profile, cal),
+        None => log::warn!("Nothing tracked yet, cannot calibrate"),
+    }
+}
 
 fn render_loop(
     tracking_state: tracking::SharedTrackingState,
     control: fig::Control,
-    command_receiver: Receiver<fig::ControlCommand>
+    command_receiver: Receiver<fig::ControlCommand>,
+    config: config::Config,
+    mut calibrations: config::Calibrations,
+    recorder: tracking::recording::Recorder,
 ) {
     let ctx = context::Context::new();
     let mut term0 = term::Term::new();
@@ -31,7 +45,7 @@ fn render_loop(
     let mut term_counter = 0;
     let mut keyframe_counter = 0;
     let mut players = avatar::PalettePlayers::new(&ctx);
-    let shader = shader::Shader::new(&ctx, "../assets/shader.vert", "../assets/shader.frag");
+    let shader = shader::Shader::new(&ctx, "shaders/avatar.vert", "shaders/avatar.frag");
     let stdout = std::io::stdout().into_raw_mode().unwrap();
     let mut raw_stdout = stdout.into_raw_mode().unwrap();
 
@@ -45,29 +59,21 @@ fn render_loop(
 
     let (mut websocket, _) = tungstenite::connect("wss://colonq.computer/bullfrog/api/channel/broadcast?token=foobar").expect("failed to open websocket");
 
-    let mut avatar_old = avatar::Avatar::new(
-        &ctx, "../assets/colonq_v1.vrm",
-        |_| glam::Mat4::from_scale_rotation_translation(
-            glam::Vec3::new(1.0, 1.0, 1.0),
-            glam::Quat::from_rotation_y(std::f32::consts::PI / 2.0),
-            glam::Vec3::new(0.0, 0.02, 0.0)
-        ),
-    ); 
-    let mut avatar_new = avatar::Avatar::new(
-        &ctx, "../assets/lcolonq_flat.vrm",
-        |sts: &fig::Control| glam::Mat4::from_scale_rotation_translation(
-            glam::Vec3::new(1.0, 1.0, 1.0),
-            glam::Quat::from_rotation_y(
-                std::f32::consts::PI / 2.0 +
-                    if sts.is("forsen") {
-                        -0.30
-                    } else {
-                        0.0
-                    }
-            ),
-            glam::Vec3::new(-0.10, -0.07, 0.10),
-        ),
+    let mut stage = stage::Stage::new();
+    stage.insert(
+        &ctx,
+        "old",
+        stage::Model::Avatar(avatar::Avatar::new(&ctx, "../assets/colonq_v1.vrm")),
+        stage::Transform::at(glam::Vec3::new(0.0, 0.02, 0.0)),
     );
+    stage.insert(
+        &ctx,
+        "new",
+        stage::Model::Avatar(avatar::Avatar::new(&ctx, "../assets/lcolonq_flat.vrm")),
+        stage::Transform::at(glam::Vec3::new(-0.10, -0.07, 0.10)),
+    );
+    stage.get_mut("old").unwrap().visible = false;
+    stage.primary = "new".to_owned();
     // avatar_new.add_hat(avatar::hat::Hat::cone(&ctx));
 
     let mut fb = framebuffer::Framebuffer::new(&ctx, true, (64, 64), (0, 0));
@@ -76,18 +82,16 @@ fn render_loop(
     let screen = framebuffer::Framebuffer {
         fbo: 0,
         tex: 0,
+        depth_buffer: 0,
         dims: ctx.dims.clone(),
         offsets: (0, 0),
         pixels_len,
         pixels,
+        stencil: Vec::new(),
     };
 
-    let fb_projection = glam::Mat4::perspective_lh(
-        std::f32::consts::PI / 4.0,
-        fb.dims.w / fb.dims.h,
-        0.1,
-        10.0,
-    );
+    let mut rig = camera::Rig::new();
+    let mut active_scene = "default".to_owned();
 
     let mut event_pump = ctx.sdl2.event_pump().unwrap();
     let mut framecount = 0;
@@ -97,6 +101,20 @@ fn render_loop(
     print!("{}", termion::cursor::Hide);
 
     let dt: f32 = 1.0 / 60.0;
+    let mut smoother = tracking::filter::Smoother::new(&config.tracking);
+    let mut tracked = tracking::Sample::neutral();
+    let vmc_sender = config.tracking.send.as_ref().and_then(|addr| {
+        match tracking::vmc::Sender::connect(addr) {
+            Ok(s) => {
+                log::info!("Sending VMC pose to {}", addr);
+                Some(s)
+            },
+            Err(e) => {
+                log::error!("Failed to start VMC sender for {}: {}", addr, e);
+                None
+            },
+        }
+    });
     let mut last = std::time::Instant::now();
     let mut acc: f32 = 0.0;
     'mainloop: loop {
@@ -108,11 +126,17 @@ fn render_loop(
             }
         }
 
-        let avatar = if control.is("old") {
-            &mut avatar_old
-        } else {
-            &mut avatar_new
-        };
+        let scene = control.scene();
+        let preset = config.scene(&scene);
+        if scene != active_scene {
+            if let Some(shot) = camera::Shot::from_string(&preset.camera) {
+                rig.transition(shot, preset.transition);
+            } else {
+                log::warn!("Unknown camera preset in scene {}: {}", scene, preset.camera);
+            }
+            active_scene = scene;
+        }
+        stage.set_primary(&preset.avatar);
 
         acc += last.elapsed().as_secs_f32();
         last = std::time::Instant::now();
@@ -124,33 +148,35 @@ fn render_loop(
                     sdl2::event::Event::Quit { .. } => {
                         break 'mainloop;
                     },
+                    sdl2::event::Event::KeyDown { keycode: Some(sdl2::keyboard::Keycode::C), .. } => {
+                        calibrate(&tracking_state, &mut calibrations, &config.tracking.profile);
+                    },
                     _ => (),
                 }
             }
 
-            avatar.transform_bone("head", &glam::Mat4::from_rotation_translation(
-                tracking_state.lock().unwrap().orientation.inverse(),
-                glam::Vec3::ZERO,
-            ));
-            avatar.transform_bone("leftUpperArm", &glam::Mat4::from_rotation_translation(
-                glam::Quat::from_rotation_z(-std::f32::consts::PI / 4.0),
-                glam::Vec3::ZERO,
-            ));
-            avatar.transform_bone("rightUpperArm", &glam::Mat4::from_rotation_translation(
-                glam::Quat::from_rotation_z(std::f32::consts::PI / 4.0),
-                glam::Vec3::ZERO,
-            ));
+            tracked = smoother.update(&tracking_state.lock().unwrap(), dt);
+            let placement = stage.get(&stage.primary).map_or(stage::Transform::at(glam::Vec3::ZERO), |e| e.transform);
+            if let Some(avatar) = stage.primary_avatar() {
+                avatar.apply_pose(&preset.pose);
+                avatar.apply_bones(&tracked.bones);
+                avatar.apply_tracking(tracked.orientation, tracked.translation, &config.tracking);
+                avatar.apply_look_at(tracked.gaze.x, tracked.gaze.y, &tracked.expression_weights);
+                avatar.update_springs(dt);
+                if let Some(sender) = &vmc_sender {
+                    let mut weights = tracked.expression_weights.clone();
+                    weights.extend(avatar.look_weights.iter().map(|(k, v)| (k.clone(), *v)));
+                    sender.send(avatar, &placement, &weights);
+                }
+            }
+            rig.update(dt);
 
             // render framebuffer to terminal
             framecount = framecount + 1;
             float_time = float_time + 0.02;
 
-            let forsen = control.is("forsen");
-            let bgcolor = if forsen {
-                (0x2c, 0x52, 0x39)
-            } else {
-                (ctx.bgcolor.0 as u8, ctx.bgcolor.1 as u8, ctx.bgcolor.2 as u8)
-            };
+            let bgcolor = preset.background
+                .unwrap_or((ctx.bgcolor.0 as u8, ctx.bgcolor.1 as u8, ctx.bgcolor.2 as u8));
             if framecount == 1000 {
                 let mut f = std::fs::File::create("oneframe.txt").unwrap();
                 // term.render_stream_nocolor_small(&mut f);
@@ -159,7 +185,11 @@ fn render_loop(
             }
             if framecount % 6 == 0 {
                 fb.populate_pixels();
-                fb.render_term(&ctx, framecount, &mut players, &control.0.lock().unwrap().palette, term, term_counter);
+                {
+                    let st = control.0.lock().unwrap();
+                    let palettes = stage.palettes(&st);
+                    fb.render_term(&ctx, framecount, &mut players, &palettes, term, term_counter);
+                }
                 term_counter += 1;
                 term.render_stream(&mut raw_stdout, bgcolor);
                 // term.render_stream_nocolor(&mut raw_stdout);
@@ -184,37 +214,8 @@ fn render_loop(
         }
 
         // compute camera position and view matrix
-        let camera_pos_base = if control.is("forsen") {
-            glam::Vec3::new(0.0, 1.35, -0.4)
-        } else {
-            glam::Vec3::new(0.0, 1.5, -0.4)
-        };
-        let camera_pos = if control.is("zoom_wave") {
-            let offset = float_time.sin() * 0.1;
-            camera_pos_base + glam::Vec3::new(0.0, 0.0, offset)
-        } else {
-            camera_pos_base
-        };
-        let up = if control.is("spin") {
-            let angle = (std::f32::consts::PI / 2.0)
-                + if control.is("spin_direction") {
-                    -(float_time / 4.0)
-                } else {
-                    float_time / 4.0
-                };
-            glam::Vec3::new(angle.cos(), angle.sin(), 0.0)
-        } else {
-            glam::Vec3::new(0.0, 1.0, 0.0)
-        };
-        let view = glam::Mat4::look_at_lh(
-            camera_pos,
-            camera_pos + if control.is("forsen") {
-                glam::Vec3::new(0.0, 0.2, 1.0)
-            } else {
-                glam::Vec3::new(0.0, 0.0, 1.0)
-            },
-            up,
-        );
+        let (camera_pos, view) = rig.view(&control, float_time);
+        let fb_projection = rig.projection(fb.dims.w / fb.dims.h);
 
         // update video players
         while let Ok(comm) = command_receiver.try_recv() {
@@ -227,6 +228,46 @@ fn render_loop(
                 fig::ControlCommand::ReloadPumpkin => {
                     // avatar.pumpkin.reload(&ctx);
                 },
+                fig::ControlCommand::CameraPreset { name, duration } => {
+                    if let Some(shot) = camera::Shot::from_string(&name) {
+                        rig.transition(shot, duration);
+                    } else {
+                        log::warn!("Unknown camera preset: {}", name);
+                    }
+                },
+                fig::ControlCommand::CameraOrbit { yaw, pitch } => rig.orbit(yaw, pitch),
+                fig::ControlCommand::CameraDolly(dolly) => rig.dolly(dolly),
+                fig::ControlCommand::CameraRoll(roll) => rig.roll(roll),
+                fig::ControlCommand::CameraReset => rig.reset(),
+                fig::ControlCommand::LoadAvatar { name, document } => {
+                    let name = name.unwrap_or_else(|| stage.primary.clone());
+                    log::info!("Swapping in new model for {}", name);
+                    stage.replace_model(&ctx, &name, stage::Model::Avatar(avatar::Avatar::from_document(&ctx, &document)));
+                },
+                fig::ControlCommand::LoadProp { name, path, texture } => {
+                    if let Some(prop) = stage::Prop::new(&ctx, &path, texture.as_deref()) {
+                        stage.insert(&ctx, &name, stage::Model::Prop(prop), stage::Transform::at(glam::Vec3::ZERO));
+                    } else {
+                        log::error!("Failed to load prop {} from {}", name, path);
+                    }
+                },
+                fig::ControlCommand::Unload { name } => {
+                    if !stage.remove(&name) {
+                        log::warn!("No stage entry to unload: {}", name);
+                    }
+                },
+                fig::ControlCommand::MoveEntry { name, transform } => {
+                    if let Some(e) = stage.get_mut(&name) {
+                        e.transform = transform;
+                    }
+                },
+                fig::ControlCommand::ShowEntry { name, visible } => {
+                    if let Some(e) = stage.get_mut(&name) {
+                        e.visible = visible;
+                    }
+                },
+                fig::ControlCommand::Calibrate => calibrate(&tracking_state, &mut calibrations, &config.tracking.profile),
+                fig::ControlCommand::Record { path } => recorder.set_path(path.as_deref()),
             }
         }
         players.update(&ctx, &control.0.lock().unwrap().palette);
@@ -236,17 +277,8 @@ fn render_loop(
         unsafe {
             gl::ClearColor(ctx.bgcolor.0 as f32 / 255.0, ctx.bgcolor.1 as f32 / 255.0, ctx.bgcolor.2 as f32 / 255.0, 1.0);
         }
-        unsafe { gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT); }
-        shader.bind(&ctx);
-        unsafe {
-            gl::UniformMatrix4fv(shader.uniform_view, 1, false as u8, view.to_cols_array().as_ptr());
-            gl::UniformMatrix4fv(shader.uniform_projection, 1, false as u8, fb_projection.to_cols_array().as_ptr());
-            gl::Uniform3fv(shader.uniform_camera_pos, 1, camera_pos.to_array().as_ptr());
-            for (enm, uniform) in &shader.uniform_expressions {
-                gl::Uniform1f(*uniform, *tracking_state.lock().unwrap().expression_weights.get(enm).unwrap());
-            }
-        }
-        avatar.render(&ctx, &shader, &view, &fb_projection, &control);
+        unsafe { gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT | gl::STENCIL_BUFFER_BIT); }
+        stage.render(&ctx, &shader, &view, &fb_projection, &camera_pos, &tracked.expression_weights);
         screen.bind(&ctx);
         unsafe { gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT); }
         unsafe {
@@ -264,22 +296,29 @@ fn main() {
     simple_logging::log_to_file("colonq.log", log::LevelFilter::Debug).unwrap();
     log_panics::init();
 
-    let tracking_state = tracking::TrackingState::new();
+    let config = config::Config::load();
+    let calibrations = config::Calibrations::load(&config);
+    let mut tracking_state = tracking::TrackingState::new();
+    tracking_state.calibration = calibrations.get(&config.tracking.profile);
     let shared_tracking_state = std::sync::Arc::new(std::sync::Mutex::new(tracking_state));
     let shared_tracking_state_clone = shared_tracking_state.clone();
+    let recorder = tracking::recording::Recorder::spawn();
+    recorder.set_path(config.tracking.record.as_deref());
 
     let control = fig::Control::new();
     let (command_sender, command_receiver) = channel();
 
     let render_handle = std::thread::spawn({
         let control = control.clone();
+        let config = config.clone();
+        let recorder = recorder.clone();
         move || {
-            render_loop(shared_tracking_state_clone, control, command_receiver);
+            render_loop(shared_tracking_state_clone, control, command_receiver, config, calibrations, recorder);
         }
     });
 
-    tracking::TrackingState::run(shared_tracking_state.clone());
-    control.run(command_sender);
+    tracking::TrackingState::run(shared_tracking_state.clone(), config.tracking.clone(), recorder);
+    control.run(command_sender, config);
 
     render_handle.join().unwrap();
 }
@@ -8,10 +8,20 @@ use crate::utils;
 pub struct Mesh {
     pub mesh: tobj::Mesh,
     pub vao: gl::types::GLuint,
+    pub buffers: [gl::types::GLuint; 4],
+}
+
+impl Drop for Mesh {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteVertexArrays(1, &self.vao);
+            gl::DeleteBuffers(self.buffers.len() as _, self.buffers.as_ptr());
+        }
+    }
 }
 
 impl Mesh {
-    pub fn build(mesh: &tobj::Mesh) -> gl::types::GLuint {
+    pub fn build(mesh: &tobj::Mesh) -> (gl::types::GLuint, [gl::types::GLuint; 4]) {
         unsafe {
             let mut vao: gl::types::GLuint = 0;
             gl::GenVertexArrays(1, &mut vao as *mut gl::types::GLuint);
@@ -65,7 +75,7 @@ impl Mesh {
                 mesh.indices.as_ptr() as *const c_void,
                 gl::STATIC_DRAW,
             );
-            vao
+            (vao, [vertices, normals, texcoords, indices])
         }
     }
 
@@ -77,8 +87,8 @@ impl Mesh {
         };
         let (meshes, _materials) = tobj::load_obj(p, &lopts).ok()?;
         let mesh = meshes.into_iter().next()?.mesh;
-        let vao = Self::build(&mesh);
-        Some(Self { mesh, vao })
+        let (vao, buffers) = Self::build(&mesh);
+        Some(Self { mesh, vao, buffers })
     }
 
     pub fn render(&self) {
@@ -1,7 +1,5 @@
 use crate::{gl, utils, context};
 
-use std::collections::HashMap;
-
 #[allow(dead_code)]
 pub struct Shader {
     pub prog: gl::types::GLuint,
@@ -11,9 +9,69 @@ pub struct Shader {
     pub uniform_normal: gl::types::GLint,
     pub uniform_camera_pos: gl::types::GLint,
 
+    // Skinned vertices blend `joint`/`weight` and, for meshes with JOINTS_1/WEIGHTS_1, `joint1`/`weight1`;
+    // `weight1` reads as zero otherwise.
     pub uniform_joint_matrices: gl::types::GLint,
 
-    pub uniform_expressions: HashMap<String, gl::types::GLint>,
+    // Morph targets are read in the vertex shader (shaders/avatar.vert) from
+    // `layout(std430, binding = 0) buffer Morphs { vec4 morph_deltas[]; };`
+    // where target t's position delta for gl_VertexID v is morph_deltas[(t * morph_vertex_count + v) * 2]
+    // and its normal delta the element after. morph_indices[i] and morph_weights[i] for i < morph_count
+    // name the targets to apply.
+    pub uniform_morph_count: gl::types::GLint,
+    pub uniform_morph_vertex_count: gl::types::GLint,
+    pub uniform_morph_indices: gl::types::GLint,
+    pub uniform_morph_weights: gl::types::GLint,
+
+    // Material values with expression binds applied; sample textures at `texcoord * uv_scale + uv_offset`.
+    pub uniform_base_color: gl::types::GLint,
+    pub uniform_emission: gl::types::GLint,
+    pub uniform_shade_color: gl::types::GLint,
+    pub uniform_uv_scale: gl::types::GLint,
+    pub uniform_uv_offset: gl::types::GLint,
+
+    // Per texture unit: `texcoord_sets[unit]` picks `texcoord` (0) or `texcoord1` (1), and the chosen
+    // UV is transformed by the KHR_texture_transform matrix `texture_transforms[unit]` before the
+    // expression scale and offset above.
+    pub uniform_texcoord_sets: gl::types::GLint,
+    pub uniform_texture_transforms: gl::types::GLint,
+
+    // glTF metallic-roughness. `alpha_mode` is 0 for opaque (alpha ignored), 1 for masked (discard
+    // below `alpha_cutoff`) and 2 for blended.
+    pub uniform_metallic: gl::types::GLint,
+    pub uniform_roughness: gl::types::GLint,
+    pub uniform_normal_scale: gl::types::GLint,
+    pub uniform_occlusion_strength: gl::types::GLint,
+    pub uniform_alpha_mode: gl::types::GLint,
+    pub uniform_alpha_cutoff: gl::types::GLint,
+
+    // MToon. `mtoon` is 0 for plain materials. Outlines are a second pass with front faces culled
+    // and `outline` set to 1: the vertex shader pushes vertices out along their normals by
+    // `outline_width` times the outline width texture, in world units (mode 1) or as a fraction of
+    // the screen height (mode 2).
+    pub uniform_mtoon: gl::types::GLint,
+    pub uniform_shading_shift: gl::types::GLint,
+    pub uniform_shading_shift_scale: gl::types::GLint,
+    pub uniform_shading_toony: gl::types::GLint,
+    pub uniform_gi_equalization: gl::types::GLint,
+    pub uniform_matcap_color: gl::types::GLint,
+    pub uniform_rim_color: gl::types::GLint,
+    pub uniform_rim_lighting_mix: gl::types::GLint,
+    pub uniform_rim_fresnel_power: gl::types::GLint,
+    pub uniform_rim_lift: gl::types::GLint,
+    pub uniform_outline: gl::types::GLint,
+    pub uniform_outline_width_mode: gl::types::GLint,
+    pub uniform_outline_width: gl::types::GLint,
+    pub uniform_outline_color: gl::types::GLint,
+    pub uniform_outline_lighting_mix: gl::types::GLint,
+}
+
+impl Drop for Shader {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteProgram(self.prog);
+        }
+    }
 }
 
 impl Shader {
@@ -45,7 +103,7 @@ impl Shader {
         }
     }
 
-    pub fn new(ctx: &context::Context, vtxpath: &str, fragpath: &str) -> Self {
+    pub fn new(_ctx: &context::Context, vtxpath: &str, fragpath: &str) -> Self {
         unsafe {
             let prog = gl::CreateProgram();
 
@@ -80,17 +138,31 @@ impl Shader {
             gl::BindAttribLocation(prog, utils::ATTRIB_TEXCOORD, b"texcoord\0".as_ptr() as *const i8);
             gl::BindAttribLocation(prog, utils::ATTRIB_JOINT, b"joint\0".as_ptr() as *const i8);
             gl::BindAttribLocation(prog, utils::ATTRIB_WEIGHT, b"weight\0".as_ptr() as *const i8);
+            gl::BindAttribLocation(prog, utils::ATTRIB_TEXCOORD1, b"texcoord1\0".as_ptr() as *const i8);
+            gl::BindAttribLocation(prog, utils::ATTRIB_JOINT1, b"joint1\0".as_ptr() as *const i8);
+            gl::BindAttribLocation(prog, utils::ATTRIB_WEIGHT1, b"weight1\0".as_ptr() as *const i8);
+            gl::BindAttribLocation(prog, utils::ATTRIB_TANGENT, b"tangent\0".as_ptr() as *const i8);
 
             gl::LinkProgram(prog);
             Self::check_link_error(prog);
 
-            for (enm, attrib) in &ctx.attrib_expressions {
-                gl::BindAttribLocation(prog, *attrib, format!("expression_{}_vertex\0", enm).as_ptr() as *const i8);
+            gl::UseProgram(prog);
+            let samplers: [(&[u8], gl::types::GLuint); utils::TEXUNIT_COUNT] = [
+                (b"base_color_texture\0", utils::TEXUNIT_BASE_COLOR),
+                (b"emissive_texture\0", utils::TEXUNIT_EMISSIVE),
+                (b"shade_multiply_texture\0", utils::TEXUNIT_SHADE_MULTIPLY),
+                (b"shading_shift_texture\0", utils::TEXUNIT_SHADING_SHIFT),
+                (b"matcap_texture\0", utils::TEXUNIT_MATCAP),
+                (b"rim_multiply_texture\0", utils::TEXUNIT_RIM_MULTIPLY),
+                (b"outline_width_texture\0", utils::TEXUNIT_OUTLINE_WIDTH),
+                (b"metallic_roughness_texture\0", utils::TEXUNIT_METALLIC_ROUGHNESS),
+                (b"normal_texture\0", utils::TEXUNIT_NORMAL),
+                (b"occlusion_texture\0", utils::TEXUNIT_OCCLUSION),
+            ];
+            for (nm, unit) in samplers.iter() {
+                gl::Uniform1i(gl::GetUniformLocation(prog, nm.as_ptr() as *const i8), *unit as _);
             }
-
-            let uniform_expressions = ctx.attrib_expressions.iter().map(|(enm, _)| {
-                (enm.clone(), gl::GetUniformLocation(prog, format!("expression_{}_weight\0", enm).as_ptr() as *const i8))
-            }).collect();
+            gl::UseProgram(0);
 
             Self {
                 prog,
@@ -100,7 +172,38 @@ impl Shader {
                 uniform_normal: gl::GetUniformLocation(prog, b"normal_matrix\0".as_ptr() as *const i8),
                 uniform_camera_pos: gl::GetUniformLocation(prog, b"camera_pos\0".as_ptr() as *const i8),
                 uniform_joint_matrices: gl::GetUniformLocation(prog, b"joint_matrices\0".as_ptr() as *const i8),
-                uniform_expressions,
+                uniform_morph_count: gl::GetUniformLocation(prog, b"morph_count\0".as_ptr() as *const i8),
+                uniform_morph_vertex_count: gl::GetUniformLocation(prog, b"morph_vertex_count\0".as_ptr() as *const i8),
+                uniform_morph_indices: gl::GetUniformLocation(prog, b"morph_indices\0".as_ptr() as *const i8),
+                uniform_morph_weights: gl::GetUniformLocation(prog, b"morph_weights\0".as_ptr() as *const i8),
+                uniform_base_color: gl::GetUniformLocation(prog, b"base_color\0".as_ptr() as *const i8),
+                uniform_emission: gl::GetUniformLocation(prog, b"emission\0".as_ptr() as *const i8),
+                uniform_shade_color: gl::GetUniformLocation(prog, b"shade_color\0".as_ptr() as *const i8),
+                uniform_uv_scale: gl::GetUniformLocation(prog, b"uv_scale\0".as_ptr() as *const i8),
+                uniform_uv_offset: gl::GetUniformLocation(prog, b"uv_offset\0".as_ptr() as *const i8),
+                uniform_texcoord_sets: gl::GetUniformLocation(prog, b"texcoord_sets\0".as_ptr() as *const i8),
+                uniform_texture_transforms: gl::GetUniformLocation(prog, b"texture_transforms\0".as_ptr() as *const i8),
+                uniform_metallic: gl::GetUniformLocation(prog, b"metallic\0".as_ptr() as *const i8),
+                uniform_roughness: gl::GetUniformLocation(prog, b"roughness\0".as_ptr() as *const i8),
+                uniform_normal_scale: gl::GetUniformLocation(prog, b"normal_scale\0".as_ptr() as *const i8),
+                uniform_occlusion_strength: gl::GetUniformLocation(prog, b"occlusion_strength\0".as_ptr() as *const i8),
+                uniform_alpha_mode: gl::GetUniformLocation(prog, b"alpha_mode\0".as_ptr() as *const i8),
+                uniform_alpha_cutoff: gl::GetUniformLocation(prog, b"alpha_cutoff\0".as_ptr() as *const i8),
+                uniform_mtoon: gl::GetUniformLocation(prog, b"mtoon\0".as_ptr() as *const i8),
+                uniform_shading_shift: gl::GetUniformLocation(prog, b"shading_shift\0".as_ptr() as *const i8),
+                uniform_shading_shift_scale: gl::GetUniformLocation(prog, b"shading_shift_scale\0".as_ptr() as *const i8),
+                uniform_shading_toony: gl::GetUniformLocation(prog, b"shading_toony\0".as_ptr() as *const i8),
+                uniform_gi_equalization: gl::GetUniformLocation(prog, b"gi_equalization\0".as_ptr() as *const i8),
+                uniform_matcap_color: gl::GetUniformLocation(prog, b"matcap_color\0".as_ptr() as *const i8),
+                uniform_rim_color: gl::GetUniformLocation(prog, b"rim_color\0".as_ptr() as *const i8),
+                uniform_rim_lighting_mix: gl::GetUniformLocation(prog, b"rim_lighting_mix\0".as_ptr() as *const i8),
+                uniform_rim_fresnel_power: gl::GetUniformLocation(prog, b"rim_fresnel_power\0".as_ptr() as *const i8),
+                uniform_rim_lift: gl::GetUniformLocation(prog, b"rim_lift\0".as_ptr() as *const i8),
+                uniform_outline: gl::GetUniformLocation(prog, b"outline\0".as_ptr() as *const i8),
+                uniform_outline_width_mode: gl::GetUniformLocation(prog, b"outline_width_mode\0".as_ptr() as *const i8),
+                uniform_outline_width: gl::GetUniformLocation(prog, b"outline_width\0".as_ptr() as *const i8),
+                uniform_outline_color: gl::GetUniformLocation(prog, b"outline_color\0".as_ptr() as *const i8),
+                uniform_outline_lighting_mix: gl::GetUniformLocation(prog, b"outline_lighting_mix\0".as_ptr() as *const i8),
             }
         }
     }
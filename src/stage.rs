@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+
+use crate::{avatar, context, fig, gl, mesh, shader, texture};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    pub translation: glam::Vec3,
+    pub rotation: glam::Quat,
+    pub scale: f32,
+}
+
+impl Transform {
+    pub fn at(translation: glam::Vec3) -> Self {
+        Self {
+            translation,
+            rotation: glam::Quat::IDENTITY,
+            scale: 1.0,
+        }
+    }
+
+    pub fn matrix(&self) -> glam::Mat4 {
+        glam::Mat4::from_scale_rotation_translation(
+            glam::Vec3::splat(self.scale),
+            self.rotation,
+            self.translation,
+        )
+    }
+}
+
+/// A static OBJ mesh placed in the scene, drawn with the stage's prop shader.
+pub struct Prop {
+    pub mesh: mesh::Mesh,
+    pub texture: Option<texture::Texture>,
+}
+
+impl Prop {
+    pub fn new(ctx: &context::Context, path: &str, texture: Option<&str>) -> Option<Self> {
+        let mesh = mesh::Mesh::new(std::path::Path::new(path))?;
+        let texture = match texture {
+            Some(p) => Some(texture::Texture::load(ctx, p)?),
+            None => None,
+        };
+        Some(Self {
+            mesh,
+            texture,
+        })
+    }
+
+    pub fn render(
+        &self,
+        ctx: &context::Context,
+        shader: &shader::Shader,
+        view: &glam::Mat4,
+        projection: &glam::Mat4,
+        position: &glam::Mat4,
+    ) {
+        shader.bind(ctx);
+        unsafe {
+            gl::UniformMatrix4fv(shader.uniform_view, 1, false as u8, view.to_cols_array().as_ptr());
+            gl::UniformMatrix4fv(shader.uniform_projection, 1, false as u8, projection.to_cols_array().as_ptr());
+            gl::UniformMatrix4fv(shader.uniform_position, 1, false as u8, position.to_cols_array().as_ptr());
+        }
+        if let Some(t) = &self.texture { t.bind(); }
+        self.mesh.render();
+    }
+}
+
+pub enum Model {
+    Avatar(avatar::Avatar),
+    Prop(Prop),
+}
+
+pub struct Entry {
+    pub name: String,
+    pub model: Model,
+    pub transform: Transform,
+    pub visible: bool,
+}
+
+/// Everything drawn into the avatar framebuffer. Entries are tagged in the stencil buffer by
+/// their index plus one, so the terminal renderer can pick each entry's palette.
+pub struct Stage {
+    pub entries: Vec<Entry>,
+    pub primary: String,
+    /// Shared by every prop, so adding props doesn't compile new programs. Compiled when the first
+    /// prop is added.
+    pub prop_shader: Option<shader::Shader>,
+}
+
+impl Stage {
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            primary: String::new(),
+            prop_shader: None,
+        }
+    }
+
+    /// Compile the prop shader if `model` is the first prop.
+    fn prepare(&mut self, ctx: &context::Context, model: &Model) {
+        if let (Model::Prop(_), None) = (model, &self.prop_shader) {
+            self.prop_shader = Some(shader::Shader::new(ctx, "../assets/shaderpumpkin.vert", "../assets/shaderpumpkin.frag"));
+        }
+    }
+
+    /// Add an entry, replacing any existing entry with the same name.
+    pub fn insert(&mut self, ctx: &context::Context, name: &str, model: Model, transform: Transform) {
+        self.prepare(ctx, &model);
+        let entry = Entry {
+            name: name.to_owned(),
+            model,
+            transform,
+            visible: true,
+        };
+        if let Some(e) = self.get_mut(name) {
+            *e = entry;
+        } else if self.entries.len() < 255 {
+            self.entries.push(entry);
+        } else {
+            log::error!("Stage is full, not adding {}", name);
+        }
+    }
+
+    /// Swap the model shown by an entry, keeping its transform and visibility. Adds the entry if it is missing.
+    pub fn replace_model(&mut self, ctx: &context::Context, name: &str, model: Model) {
+        self.prepare(ctx, &model);
+        if let Some(e) = self.get_mut(name) {
+            e.model = model;
+        } else {
+            self.insert(ctx, name, model, Transform::at(glam::Vec3::ZERO));
+        }
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool {
+        let len = self.entries.len();
+        self.entries.retain(|e| e.name != name);
+        self.entries.len() != len
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Entry> {
+        self.entries.iter().find(|e| e.name == name)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Entry> {
+        self.entries.iter_mut().find(|e| e.name == name)
+    }
+
+    /// The avatar that receives tracking and pose.
+    pub fn primary_avatar(&mut self) -> Option<&mut avatar::Avatar> {
+        let primary = self.primary.clone();
+        match self.get_mut(&primary).map(|e| &mut e.model) {
+            Some(Model::Avatar(a)) => Some(a),
+            _ => None,
+        }
+    }
+
+    /// Make another avatar primary, hiding the previous one and showing the new one.
+    pub fn set_primary(&mut self, name: &str) {
+        if name == self.primary { return; }
+        let old = std::mem::replace(&mut self.primary, name.to_owned());
+        if let Some(e) = self.get_mut(&old) { e.visible = false; }
+        if let Some(e) = self.get_mut(name) { e.visible = true; }
+    }
+
+    /// Palettes indexed by stencil value: slot 0 is the background, slot i + 1 is entry i.
+    pub fn palettes<'a>(&self, st: &'a fig::ControlState) -> Vec<&'a avatar::Palette> {
+        std::iter::once(&st.palette)
+            .chain(self.entries.iter().map(|e| st.entry_palettes.get(&e.name).unwrap_or(&st.palette)))
+            .collect()
+    }
+
+    pub fn render(
+        &self,
+        ctx: &context::Context,
+        shader: &shader::Shader,
+        view: &glam::Mat4,
+        projection: &glam::Mat4,
+        camera_pos: &glam::Vec3,
+        expression_weights: &HashMap<String, f32>,
+    ) {
+        let none = HashMap::new();
+        unsafe {
+            gl::Enable(gl::STENCIL_TEST);
+            gl::StencilOp(gl::KEEP, gl::KEEP, gl::REPLACE);
+        }
+        for (i, e) in self.entries.iter().enumerate().filter(|(_, e)| e.visible) {
+            unsafe { gl::StencilFunc(gl::ALWAYS, (i + 1) as _, 0xff); }
+            let position = e.transform.matrix();
+            match &e.model {
+                Model::Avatar(a) => {
+                    shader.bind(ctx);
+                    unsafe {
+                        gl::UniformMatrix4fv(shader.uniform_view, 1, false as u8, view.to_cols_array().as_ptr());
+                        gl::UniformMatrix4fv(shader.uniform_projection, 1, false as u8, projection.to_cols_array().as_ptr());
+                        gl::Uniform3fv(shader.uniform_camera_pos, 1, camera_pos.to_array().as_ptr());
+                    }
+                    // only the primary avatar is tracked
+                    let (weights, look) = if e.name == self.primary {
+                        (expression_weights, &a.look_weights)
+                    } else {
+                        (&none, &none)
+                    };
+                    a.render(ctx, shader, &position, weights, look, camera_pos);
+                },
+                Model::Prop(p) => {
+                    if let Some(s) = &self.prop_shader {
+                        p.render(ctx, s, view, projection, &position);
+                    }
+                },
+            }
+        }
+        unsafe { gl::Disable(gl::STENCIL_TEST); }
+    }
+}
@@ -6,11 +6,23 @@ pub struct Texture {
     pub tid: gl::types::GLuint,
 }
 
+impl Drop for Texture {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteTextures(1, &self.tid);
+        }
+    }
+}
+
 impl Texture {
-    pub fn new(_ctx: &context::Context, p: &str) -> Self {
-        let presurf = sdl2::surface::Surface::from_file(p).unwrap();
-        let surf = presurf.convert_format(sdl2::pixels::PixelFormatEnum::ABGR8888).unwrap();
-        let pixels = surf.without_lock().unwrap();
+    pub fn new(ctx: &context::Context, p: &str) -> Self {
+        Self::load(ctx, p).unwrap()
+    }
+
+    pub fn load(_ctx: &context::Context, p: &str) -> Option<Self> {
+        let presurf = sdl2::surface::Surface::from_file(p).ok()?;
+        let surf = presurf.convert_format(sdl2::pixels::PixelFormatEnum::ABGR8888).ok()?;
+        let pixels = surf.without_lock()?;
         let mut texture: gl::types::GLuint = 0;
         unsafe {
             gl::GenTextures(1, &mut texture as *mut gl::types::GLuint);
@@ -32,7 +44,7 @@ impl Texture {
             );
             gl::GenerateMipmap(gl::TEXTURE_2D);
         }
-        Self { tid: texture }
+        Some(Self { tid: texture })
     }
 
     pub fn reload(&self, _ctx: &context::Context, p: &str) {
@@ -1,16 +1,40 @@
 use std::collections::HashMap;
 
-use byteorder::ByteOrder;
+use crate::config;
+
+pub mod filter;
+pub mod lipsync;
+pub mod openseeface;
+pub mod osc;
+pub mod recording;
+pub mod vmc;
 
 pub type SharedTrackingState = std::sync::Arc<std::sync::Mutex<TrackingState>>;
 
-pub struct TrackingState {
+/// Something that feeds `TrackingState`, run on its own thread.
+pub trait TrackingSource: Send {
+    fn name(&self) -> &str;
+
+    /// Block until the next update arrives and apply it.
+    fn receive(&mut self, sts: &SharedTrackingState, config: &config::Tracking) -> Result<(), String>;
+}
+
+/// Everything the tracker tells us about the face at one instant.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
     pub expression_weights: HashMap<String, f32>,
     pub orientation: glam::Quat,
+    /// Head offset from where it was first seen, in tracker units with +Y up and +Z toward the camera.
+    pub translation: glam::Vec3,
+    /// Eye gaze relative to the head as (yaw, pitch) in degrees.
+    pub gaze: glam::Vec2,
+    /// Local rotations for humanoid bones, keyed by VRM bone name, from full-body sources.
+    pub bones: HashMap<String, glam::Quat>,
 }
 
-impl TrackingState {
-    pub fn new() -> Self {
+impl Sample {
+    /// Facing forward with every expression relaxed.
+    pub fn neutral() -> Self {
         Self {
             expression_weights: HashMap::from([
                 ("happy".to_owned(), 0.0),
@@ -26,66 +50,221 @@ impl TrackingState {
                 ("blink".to_owned(), 0.0),
             ]),
             orientation: glam::Quat::IDENTITY,
+            translation: glam::Vec3::ZERO,
+            gaze: glam::Vec2::ZERO,
+            bones: HashMap::new(),
+        }
+    }
+
+    /// Blend toward `other`; expressions missing from either side count as 0 and bones as unrotated.
+    pub fn lerp(&self, other: &Self, t: f32) -> Self {
+        let mut expression_weights = self.expression_weights.clone();
+        for (nm, e) in expression_weights.iter_mut() {
+            if !other.expression_weights.contains_key(nm) {
+                *e -= *e * t;
+            }
+        }
+        for (nm, w) in &other.expression_weights {
+            let e = expression_weights.entry(nm.clone()).or_insert(0.0);
+            *e += (w - *e) * t;
+        }
+        let mut bones = self.bones.clone();
+        for (nm, q) in bones.iter_mut() {
+            *q = q.slerp(other.bones.get(nm).copied().unwrap_or(glam::Quat::IDENTITY), t);
+        }
+        for (nm, q) in &other.bones {
+            bones.entry(nm.clone()).or_insert_with(|| glam::Quat::IDENTITY.slerp(*q, t));
+        }
+        Self {
+            expression_weights,
+            orientation: self.orientation.slerp(other.orientation, t),
+            translation: self.translation.lerp(other.translation, t),
+            gaze: self.gaze.lerp(other.gaze, t),
+            bones,
+        }
+    }
+}
+
+pub struct TrackingState {
+    pub sample: Sample,
+    pub previous: Sample,
+    /// When the latest sample arrived, and the smoothed time between samples in seconds.
+    pub received: Option<std::time::Instant>,
+    pub interval: f32,
+    /// Neutral pose subtracted from the raw tracker pose; without one, the first position seen is neutral.
+    pub calibration: Option<config::Calibration>,
+    raw: Option<config::Calibration>,
+    neutral_translation: Option<glam::Vec3>,
+    /// Viseme weights from audio, combined with the camera's after smoothing.
+    pub lipsync: HashMap<String, f32>,
+}
+
+impl TrackingState {
+    pub fn new() -> Self {
+        Self {
+            sample: Sample::neutral(),
+            previous: Sample::neutral(),
+            received: None,
+            interval: 1.0 / 30.0,
+            calibration: None,
+            raw: None,
+            neutral_translation: None,
+            lipsync: HashMap::new(),
+        }
+    }
+
+    fn push(&mut self, sample: Sample) {
+        let now = std::time::Instant::now();
+        if let Some(r) = self.received {
+            let elapsed = now.duration_since(r).as_secs_f32().clamp(1.0 / 240.0, 0.5);
+            self.interval += (elapsed - self.interval) * 0.2;
         }
+        self.previous = std::mem::replace(&mut self.sample, sample);
+        self.received = Some(now);
     }
 
-    pub fn run(sts: SharedTrackingState) {
-        let blink_offset
-            = 8 // timestamp
-            + 4 // face id
-            + 4 // width
-            + 4 // height
-            ;
-        let quat_offset
-            = blink_offset
-            + 4 // left eye blink
-            + 4 // right eye blink
-            + 1 // success
-            + 4 // pnp error
-            ;
-        let mouth_offset
-            = quat_offset
-            + 12 // euler
-            + 12 // translation
-            + (68 * (4 + 8 + 12)) // points
-            + 16 // right gaze
-            + 16 // left gaze
-            + 4 // eye left
-            + 4 // eye right
-            + 4 // eyebrow steepness left
-            + 4 // eyebrow updown left
-            + 4 // eyebrow quirk left
-            + 4 // eyebrow steepness right
-            + 4 // eyebrow updown right
-            + 4 // eyebrow quirk right
-            + 4 // mouthcorner updown left
-            + 4 // mouthcorner inout left
-            + 4 // mouthcorner updown right
-            + 4 // mouthcorner inout right
-            ;
-        std::thread::spawn(move || {
-            let socket = std::net::UdpSocket::bind("127.0.0.1:11573").unwrap();
-            let mut buf = [0; 65535];
-            loop {
-                socket.recv_from(&mut buf).unwrap();
-                let left_blinking = byteorder::LittleEndian::read_f32(&buf[blink_offset..]);
-                let right_blinking = byteorder::LittleEndian::read_f32(&buf[blink_offset+4..]);
-                let mouth_open = byteorder::LittleEndian::read_f32(&buf[mouth_offset..]);
-                let new = glam::Quat::from_xyzw(
-                    byteorder::LittleEndian::read_f32(&buf[quat_offset..]),
-                    -byteorder::LittleEndian::read_f32(&buf[quat_offset+4..]),
-                    -byteorder::LittleEndian::read_f32(&buf[quat_offset+8..]),
-                    byteorder::LittleEndian::read_f32(&buf[quat_offset+12..]),
-                ).mul_quat(glam::Quat::from_euler(
-                    glam::EulerRot::XYZ,
-                    std::f32::consts::PI,
-                    0.0,
-                    std::f32::consts::PI / 2.0,
-                ));
-                sts.lock().unwrap().orientation = new;
-                sts.lock().unwrap().expression_weights.insert("blink".to_owned(), 1.0 - (left_blinking + right_blinking) / 2.0);
-                sts.lock().unwrap().expression_weights.insert("oh".to_owned(), mouth_open.clamp(0.0, 1.0));
+    /// Take the latest raw pose as neutral from now on. Returns it so it can be saved, or `None` if nothing has been tracked.
+    pub fn calibrate(&mut self) -> Option<config::Calibration> {
+        self.calibration = self.raw;
+        self.raw
+    }
+
+    /// Seconds since the last sample arrived, if any has.
+    pub fn age(&self) -> Option<f32> {
+        self.received.map(|r| r.elapsed().as_secs_f32())
+    }
+
+    /// The tracked face right now: each new sample is eased in over one packet interval,
+    /// so the 60 Hz render step sees continuous motion from a slower tracker.
+    pub fn interpolated(&self) -> Sample {
+        match self.age() {
+            Some(age) => self.previous.lerp(&self.sample, (age / self.interval).min(1.0)),
+            None => self.sample.clone(),
+        }
+    }
+
+    /// Update from a decoded packet. When several faces are tracked, the lowest face id drives the avatar.
+    pub fn apply(&mut self, faces: &[openseeface::Face], config: &config::Tracking) {
+        let face = match faces.iter().min_by_key(|f| f.id) {
+            Some(f) => f,
+            None => return,
+        };
+        let mut sample = self.sample.clone();
+        let q = face.quaternion;
+        let orientation = glam::Quat::from_xyzw(q.x, -q.y, -q.z, q.w).mul_quat(glam::Quat::from_euler(
+            glam::EulerRot::XYZ,
+            std::f32::consts::PI,
+            0.0,
+            std::f32::consts::PI / 2.0,
+        ));
+        sample.orientation = match self.calibration {
+            Some(c) => c.orientation.inverse().mul_quat(orientation),
+            None => orientation,
+        };
+        if face.success {
+            let t = glam::Vec3::new(face.translation.x, -face.translation.y, -face.translation.z);
+            self.raw = Some(config::Calibration { orientation, translation: t });
+            let origin = match self.calibration {
+                Some(c) => c.translation,
+                None => *self.neutral_translation.get_or_insert(t),
+            };
+            sample.translation = t - origin;
+            let (yaw, pitch) = face.gaze();
+            sample.gaze = glam::Vec2::new(yaw, pitch);
+        }
+        let mut weights: HashMap<String, f32> = HashMap::new();
+        for m in &config.mappings {
+            if let Some(v) = face.feature(&m.feature) {
+                let w = weights.entry(m.expression.clone()).or_insert(0.0);
+                *w = w.max(m.evaluate(v));
             }
-        });
+        }
+        sample.expression_weights.extend(weights);
+        self.push(sample);
+    }
+
+    /// Update from a VMC bundle: bone rotations and, once applied, expression weights.
+    pub fn apply_vmc(&mut self, bones: HashMap<String, glam::Quat>, blends: HashMap<String, f32>) {
+        let mut sample = self.sample.clone();
+        sample.bones.extend(bones);
+        sample.expression_weights.extend(blends);
+        self.push(sample);
+    }
+
+    /// Start a receiving thread for every configured source. Live sources pass their raw packets to `recorder`.
+    pub fn run(sts: SharedTrackingState, config: config::Tracking, recorder: recording::Recorder) {
+        let mut sources: Vec<(String, Result<Box<dyn TrackingSource>, String>)> = config.sources.iter().map(|source| {
+            let bound: Result<Box<dyn TrackingSource>, String> = match source {
+                config::Source::OpenSeeFace(addr) => openseeface::Receiver::bind(addr, recorder.clone())
+                    .map(|r| Box::new(r) as _)
+                    .map_err(|e| e.to_string()),
+                config::Source::Vmc(addr) => vmc::Receiver::bind(addr, recorder.clone())
+                    .map(|r| Box::new(r) as _)
+                    .map_err(|e| e.to_string()),
+                config::Source::Playback { path, speed } => recording::Playback::open(path, *speed)
+                    .map(|r| Box::new(r) as _),
+            };
+            (format!("{:?}", source), bound)
+        }).collect();
+        if let Some(ls) = &config.lipsync {
+            sources.push((format!("{:?}", ls.input), lipsync::LipSync::open(ls).map(|r| Box::new(r) as _)));
+        }
+        for (desc, bound) in sources {
+            let mut receiver = match bound {
+                Ok(r) => r,
+                Err(e) => {
+                    log::error!("Failed to start tracking source {}: {}", desc, e);
+                    continue;
+                },
+            };
+            log::info!("Receiving {} tracking from {}", receiver.name(), desc);
+            let sts = sts.clone();
+            let config = config.clone();
+            std::thread::spawn(move || {
+                // a source that keeps failing (a closed socket, a missing device) backs off instead of spinning
+                let mut backoff = std::time::Duration::ZERO;
+                loop {
+                    match receiver.receive(&sts, &config) {
+                        Ok(()) => backoff = std::time::Duration::ZERO,
+                        Err(e) => {
+                            log::warn!("Dropping {} packet: {}", receiver.name(), e);
+                            backoff = (backoff * 2).clamp(
+                                std::time::Duration::from_millis(10),
+                                std::time::Duration::from_secs(1),
+                            );
+                            std::thread::sleep(backoff);
+                        },
+                    }
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn face() -> openseeface::Face {
+        openseeface::decode(include_bytes!("tracking/testdata/openseeface_frame.bin")).unwrap().remove(0)
+    }
+
+    #[test]
+    fn calibrated_pose_is_neutral() {
+        let config = config::Tracking::new();
+        let mut state = TrackingState::new();
+        assert_eq!(state.calibrate(), None);
+        let mut face = face();
+        state.apply(&[face.clone()], &config);
+        assert!(!state.sample.orientation.abs_diff_eq(glam::Quat::IDENTITY, 1e-3));
+        let calibration = state.calibrate().unwrap();
+        assert_eq!(state.calibration, Some(calibration));
+        state.apply(&[face.clone()], &config);
+        assert!(state.sample.orientation.abs_diff_eq(glam::Quat::IDENTITY, 1e-5));
+        assert!(state.sample.translation.abs_diff_eq(glam::Vec3::ZERO, 1e-6));
+        // moving away from the calibrated position shows up as an offset, with +Y up and +Z toward the camera
+        face.translation += glam::Vec3::new(0.5, 0.25, 1.0);
+        state.apply(&[face], &config);
+        assert!(state.sample.translation.abs_diff_eq(glam::Vec3::new(0.5, -0.25, -1.0), 1e-5));
     }
 }
@@ -0,0 +1,220 @@
+// Smoothing between the tracker and the avatar. Every channel goes through a one-euro filter
+// (Casiez et al. 2012): heavy smoothing while the value is still, little lag while it moves fast.
+
+use std::collections::HashMap;
+
+use crate::config;
+
+use super::{Sample, TrackingState};
+
+fn alpha(cutoff: f32, dt: f32) -> f32 {
+    let tau = 1.0 / (2.0 * std::f32::consts::PI * cutoff);
+    1.0 / (1.0 + tau / dt)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OneEuro {
+    min_cutoff: f32,
+    beta: f32,
+    d_cutoff: f32,
+    x: Option<f32>,
+    dx: f32,
+}
+
+impl OneEuro {
+    pub fn new(config: &config::Filter) -> Self {
+        Self {
+            min_cutoff: config.min_cutoff,
+            beta: config.beta,
+            d_cutoff: config.d_cutoff,
+            x: None,
+            dx: 0.0,
+        }
+    }
+
+    pub fn filter(&mut self, x: f32, dt: f32) -> f32 {
+        let prev = match self.x {
+            Some(p) => p,
+            None => {
+                self.x = Some(x);
+                return x;
+            },
+        };
+        let a_d = alpha(self.d_cutoff, dt);
+        self.dx += ((x - prev) / dt - self.dx) * a_d;
+        let a = alpha(self.min_cutoff + self.beta * self.dx.abs(), dt);
+        let filtered = prev + (x - prev) * a;
+        self.x = Some(filtered);
+        filtered
+    }
+}
+
+/// Per-channel filters for a whole `Sample`, plus the fade to neutral when tracking drops out
+/// and the mix with audio lip sync.
+pub struct Smoother {
+    config: config::Filter,
+    lipsync_override: bool,
+    orientation: [OneEuro; 4],
+    translation: [OneEuro; 3],
+    gaze: [OneEuro; 2],
+    expressions: HashMap<String, OneEuro>,
+    last: glam::Quat,
+    /// 0 while tracking is live, rising to 1 once the tracker has been silent past the timeout.
+    idle: f32,
+}
+
+impl Smoother {
+    pub fn new(config: &config::Tracking) -> Self {
+        let f = OneEuro::new(&config.filter);
+        Self {
+            config: config.filter.clone(),
+            lipsync_override: config.lipsync.as_ref().map_or(false, |l| l.override_camera),
+            orientation: [f; 4],
+            translation: [f; 3],
+            gaze: [f; 2],
+            expressions: HashMap::new(),
+            last: glam::Quat::IDENTITY,
+            idle: 1.0,
+        }
+    }
+
+    /// Advance by one render step and return what the avatar should show.
+    pub fn update(&mut self, st: &TrackingState, dt: f32) -> Sample {
+        let live = st.age().map_or(false, |age| age < self.config.timeout);
+        let k = 1.0 - (-self.config.idle_rate * dt).exp();
+        self.idle += (if live { 0.0 } else { 1.0 } - self.idle) * k;
+
+        let raw = st.interpolated();
+        // q and -q are the same rotation; stay on the side of the last output so components don't jump
+        let q = if raw.orientation.dot(self.last) < 0.0 { -raw.orientation } else { raw.orientation };
+        let qf: Vec<f32> = self.orientation.iter_mut().zip(q.to_array()).map(|(f, x)| f.filter(x, dt)).collect();
+        self.last = glam::Quat::from_slice(&qf).normalize();
+        let tf: Vec<f32> = self.translation.iter_mut().zip(raw.translation.to_array()).map(|(f, x)| f.filter(x, dt)).collect();
+        // gaze is filtered in radians so the same beta suits it as the other channels
+        let gf: Vec<f32> = self.gaze.iter_mut()
+            .zip(raw.gaze.to_array())
+            .map(|(f, x)| f.filter(x.to_radians(), dt).to_degrees())
+            .collect();
+        let (config, expressions) = (&self.config, &mut self.expressions);
+        let expression_weights = raw.expression_weights.iter().map(|(nm, w)| {
+            let f = expressions.entry(nm.clone()).or_insert_with(|| OneEuro::new(config));
+            (nm.clone(), f.filter(*w, dt).clamp(0.0, 1.0))
+        }).collect();
+        let filtered = Sample {
+            expression_weights,
+            orientation: self.last,
+            translation: glam::Vec3::from_slice(&tf),
+            gaze: glam::Vec2::from_slice(&gf),
+            // full-body bones are already smooth from their senders, so they are only interpolated
+            bones: raw.bones,
+        };
+        let mut out = filtered.lerp(&Sample::neutral(), self.idle);
+        // audio keeps the mouth moving even when the camera has dropped out
+        for (nm, w) in &st.lipsync {
+            let e = out.expression_weights.entry(nm.clone()).or_insert(0.0);
+            *e = if self.lipsync_override { *w } else { e.max(*w) };
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 1.0 / 60.0;
+
+    /// A tracker that has just sent `sample`, with nothing left to interpolate.
+    fn live(sample: Sample) -> TrackingState {
+        let mut st = TrackingState::new();
+        st.previous = sample.clone();
+        st.sample = sample;
+        st.received = Some(std::time::Instant::now());
+        st
+    }
+
+    fn tracked() -> Sample {
+        let mut sample = Sample::neutral();
+        sample.orientation = glam::Quat::from_rotation_y(0.5);
+        sample.translation = glam::Vec3::new(0.1, 0.2, 0.3);
+        sample.expression_weights.insert("aa".to_owned(), 0.8);
+        sample
+    }
+
+    #[test]
+    fn one_euro_converges_to_constant_input() {
+        let mut f = OneEuro::new(&config::Filter::new());
+        assert_eq!(f.filter(0.0, DT), 0.0);
+        let mut x = 0.0;
+        for _ in 0..300 {
+            let next = f.filter(1.0, DT);
+            assert!(next >= x && next <= 1.0);
+            x = next;
+        }
+        assert!((x - 1.0).abs() < 1e-3, "{}", x);
+    }
+
+    #[test]
+    fn one_euro_lags_less_when_input_moves_faster() {
+        // lag behind a ramp, in seconds of the ramp's own motion
+        let lag = |speed: f32| {
+            let mut f = OneEuro::new(&config::Filter::new());
+            let mut out = 0.0;
+            for i in 0..120 {
+                out = f.filter(speed * i as f32 * DT, DT);
+            }
+            (speed * 119.0 * DT - out) / speed
+        };
+        let (slow, fast) = (lag(0.1), lag(10.0));
+        assert!(slow > 0.0 && fast > 0.0);
+        assert!(fast < slow / 2.0, "fast {} slow {}", fast, slow);
+    }
+
+    #[test]
+    fn eases_to_neutral_after_timeout() {
+        let config = config::Tracking::new();
+        let mut smoother = Smoother::new(&config);
+        let mut st = live(tracked());
+        let mut out = Sample::neutral();
+        for _ in 0..300 {
+            out = smoother.update(&st, DT);
+        }
+        assert!(out.orientation.abs_diff_eq(glam::Quat::from_rotation_y(0.5), 1e-3));
+        assert!((out.expression_weights["aa"] - 0.8).abs() < 1e-2);
+
+        // past the timeout the pose eases out gradually rather than snapping to neutral
+        st.received = std::time::Instant::now().checked_sub(std::time::Duration::from_secs_f32(config.filter.timeout + 0.5));
+        out = smoother.update(&st, DT);
+        assert!(out.expression_weights["aa"] > 0.5);
+        for _ in 0..300 {
+            out = smoother.update(&st, DT);
+        }
+        assert!(out.orientation.abs_diff_eq(glam::Quat::IDENTITY, 1e-3));
+        assert!(out.translation.abs_diff_eq(glam::Vec3::ZERO, 1e-3));
+        assert!(out.expression_weights["aa"] < 1e-3);
+    }
+
+    #[test]
+    fn lipsync_blends_or_overrides_the_camera() {
+        let run = |override_camera: bool, camera: f32| {
+            let mut config = config::Tracking::new();
+            config.lipsync = Some(config::LipSync { override_camera, ..config::LipSync::new() });
+            let mut smoother = Smoother::new(&config);
+            let mut sample = tracked();
+            sample.expression_weights.insert("aa".to_owned(), camera);
+            let mut st = live(sample);
+            st.lipsync.insert("aa".to_owned(), 0.3);
+            let mut out = Sample::neutral();
+            for _ in 0..300 {
+                out = smoother.update(&st, DT);
+            }
+            out.expression_weights["aa"]
+        };
+        // blend takes whichever of camera and audio is larger
+        assert!((run(false, 0.8) - 0.8).abs() < 1e-2);
+        assert_eq!(run(false, 0.1), 0.3);
+        // override uses the audio even when the camera says more
+        assert_eq!(run(true, 0.8), 0.3);
+        assert_eq!(run(true, 0.1), 0.3);
+    }
+}
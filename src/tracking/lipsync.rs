@@ -0,0 +1,352 @@
+// Audio lip sync: loudness opens the mouth, and the rough shape of the spectrum picks the vowel.
+//
+// Each 20 ms frame is split into four bands around the first two formants. The band energies are
+// compared with a template per viseme; the closest templates get the most weight, scaled by loudness.
+// It is a heuristic, not phoneme recognition, but it reads well at terminal resolution.
+
+use std::io::Read;
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::config;
+
+use super::{SharedTrackingState, TrackingSource};
+
+pub const VISEMES: [&str; 5] = ["aa", "ih", "ou", "ee", "oh"];
+
+/// Band edges in Hz: low F1, high F1, low F2, high F2.
+const BANDS: [(f32, f32); 4] = [(150.0, 500.0), (500.0, 900.0), (900.0, 1500.0), (1800.0, 3000.0)];
+
+/// Relative band energies typical of each viseme, in `VISEMES` order.
+const TEMPLATES: [[f32; 4]; 5] = [
+    [0.20, 0.45, 0.30, 0.05],
+    [0.40, 0.20, 0.10, 0.30],
+    [0.70, 0.20, 0.05, 0.05],
+    [0.55, 0.05, 0.05, 0.35],
+    [0.35, 0.45, 0.15, 0.05],
+];
+
+const ATTACK: f32 = 30.0;
+const RELEASE: f32 = 12.0;
+
+/// Power of `samples` at frequency `f` (Goertzel).
+fn power(samples: &[f32], rate: f32, f: f32) -> f32 {
+    let coeff = 2.0 * (2.0 * std::f32::consts::PI * f / rate).cos();
+    let (mut s1, mut s2) = (0.0, 0.0);
+    for x in samples {
+        let s = x + coeff * s1 - s2;
+        s2 = s1;
+        s1 = s;
+    }
+    s1 * s1 + s2 * s2 - coeff * s1 * s2
+}
+
+pub struct Analyzer {
+    rate: f32,
+    frame: Vec<f32>,
+    gain: f32,
+    noise_floor: f32,
+    weights: [f32; 5],
+}
+
+impl Analyzer {
+    pub fn new(rate: u32, config: &config::LipSync) -> Self {
+        Self {
+            rate: rate as f32,
+            frame: Vec::new(),
+            gain: config.gain,
+            noise_floor: config.noise_floor,
+            weights: [0.0; 5],
+        }
+    }
+
+    fn frame_len(&self) -> usize {
+        (self.rate / 50.0) as usize
+    }
+
+    /// Feed mono samples in [-1, 1]. Returns new viseme weights each time a frame completes.
+    pub fn push(&mut self, samples: &[f32]) -> Option<[f32; 5]> {
+        let mut out = None;
+        for s in samples {
+            self.frame.push(*s);
+            if self.frame.len() == self.frame_len() {
+                out = Some(self.analyze());
+                self.frame.clear();
+            }
+        }
+        out
+    }
+
+    fn analyze(&mut self) -> [f32; 5] {
+        let n = self.frame.len();
+        let rms = (self.frame.iter().map(|x| x * x).sum::<f32>() / n as f32).sqrt();
+        let level = ((rms - self.noise_floor) * self.gain).clamp(0.0, 1.0);
+        let windowed: Vec<f32> = self.frame.iter().enumerate().map(|(i, x)| {
+            x * (0.5 - 0.5 * (2.0 * std::f32::consts::PI * i as f32 / n as f32).cos())
+        }).collect();
+        let step = self.rate / n as f32;
+        let mut bands = [0.0; 4];
+        for (b, (lo, hi)) in bands.iter_mut().zip(BANDS.iter()) {
+            let mut f = *lo;
+            while f < *hi {
+                *b += power(&windowed, self.rate, f);
+                f += step;
+            }
+        }
+        let total: f32 = bands.iter().sum();
+        let mut target = [0.0; 5];
+        if total > 0.0 && level > 0.0 {
+            let norm: f32 = bands.iter().map(|b| b * b).sum::<f32>().sqrt();
+            let scores: Vec<f32> = TEMPLATES.iter().map(|t| {
+                let tn = t.iter().map(|x| x * x).sum::<f32>().sqrt();
+                let cos = t.iter().zip(bands.iter()).map(|(a, b)| a * b).sum::<f32>() / (tn * norm);
+                cos.max(0.0).powi(8)
+            }).collect();
+            let sum: f32 = scores.iter().sum();
+            if sum > 0.0 {
+                for (t, s) in target.iter_mut().zip(scores) {
+                    *t = level * s / sum;
+                }
+            }
+        }
+        let dt = n as f32 / self.rate;
+        for (w, t) in self.weights.iter_mut().zip(target.iter()) {
+            let rate = if t > w { ATTACK } else { RELEASE };
+            *w += (t - *w) * (1.0 - (-rate * dt).exp());
+        }
+        self.weights
+    }
+}
+
+/// A parsed PCM WAV file, mixed down to mono.
+pub struct Wav {
+    pub rate: u32,
+    pub samples: Vec<f32>,
+}
+
+impl Wav {
+    pub fn open(path: &str) -> Result<Self, String> {
+        let bytes = std::fs::read(path).map_err(|e| e.to_string())?;
+        if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+            return Err(format!("{} is not a WAV file", path));
+        }
+        let mut pos = 12;
+        let mut format = None;
+        while pos + 8 <= bytes.len() {
+            let id = &bytes[pos..pos + 4];
+            let len = LittleEndian::read_u32(&bytes[pos + 4..pos + 8]) as usize;
+            let body = &bytes[pos + 8..(pos + 8 + len).min(bytes.len())];
+            if id == b"fmt " && body.len() >= 16 {
+                let tag = LittleEndian::read_u16(&body[0..2]);
+                let channels = LittleEndian::read_u16(&body[2..4]).max(1) as usize;
+                let rate = LittleEndian::read_u32(&body[4..8]);
+                let bits = LittleEndian::read_u16(&body[14..16]);
+                if tag != 1 || bits != 16 {
+                    return Err(format!("{} is not 16-bit PCM", path));
+                }
+                format = Some((channels, rate));
+            } else if id == b"data" {
+                let (channels, rate) = format.ok_or("WAV data before fmt chunk")?;
+                let samples: Vec<f32> = body.chunks_exact(2 * channels).map(|frame| {
+                    frame.chunks_exact(2).map(|s| LittleEndian::read_i16(s) as f32 / 32768.0).sum::<f32>() / channels as f32
+                }).collect();
+                if samples.is_empty() || rate < 50 {
+                    break;
+                }
+                return Ok(Self { rate, samples });
+            }
+            pos += 8 + len + (len & 1);
+        }
+        Err(format!("{} has no audio data", path))
+    }
+}
+
+enum Input {
+    Capture(std::process::Child),
+    File { wav: Wav, pos: usize, start: std::time::Instant },
+}
+
+/// Capture rate for live input.
+const RATE: u32 = 16000;
+
+pub struct LipSync {
+    input: Input,
+    analyzer: Analyzer,
+}
+
+impl LipSync {
+    /// Open "pulse" (parec), "alsa" (arecord) or a WAV file, which is played in real time in a loop.
+    pub fn open(config: &config::LipSync) -> Result<Self, String> {
+        let capture = |cmd: &str, args: &[&str]| {
+            std::process::Command::new(cmd)
+                .args(args)
+                .stdout(std::process::Stdio::piped())
+                .stderr(std::process::Stdio::null())
+                .spawn()
+                .map(Input::Capture)
+                .map_err(|e| format!("failed to start {}: {}", cmd, e))
+        };
+        let rate = RATE.to_string();
+        let (input, rate) = match config.input.as_str() {
+            "pulse" => (capture("parec", &["--format=s16le", "--channels=1", &format!("--rate={}", rate), "--raw"])?, RATE),
+            "alsa" => (capture("arecord", &["-q", "-t", "raw", "-f", "S16_LE", "-c", "1", "-r", &rate])?, RATE),
+            path => {
+                let wav = Wav::open(path)?;
+                let rate = wav.rate;
+                (Input::File { wav, pos: 0, start: std::time::Instant::now() }, rate)
+            },
+        };
+        Ok(Self {
+            input,
+            analyzer: Analyzer::new(rate, config),
+        })
+    }
+
+    /// Read the next 20 ms of audio.
+    fn read(&mut self) -> Result<Vec<f32>, String> {
+        let n = self.analyzer.frame_len();
+        match &mut self.input {
+            Input::Capture(child) => {
+                let mut buf = vec![0; n * 2];
+                if let Err(e) = child.stdout.as_mut().ok_or("capture has no output")?.read_exact(&mut buf) {
+                    // the capture is gone for good; don't spin on it
+                    std::thread::sleep(std::time::Duration::from_secs(1));
+                    return Err(format!("audio capture ended: {}", e));
+                }
+                Ok(buf.chunks_exact(2).map(|s| LittleEndian::read_i16(s) as f32 / 32768.0).collect())
+            },
+            Input::File { wav, pos, start } => {
+                if *pos >= wav.samples.len() {
+                    *pos = 0;
+                    *start = std::time::Instant::now();
+                }
+                let end = (*pos + n).min(wav.samples.len());
+                let chunk = wav.samples[*pos..end].to_vec();
+                *pos = end;
+                let due = std::time::Duration::from_secs_f32(*pos as f32 / wav.rate as f32);
+                if let Some(wait) = due.checked_sub(start.elapsed()) {
+                    std::thread::sleep(wait);
+                }
+                Ok(chunk)
+            },
+        }
+    }
+}
+
+impl Drop for LipSync {
+    fn drop(&mut self) {
+        if let Input::Capture(child) = &mut self.input {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+impl TrackingSource for LipSync {
+    fn name(&self) -> &str {
+        "lip sync"
+    }
+
+    fn receive(&mut self, sts: &SharedTrackingState, _config: &config::Tracking) -> Result<(), String> {
+        let samples = self.read()?;
+        if let Some(weights) = self.analyzer.push(&samples) {
+            let mut st = sts.lock().unwrap();
+            for (nm, w) in VISEMES.iter().zip(weights.iter()) {
+                st.lipsync.insert((*nm).to_owned(), *w);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 16000;
+
+    /// A WAV file with the given format fields, and its `data` chunk after or before `fmt `.
+    fn wav(tag: u16, channels: u16, bits: u16, samples: &[i16], data_first: bool) -> Vec<u8> {
+        let mut fmt = b"fmt ".to_vec();
+        fmt.extend_from_slice(&16u32.to_le_bytes());
+        fmt.extend_from_slice(&tag.to_le_bytes());
+        fmt.extend_from_slice(&channels.to_le_bytes());
+        fmt.extend_from_slice(&RATE.to_le_bytes());
+        fmt.extend_from_slice(&(RATE * channels as u32 * bits as u32 / 8).to_le_bytes());
+        fmt.extend_from_slice(&(channels * bits / 8).to_le_bytes());
+        fmt.extend_from_slice(&bits.to_le_bytes());
+        let mut data = b"data".to_vec();
+        data.extend_from_slice(&(samples.len() as u32 * 2).to_le_bytes());
+        for s in samples {
+            data.extend_from_slice(&s.to_le_bytes());
+        }
+        let chunks = if data_first { [data, fmt].concat() } else { [fmt, data].concat() };
+        let mut out = b"RIFF".to_vec();
+        out.extend_from_slice(&(chunks.len() as u32 + 4).to_le_bytes());
+        out.extend_from_slice(b"WAVE");
+        out.extend_from_slice(&chunks);
+        out
+    }
+
+    fn open(name: &str, bytes: &[u8]) -> Result<Wav, String> {
+        let path = std::env::temp_dir().join(format!("colonq-lipsync-{}-{}.wav", name, std::process::id()));
+        std::fs::write(&path, bytes).unwrap();
+        let wav = Wav::open(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+        wav
+    }
+
+    fn tone(freqs: &[f32], amplitude: f32, n: usize) -> Vec<f32> {
+        (0..n).map(|i| {
+            let t = i as f32 / RATE as f32;
+            freqs.iter().map(|f| (2.0 * std::f32::consts::PI * f * t).sin()).sum::<f32>() * amplitude / freqs.len() as f32
+        }).collect()
+    }
+
+    #[test]
+    fn reads_mono_and_stereo_pcm() {
+        let mono = open("mono", &wav(1, 1, 16, &[0, 16384, -16384, 32767], false)).unwrap();
+        assert_eq!(mono.rate, RATE);
+        assert_eq!(mono.samples, vec![0.0, 0.5, -0.5, 32767.0 / 32768.0]);
+        // stereo frames are averaged into one sample each
+        let stereo = open("stereo", &wav(1, 2, 16, &[16384, 0, -16384, -16384, 8192, -8192], false)).unwrap();
+        assert_eq!(stereo.rate, RATE);
+        assert_eq!(stereo.samples, vec![0.25, -0.5, 0.0]);
+    }
+
+    #[test]
+    fn rejects_unsupported_wavs() {
+        assert!(open("float", &wav(3, 1, 16, &[0, 1], false)).is_err());
+        assert!(open("8bit", &wav(1, 1, 8, &[0, 1], false)).is_err());
+        assert!(open("order", &wav(1, 1, 16, &[0, 1], true)).is_err());
+        assert!(open("empty", &wav(1, 1, 16, &[], false)).is_err());
+        assert!(open("riff", b"RIFF\0\0\0\0AVI ").is_err());
+    }
+
+    #[test]
+    fn weights_arrive_once_per_frame() {
+        let mut analyzer = Analyzer::new(RATE, &config::LipSync::new());
+        let frame = RATE as usize / 50;
+        assert_eq!(analyzer.push(&vec![0.0; frame - 1]), None);
+        assert_eq!(analyzer.push(&[0.0]), Some([0.0; 5]));
+        assert_eq!(analyzer.push(&vec![0.0; frame / 2]), None);
+        // quiet hiss below the noise floor still reads as silence
+        let hiss: Vec<f32> = (0..frame).map(|i| if i % 2 == 0 { 0.005 } else { -0.005 }).collect();
+        let weights = analyzer.push(&hiss).unwrap();
+        assert!(weights.iter().all(|w| *w < 1e-3), "{:?}", weights);
+    }
+
+    #[test]
+    fn formants_pick_the_viseme() {
+        let dominant = |freqs: &[f32]| {
+            let mut analyzer = Analyzer::new(RATE, &config::LipSync::new());
+            let weights = analyzer.push(&tone(freqs, 0.5, RATE as usize / 5)).unwrap();
+            let best = (0..5).max_by(|a, b| weights[*a].partial_cmp(&weights[*b]).unwrap()).unwrap();
+            assert!(weights[best] > 0.3, "{:?}", weights);
+            VISEMES[best]
+        };
+        // energy only below 500 Hz is a rounded "ou"; a strong second band above it is an open "aa"
+        assert_eq!(dominant(&[300.0]), "ou");
+        assert_eq!(dominant(&[300.0, 700.0, 700.0, 1200.0]), "aa");
+    }
+}
@@ -0,0 +1,287 @@
+// Decoder for the binary UDP packets sent by OpenSeeFace's facetracker.py.
+// A packet is one or more fixed-size frames, one per tracked face, all little-endian.
+
+use std::io::Cursor;
+
+use byteorder::{LittleEndian, ReadBytesExt};
+
+use crate::config;
+
+use super::{recording, SharedTrackingState, TrackingSource};
+
+pub const POINTS: usize = 68;
+
+pub const FRAME_SIZE: usize
+    = 8 // timestamp
+    + 4 // face id
+    + 2 * 4 // width, height
+    + 2 * 4 // right, left eye open
+    + 1 // success
+    + 4 // pnp error
+    + 4 * 4 // quaternion
+    + 3 * 4 // euler
+    + 3 * 4 // translation
+    + 4 * POINTS // landmark confidences
+    + 2 * 4 * POINTS // landmarks
+    + 3 * 4 * (POINTS + 2) // 3d points, plus both pupils
+    + 4 * 14 // features
+    ;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Features {
+    pub eye_left: f32,
+    pub eye_right: f32,
+    pub eyebrow_steepness_left: f32,
+    pub eyebrow_updown_left: f32,
+    pub eyebrow_quirk_left: f32,
+    pub eyebrow_steepness_right: f32,
+    pub eyebrow_updown_right: f32,
+    pub eyebrow_quirk_right: f32,
+    pub mouth_corner_updown_left: f32,
+    pub mouth_corner_inout_left: f32,
+    pub mouth_corner_updown_right: f32,
+    pub mouth_corner_inout_right: f32,
+    pub mouth_open: f32,
+    pub mouth_wide: f32,
+}
+
+/// One tracked face. Rotation and translation are exactly as sent, in OpenCV camera space.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Face {
+    pub time: f64,
+    pub id: i32,
+    pub resolution: glam::Vec2,
+    pub right_eye_open: f32,
+    pub left_eye_open: f32,
+    pub success: bool,
+    pub fit_error: f32,
+    pub quaternion: glam::Quat,
+    pub euler: glam::Vec3,
+    pub translation: glam::Vec3,
+    pub confidence: [f32; POINTS],
+    pub points: [glam::Vec2; POINTS],
+    pub points_3d: [glam::Vec3; POINTS + 2],
+    pub features: Features,
+}
+
+fn read_vec2(r: &mut Cursor<&[u8]>) -> glam::Vec2 {
+    let x = r.read_f32::<LittleEndian>().unwrap();
+    let y = r.read_f32::<LittleEndian>().unwrap();
+    glam::Vec2::new(x, y)
+}
+
+fn read_vec3(r: &mut Cursor<&[u8]>) -> glam::Vec3 {
+    let x = r.read_f32::<LittleEndian>().unwrap();
+    let y = r.read_f32::<LittleEndian>().unwrap();
+    let z = r.read_f32::<LittleEndian>().unwrap();
+    glam::Vec3::new(x, y, z)
+}
+
+impl Face {
+    /// Look up a scalar tracker value by its config name, e.g. "mouth-open" or "eyebrow-updown-left".
+    pub fn feature(&self, nm: &str) -> Option<f32> {
+        let f = &self.features;
+        match nm {
+            "eye-open" => Some((self.left_eye_open + self.right_eye_open) / 2.0),
+            "eye-open-left" => Some(self.left_eye_open),
+            "eye-open-right" => Some(self.right_eye_open),
+            "eye-left" => Some(f.eye_left),
+            "eye-right" => Some(f.eye_right),
+            "eyebrow-steepness-left" => Some(f.eyebrow_steepness_left),
+            "eyebrow-updown-left" => Some(f.eyebrow_updown_left),
+            "eyebrow-quirk-left" => Some(f.eyebrow_quirk_left),
+            "eyebrow-steepness-right" => Some(f.eyebrow_steepness_right),
+            "eyebrow-updown-right" => Some(f.eyebrow_updown_right),
+            "eyebrow-quirk-right" => Some(f.eyebrow_quirk_right),
+            "mouth-corner-updown-left" => Some(f.mouth_corner_updown_left),
+            "mouth-corner-inout-left" => Some(f.mouth_corner_inout_left),
+            "mouth-corner-updown-right" => Some(f.mouth_corner_updown_right),
+            "mouth-corner-inout-right" => Some(f.mouth_corner_inout_right),
+            "mouth-open" => Some(f.mouth_open),
+            "mouth-wide" => Some(f.mouth_wide),
+            _ => None,
+        }
+    }
+
+    /// Gaze direction relative to the head as (yaw, pitch) in degrees: positive yaw looks to the
+    /// face's left and positive pitch looks up. Built from the 3d points alone (points 66 and 67 are
+    /// the pupils, 68 and 69 the eyeball centers), so it does not depend on the rotation convention.
+    pub fn gaze(&self) -> (f32, f32) {
+        let p = &self.points_3d;
+        let right = (p[68] - p[69]).normalize_or_zero();
+        let forward = (p[30] - (p[68] + p[69]) / 2.0).reject_from_normalized(right).normalize_or_zero();
+        let up = right.cross(forward);
+        let dir = ((p[66] - p[68]).normalize_or_zero() + (p[67] - p[69]).normalize_or_zero()).normalize_or_zero();
+        let front = dir.dot(forward);
+        (
+            (-dir.dot(right)).atan2(front).to_degrees(),
+            dir.dot(up).atan2(front).to_degrees(),
+        )
+    }
+
+    /// Decode a single frame. The caller has already checked that it is `FRAME_SIZE` bytes long.
+    fn decode(frame: &[u8]) -> Self {
+        let mut r = Cursor::new(frame);
+        let f = |r: &mut Cursor<&[u8]>| r.read_f32::<LittleEndian>().unwrap();
+        let time = r.read_f64::<LittleEndian>().unwrap();
+        let id = r.read_i32::<LittleEndian>().unwrap();
+        let resolution = read_vec2(&mut r);
+        let right_eye_open = f(&mut r);
+        let left_eye_open = f(&mut r);
+        let success = r.read_u8().unwrap() != 0;
+        let fit_error = f(&mut r);
+        let quaternion = glam::Quat::from_xyzw(f(&mut r), f(&mut r), f(&mut r), f(&mut r));
+        let euler = read_vec3(&mut r);
+        let translation = read_vec3(&mut r);
+        let mut confidence = [0.0; POINTS];
+        for c in confidence.iter_mut() {
+            *c = f(&mut r);
+        }
+        let mut points = [glam::Vec2::ZERO; POINTS];
+        for p in points.iter_mut() {
+            *p = read_vec2(&mut r);
+        }
+        let mut points_3d = [glam::Vec3::ZERO; POINTS + 2];
+        for p in points_3d.iter_mut() {
+            *p = read_vec3(&mut r);
+        }
+        let features = Features {
+            eye_left: f(&mut r),
+            eye_right: f(&mut r),
+            eyebrow_steepness_left: f(&mut r),
+            eyebrow_updown_left: f(&mut r),
+            eyebrow_quirk_left: f(&mut r),
+            eyebrow_steepness_right: f(&mut r),
+            eyebrow_updown_right: f(&mut r),
+            eyebrow_quirk_right: f(&mut r),
+            mouth_corner_updown_left: f(&mut r),
+            mouth_corner_inout_left: f(&mut r),
+            mouth_corner_updown_right: f(&mut r),
+            mouth_corner_inout_right: f(&mut r),
+            mouth_open: f(&mut r),
+            mouth_wide: f(&mut r),
+        };
+        Self {
+            time,
+            id,
+            resolution,
+            right_eye_open,
+            left_eye_open,
+            success,
+            fit_error,
+            quaternion,
+            euler,
+            translation,
+            confidence,
+            points,
+            points_3d,
+            features,
+        }
+    }
+}
+
+/// Decode every face in a packet, rejecting packets that are not a whole number of frames.
+pub fn decode(packet: &[u8]) -> Result<Vec<Face>, String> {
+    if packet.is_empty() || packet.len() % FRAME_SIZE != 0 {
+        return Err(format!("packet length {} is not a multiple of {}", packet.len(), FRAME_SIZE));
+    }
+    Ok(packet.chunks_exact(FRAME_SIZE).map(Face::decode).collect())
+}
+
+/// Decode a packet and apply it to the tracking state.
+pub fn handle(packet: &[u8], sts: &SharedTrackingState, config: &config::Tracking) -> Result<(), String> {
+    let faces = decode(packet)?;
+    sts.lock().unwrap().apply(&faces, config);
+    Ok(())
+}
+
+pub struct Receiver {
+    socket: std::net::UdpSocket,
+    buf: Vec<u8>,
+    recorder: recording::Recorder,
+}
+
+impl Receiver {
+    pub fn bind(addr: &str, recorder: recording::Recorder) -> std::io::Result<Self> {
+        Ok(Self {
+            socket: std::net::UdpSocket::bind(addr)?,
+            buf: vec![0; 65535],
+            recorder,
+        })
+    }
+}
+
+impl TrackingSource for Receiver {
+    fn name(&self) -> &str {
+        "OpenSeeFace"
+    }
+
+    fn receive(&mut self, sts: &SharedTrackingState, config: &config::Tracking) -> Result<(), String> {
+        let (len, _) = self.socket.recv_from(&mut self.buf).map_err(|e| e.to_string())?;
+        self.recorder.record(recording::Kind::OpenSeeFace, &self.buf[..len]);
+        handle(&self.buf[..len], sts, config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Frame 68 of deps/OpenSeeFace/data.txt (a facetracker.py log), packed the way facetracker.py sends it.
+    const FRAME: &[u8] = include_bytes!("testdata/openseeface_frame.bin");
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-5
+    }
+
+    #[test]
+    fn decodes_recorded_frame() {
+        assert_eq!(FRAME.len(), FRAME_SIZE);
+        let faces = decode(FRAME).unwrap();
+        assert_eq!(faces.len(), 1);
+        let face = &faces[0];
+        assert_eq!(face.time, 1674277018.8320255);
+        assert_eq!(face.id, 0);
+        assert_eq!(face.resolution, glam::Vec2::new(640.0, 360.0));
+        assert!(close(face.right_eye_open, 0.743449528245919));
+        assert!(close(face.left_eye_open, 0.9474614299010795));
+        assert!(face.success);
+        assert!(close(face.fit_error, 8.628542413904238));
+        let q = face.quaternion;
+        assert!(close(q.x, 0.573398232460022) && close(q.y, 0.679618239402771));
+        assert!(close(q.z, 0.39886045455932617) && close(q.w, 0.22415128350257874));
+        assert!(close(face.euler.x, 153.87633762650077));
+        assert!(close(face.euler.y, -49.648475968647794));
+        assert!(close(face.euler.z, 111.94206462461504));
+        assert!(close(face.translation.z, -5.529661655426025));
+        assert!(close(face.confidence[0], 0.5511701107025146));
+        assert!(close(face.points[0].x, 266.9062502562566) && close(face.points[0].y, 167.01198012488229));
+        let p = face.points_3d[66];
+        assert!(close(p.x, 0.19395872950553894) && close(p.y, -0.3252256512641907) && close(p.z, 0.19859635829925537));
+        assert!(close(face.features.eye_left, -0.052538570098920524));
+        assert!(close(face.features.eye_right, -0.25655047175408097));
+        assert!(close(face.features.mouth_open, -0.3605625789273001));
+        assert!(close(face.features.mouth_wide, 0.30509904096849877));
+        assert_eq!(face.feature("eye-open-left"), Some(face.left_eye_open));
+    }
+
+    #[test]
+    fn decodes_two_faces() {
+        let mut packet = FRAME.to_vec();
+        packet.extend_from_slice(FRAME);
+        packet[FRAME_SIZE + 8..FRAME_SIZE + 12].copy_from_slice(&1i32.to_le_bytes());
+        let faces = decode(&packet).unwrap();
+        assert_eq!(faces.iter().map(|f| f.id).collect::<Vec<_>>(), vec![0, 1]);
+        assert_eq!(faces[0].points_3d, faces[1].points_3d);
+    }
+
+    #[test]
+    fn rejects_partial_packets() {
+        assert!(decode(&[]).is_err());
+        assert!(decode(&FRAME[..FRAME_SIZE - 1]).is_err());
+        assert!(decode(&FRAME[..100]).is_err());
+        let mut long = FRAME.to_vec();
+        long.extend_from_slice(&FRAME[..10]);
+        assert!(decode(&long).is_err());
+    }
+}
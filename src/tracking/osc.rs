@@ -0,0 +1,241 @@
+// Minimal Open Sound Control 1.0 codec, enough for the VMC protocol.
+// Everything is big-endian and padded to four bytes; bundles are flattened into their messages.
+
+use byteorder::{BigEndian, ByteOrder};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Arg {
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    Str(String),
+    Blob(Vec<u8>),
+    Bool(bool),
+    Nil,
+}
+
+impl Arg {
+    pub fn as_f32(&self) -> Option<f32> {
+        match self {
+            Arg::Float(f) => Some(*f),
+            Arg::Double(d) => Some(*d as f32),
+            Arg::Int(i) => Some(*i as f32),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Arg::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub addr: String,
+    pub args: Vec<Arg>,
+}
+
+fn pad(n: usize) -> usize {
+    (n + 3) & !3
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.buf.len() - self.pos < n {
+            return Err("truncated OSC packet".to_owned());
+        }
+        let s = &self.buf[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(s)
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let rest = &self.buf[self.pos..];
+        let len = rest.iter().position(|b| *b == 0).ok_or("unterminated OSC string")?;
+        let s = String::from_utf8(rest[..len].to_vec()).map_err(|e| e.to_string())?;
+        self.take(pad(len + 1))?;
+        Ok(s)
+    }
+}
+
+fn decode_message(buf: &[u8]) -> Result<Message, String> {
+    let mut r = Reader { buf, pos: 0 };
+    let addr = r.string()?;
+    let mut args = Vec::new();
+    if r.pos == buf.len() {
+        return Ok(Message { addr, args });
+    }
+    let tags = r.string()?;
+    for t in tags.strip_prefix(',').ok_or("missing OSC type tags")?.chars() {
+        args.push(match t {
+            'i' => Arg::Int(BigEndian::read_i32(r.take(4)?)),
+            'h' => Arg::Long(BigEndian::read_i64(r.take(8)?)),
+            'f' => Arg::Float(BigEndian::read_f32(r.take(4)?)),
+            'd' => Arg::Double(BigEndian::read_f64(r.take(8)?)),
+            's' | 'S' => Arg::Str(r.string()?),
+            'b' => {
+                let len = BigEndian::read_i32(r.take(4)?).max(0) as usize;
+                let blob = r.take(len)?.to_vec();
+                r.take(pad(len) - len)?;
+                Arg::Blob(blob)
+            },
+            'T' => Arg::Bool(true),
+            'F' => Arg::Bool(false),
+            'N' | 'I' => Arg::Nil,
+            other => return Err(format!("unsupported OSC type tag {}", other)),
+        });
+    }
+    Ok(Message { addr, args })
+}
+
+/// Decode a packet into its messages, in order, descending into nested bundles.
+pub fn decode(packet: &[u8]) -> Result<Vec<Message>, String> {
+    let mut out = Vec::new();
+    decode_into(packet, &mut out)?;
+    Ok(out)
+}
+
+fn decode_into(packet: &[u8], out: &mut Vec<Message>) -> Result<(), String> {
+    if packet.starts_with(b"#bundle\0") {
+        if packet.len() < 16 {
+            return Err("truncated OSC bundle".to_owned());
+        }
+        let mut r = Reader { buf: packet, pos: 16 }; // tag and time tag
+        while r.pos < packet.len() {
+            let len = BigEndian::read_i32(r.take(4)?).max(0) as usize;
+            decode_into(r.take(len)?, out)?;
+        }
+        Ok(())
+    } else {
+        out.push(decode_message(packet)?);
+        Ok(())
+    }
+}
+
+fn encode_string(out: &mut Vec<u8>, s: &str) {
+    out.extend_from_slice(s.as_bytes());
+    out.resize(out.len() + pad(s.len() + 1) - s.len(), 0);
+}
+
+pub fn encode(m: &Message) -> Vec<u8> {
+    let mut out = Vec::new();
+    encode_string(&mut out, &m.addr);
+    let tags: String = std::iter::once(',').chain(m.args.iter().map(|a| match a {
+        Arg::Int(_) => 'i',
+        Arg::Long(_) => 'h',
+        Arg::Float(_) => 'f',
+        Arg::Double(_) => 'd',
+        Arg::Str(_) => 's',
+        Arg::Blob(_) => 'b',
+        Arg::Bool(true) => 'T',
+        Arg::Bool(false) => 'F',
+        Arg::Nil => 'N',
+    })).collect();
+    encode_string(&mut out, &tags);
+    for a in &m.args {
+        let mut b = [0; 8];
+        match a {
+            Arg::Int(i) => { BigEndian::write_i32(&mut b, *i); out.extend_from_slice(&b[..4]) },
+            Arg::Long(l) => { BigEndian::write_i64(&mut b, *l); out.extend_from_slice(&b) },
+            Arg::Float(f) => { BigEndian::write_f32(&mut b, *f); out.extend_from_slice(&b[..4]) },
+            Arg::Double(d) => { BigEndian::write_f64(&mut b, *d); out.extend_from_slice(&b) },
+            Arg::Str(s) => encode_string(&mut out, s),
+            Arg::Blob(blob) => {
+                BigEndian::write_i32(&mut b, blob.len() as i32);
+                out.extend_from_slice(&b[..4]);
+                out.extend_from_slice(blob);
+                out.resize(out.len() + pad(blob.len()) - blob.len(), 0);
+            },
+            Arg::Bool(_) | Arg::Nil => {},
+        }
+    }
+    out
+}
+
+/// Encode messages as one bundle to be handled immediately.
+pub fn encode_bundle(ms: &[Message]) -> Vec<u8> {
+    let mut out = b"#bundle\0".to_vec();
+    out.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1]);
+    for m in ms {
+        let enc = encode(m);
+        let mut len = [0; 4];
+        BigEndian::write_i32(&mut len, enc.len() as i32);
+        out.extend_from_slice(&len);
+        out.extend_from_slice(&enc);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message() -> Message {
+        Message {
+            addr: "/VMC/Ext/Blend/Val".to_owned(),
+            args: vec![Arg::Str("Joy".to_owned()), Arg::Float(0.25), Arg::Int(-3)],
+        }
+    }
+
+    #[test]
+    fn round_trips_a_padded_message() {
+        let enc = encode(&message());
+        assert_eq!(enc.len() % 4, 0);
+        // strings are nul-terminated and padded to four bytes, so a 4-byte string takes eight
+        assert_eq!(&enc[..20], b"/VMC/Ext/Blend/Val\0\0");
+        assert_eq!(&enc[20..28], b",sfi\0\0\0\0");
+        assert_eq!(&enc[28..32], b"Joy\0");
+        assert_eq!(&enc[32..36], &0.25f32.to_be_bytes());
+        assert_eq!(&enc[36..40], &(-3i32).to_be_bytes());
+        assert_eq!(enc.len(), 40);
+        assert_eq!(decode(&enc).unwrap(), vec![message()]);
+    }
+
+    #[test]
+    fn decodes_nested_bundles_in_order() {
+        let first = Message { addr: "/a".to_owned(), args: vec![Arg::Int(1)] };
+        let inner = encode_bundle(&[message(), first.clone()]);
+        let last = encode(&Message { addr: "/b".to_owned(), args: vec![] });
+        let mut outer = b"#bundle\0".to_vec();
+        outer.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1]);
+        for part in [&inner, &last] {
+            outer.extend_from_slice(&(part.len() as i32).to_be_bytes());
+            outer.extend_from_slice(part);
+        }
+        let ms = decode(&outer).unwrap();
+        assert_eq!(ms.iter().map(|m| m.addr.as_str()).collect::<Vec<_>>(), vec!["/VMC/Ext/Blend/Val", "/a", "/b"]);
+        assert_eq!(ms[0], message());
+        assert_eq!(ms[1], first);
+    }
+
+    #[test]
+    fn truncated_packets_are_errors() {
+        let enc = encode(&message());
+        // anything cut after the type tags is missing arguments
+        for n in 28..enc.len() {
+            assert!(decode(&enc[..n]).is_err(), "{} bytes decoded", n);
+        }
+        // shorter prefixes must not panic, whatever they decode to
+        for n in 0..28 {
+            let _ = decode(&enc[..n]);
+        }
+        let bundle = encode_bundle(&[message()]);
+        // a bare bundle header is an empty bundle
+        assert_eq!(decode(&bundle[..16]), Ok(vec![]));
+        for n in (8..16).chain(17..bundle.len()) {
+            assert!(decode(&bundle[..n]).is_err(), "{} bytes of bundle decoded", n);
+        }
+        let mut lying = bundle.clone();
+        lying[16..20].copy_from_slice(&1000i32.to_be_bytes());
+        assert!(decode(&lying).is_err());
+    }
+}
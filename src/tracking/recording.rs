@@ -0,0 +1,294 @@
+// Raw tracking packets saved with their arrival times, so a session can be replayed without a camera.
+//
+// File layout: the magic "COLONQTR", then per packet a kind byte, an f64 of seconds since the
+// recording started, a u32 length and the packet bytes, all little-endian.
+
+use std::io::{Read, Write};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::config;
+
+use super::{openseeface, vmc, SharedTrackingState, TrackingSource};
+
+const MAGIC: &[u8; 8] = b"COLONQTR";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    OpenSeeFace,
+    Vmc,
+}
+
+impl Kind {
+    fn to_byte(self) -> u8 {
+        match self {
+            Kind::OpenSeeFace => 0,
+            Kind::Vmc => 1,
+        }
+    }
+
+    fn from_byte(b: u8) -> Option<Self> {
+        match b {
+            0 => Some(Kind::OpenSeeFace),
+            1 => Some(Kind::Vmc),
+            _ => None,
+        }
+    }
+}
+
+pub struct Packet {
+    pub kind: Kind,
+    pub time: f64,
+    pub data: Vec<u8>,
+}
+
+/// Writes packets in the recording format, stamped relative to when the recording started.
+struct Writer<W: Write> {
+    out: W,
+    start: std::time::Instant,
+}
+
+impl<W: Write> Writer<W> {
+    fn new(mut out: W, start: std::time::Instant) -> std::io::Result<Self> {
+        out.write_all(MAGIC)?;
+        Ok(Self { out, start })
+    }
+
+    fn write(&mut self, kind: Kind, received: std::time::Instant, data: &[u8]) -> std::io::Result<()> {
+        self.out.write_u8(kind.to_byte())?;
+        self.out.write_f64::<LittleEndian>(received.saturating_duration_since(self.start).as_secs_f64())?;
+        self.out.write_u32::<LittleEndian>(data.len() as u32)?;
+        self.out.write_all(data)
+    }
+}
+
+fn finish<W: Write>(writer: &mut Option<Writer<W>>) {
+    if let Some(mut w) = writer.take() {
+        if let Err(e) = w.out.flush() {
+            log::error!("Failed to finish tracking recording: {}", e);
+        }
+    }
+}
+
+enum Command {
+    Start(String),
+    Stop,
+    Packet(Kind, std::time::Instant, Vec<u8>),
+}
+
+/// Handle to the thread that saves raw packets from live sources. Receivers hold a clone each and
+/// hand packets over without blocking on disk; the file is flushed about once a second and on stop,
+/// so a recording cut short by a crash loses at most the last second.
+#[derive(Clone)]
+pub struct Recorder {
+    tx: std::sync::mpsc::Sender<Command>,
+    active: std::sync::Arc<std::sync::atomic::AtomicBool>,
+}
+
+impl Recorder {
+    const FLUSH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+    pub fn spawn() -> Self {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let active = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let flag = active.clone();
+        std::thread::spawn(move || {
+            let mut writer: Option<Writer<std::io::BufWriter<std::fs::File>>> = None;
+            let mut last_flush = std::time::Instant::now();
+            loop {
+                let result = match rx.recv_timeout(Self::FLUSH_INTERVAL) {
+                    Ok(Command::Start(path)) => {
+                        finish(&mut writer);
+                        let created = std::fs::File::create(&path)
+                            .and_then(|f| Writer::new(std::io::BufWriter::new(f), std::time::Instant::now()));
+                        match created {
+                            Ok(w) => {
+                                log::info!("Recording tracking to {}", path);
+                                writer = Some(w);
+                            },
+                            Err(e) => log::error!("Failed to record tracking to {}: {}", path, e),
+                        }
+                        flag.store(writer.is_some(), std::sync::atomic::Ordering::Relaxed);
+                        Ok(())
+                    },
+                    Ok(Command::Stop) => {
+                        flag.store(false, std::sync::atomic::Ordering::Relaxed);
+                        finish(&mut writer);
+                        Ok(())
+                    },
+                    Ok(Command::Packet(kind, received, data)) => match &mut writer {
+                        Some(w) => w.write(kind, received, &data),
+                        None => Ok(()),
+                    },
+                    Err(std::sync::mpsc::RecvTimeoutError::Timeout) => Ok(()),
+                    Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => {
+                        finish(&mut writer);
+                        break;
+                    },
+                };
+                let result = match &mut writer {
+                    Some(w) if result.is_ok() && last_flush.elapsed() >= Self::FLUSH_INTERVAL => {
+                        last_flush = std::time::Instant::now();
+                        w.out.flush()
+                    },
+                    _ => result,
+                };
+                if let Err(e) = result {
+                    log::error!("Stopped tracking recording: {}", e);
+                    flag.store(false, std::sync::atomic::Ordering::Relaxed);
+                    writer = None;
+                }
+            }
+        });
+        Self { tx, active }
+    }
+
+    /// Start recording raw packets to a file, or stop with `None`.
+    pub fn set_path(&self, path: Option<&str>) {
+        let command = match path {
+            Some(p) => Command::Start(p.to_owned()),
+            None => Command::Stop,
+        };
+        let _ = self.tx.send(command);
+    }
+
+    /// Queue a packet that just arrived. Does nothing unless a recording is running.
+    pub fn record(&self, kind: Kind, data: &[u8]) {
+        if self.active.load(std::sync::atomic::Ordering::Relaxed) {
+            let _ = self.tx.send(Command::Packet(kind, std::time::Instant::now(), data.to_vec()));
+        }
+    }
+}
+
+pub fn read(path: &str) -> Result<Vec<Packet>, String> {
+    let r = std::io::BufReader::new(std::fs::File::open(path).map_err(|e| e.to_string())?);
+    read_from(r).map_err(|e| format!("{}: {}", path, e))
+}
+
+fn read_from<R: Read>(mut r: R) -> Result<Vec<Packet>, String> {
+    let mut magic = [0; 8];
+    r.read_exact(&mut magic).map_err(|e| e.to_string())?;
+    if &magic != MAGIC {
+        return Err("not a tracking recording".to_owned());
+    }
+    let mut packets = Vec::new();
+    loop {
+        let kind = match r.read_u8() {
+            Ok(b) => Kind::from_byte(b).ok_or_else(|| format!("unknown packet kind {}", b))?,
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.to_string()),
+        };
+        let time = r.read_f64::<LittleEndian>().map_err(|e| e.to_string())?;
+        let len = r.read_u32::<LittleEndian>().map_err(|e| e.to_string())? as usize;
+        let mut data = vec![0; len];
+        r.read_exact(&mut data).map_err(|e| e.to_string())?;
+        packets.push(Packet { kind, time, data });
+    }
+    Ok(packets)
+}
+
+/// Replays a recording in a loop, `speed` times faster than it was captured.
+pub struct Playback {
+    path: String,
+    packets: Vec<Packet>,
+    speed: f64,
+    next: usize,
+    start: std::time::Instant,
+    vmc: vmc::Decoder,
+}
+
+impl Playback {
+    pub fn open(path: &str, speed: f32) -> Result<Self, String> {
+        let packets = read(path)?;
+        if packets.is_empty() {
+            return Err(format!("{} has no packets", path));
+        }
+        Ok(Self {
+            path: path.to_owned(),
+            packets,
+            speed: (speed as f64).max(0.001),
+            next: 0,
+            start: std::time::Instant::now(),
+            vmc: vmc::Decoder::new(),
+        })
+    }
+}
+
+impl TrackingSource for Playback {
+    fn name(&self) -> &str {
+        "playback"
+    }
+
+    fn receive(&mut self, sts: &SharedTrackingState, config: &config::Tracking) -> Result<(), String> {
+        if self.next == self.packets.len() {
+            log::info!("Restarting tracking playback of {}", self.path);
+            self.next = 0;
+            self.start = std::time::Instant::now();
+        }
+        let p = &self.packets[self.next];
+        self.next += 1;
+        let due = std::time::Duration::from_secs_f64(p.time / self.speed);
+        if let Some(wait) = due.checked_sub(self.start.elapsed()) {
+            std::thread::sleep(wait);
+        }
+        match p.kind {
+            Kind::OpenSeeFace => openseeface::handle(&p.data, sts, config),
+            Kind::Vmc => self.vmc.handle(&p.data, sts),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_packets() {
+        let start = std::time::Instant::now();
+        let mut w = Writer::new(Vec::new(), start).unwrap();
+        w.write(Kind::OpenSeeFace, start + std::time::Duration::from_millis(250), &[1, 2, 3]).unwrap();
+        w.write(Kind::Vmc, start + std::time::Duration::from_millis(500), b"#bundle\0").unwrap();
+        w.write(Kind::Vmc, start + std::time::Duration::from_secs(1), &[]).unwrap();
+        let packets = read_from(&w.out[..]).unwrap();
+        assert_eq!(packets.len(), 3);
+        assert_eq!(packets[0].kind, Kind::OpenSeeFace);
+        assert_eq!(packets[0].time, 0.25);
+        assert_eq!(packets[0].data, vec![1, 2, 3]);
+        assert_eq!(packets[1].kind, Kind::Vmc);
+        assert_eq!(packets[1].time, 0.5);
+        assert_eq!(packets[1].data, b"#bundle\0".to_vec());
+        assert_eq!(packets[2].time, 1.0);
+        assert!(packets[2].data.is_empty());
+    }
+
+    #[test]
+    fn records_to_file_for_playback() {
+        let path = std::env::temp_dir().join(format!("colonq-recording-{}.bin", std::process::id()));
+        let path = path.to_str().unwrap();
+        let recorder = Recorder::spawn();
+        recorder.set_path(Some(path));
+        while !recorder.active.load(std::sync::atomic::Ordering::Relaxed) {
+            std::thread::yield_now();
+        }
+        recorder.record(Kind::Vmc, &[4, 5, 6]);
+        recorder.record(Kind::OpenSeeFace, &[7]);
+        // dropping the last handle ends the thread, which flushes the file
+        drop(recorder);
+        let mut packets = None;
+        for _ in 0..100 {
+            match read(path) {
+                Ok(p) if p.len() == 2 => {
+                    packets = Some(p);
+                    break;
+                },
+                _ => std::thread::sleep(std::time::Duration::from_millis(10)),
+            }
+        }
+        let packets = packets.expect("recording was not written");
+        assert_eq!((packets[0].kind, packets[0].data.clone()), (Kind::Vmc, vec![4, 5, 6]));
+        assert_eq!((packets[1].kind, packets[1].data.clone()), (Kind::OpenSeeFace, vec![7]));
+        assert!(packets[0].time <= packets[1].time);
+        assert!(Playback::open(path, 1.0).is_ok());
+        std::fs::remove_file(path).unwrap();
+    }
+}
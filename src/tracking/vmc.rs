@@ -0,0 +1,293 @@
+// The VMC protocol (OSC over UDP), as spoken by VSeeFace, iFacialMocap bridges and friends.
+// Bones travel as /VMC/Ext/Bone/Pos and expressions as /VMC/Ext/Blend/Val, committed by /VMC/Ext/Blend/Apply.
+
+use std::collections::HashMap;
+
+use crate::{avatar, config, stage, vrm::v0};
+
+use super::{osc, recording, SharedTrackingState, TrackingSource};
+
+/// VRM 0.x blend shape names used on the wire, and the VRM 1.0 expressions they stand for.
+const BLEND_NAMES: [(&str, &str); 11] = [
+    ("A", "aa"),
+    ("I", "ih"),
+    ("U", "ou"),
+    ("E", "ee"),
+    ("O", "oh"),
+    ("Blink", "blink"),
+    ("Joy", "happy"),
+    ("Angry", "angry"),
+    ("Sorrow", "sad"),
+    ("Fun", "relaxed"),
+    ("Surprised", "surprised"),
+];
+
+/// Custom expression names travel capitalized, like the presets.
+fn capitalize(nm: &str) -> String {
+    let mut cs = nm.chars();
+    match cs.next() {
+        Some(c) => c.to_uppercase().chain(cs).collect(),
+        None => String::new(),
+    }
+}
+
+fn decapitalize(nm: &str) -> String {
+    let mut cs = nm.chars();
+    match cs.next() {
+        Some(c) => c.to_lowercase().chain(cs).collect(),
+        None => String::new(),
+    }
+}
+
+/// The reverse of `expression_name`, so receivers that only know VRM 0.x presets understand us.
+pub fn blend_name(nm: &str) -> String {
+    BLEND_NAMES.iter()
+        .find(|(_, vrm)| *vrm == nm)
+        .map(|(vmc, _)| (*vmc).to_owned())
+        .unwrap_or_else(|| capitalize(nm))
+}
+
+pub fn expression_name(nm: &str) -> String {
+    BLEND_NAMES.iter()
+        .find(|(vmc, _)| *vmc == nm)
+        .map(|(_, vrm)| (*vrm).to_owned())
+        .unwrap_or_else(|| decapitalize(nm))
+}
+
+/// Convert a rotation between Unity's left-handed space and glTF's right-handed one (X mirrored).
+/// The conversion is its own inverse.
+pub fn from_unity(q: glam::Quat) -> glam::Quat {
+    glam::Quat::from_xyzw(q.x, -q.y, -q.z, q.w)
+}
+
+/// Turns VMC packets into tracking updates. Expression values are held until the sender applies them.
+pub struct Decoder {
+    blends: HashMap<String, f32>,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self {
+            blends: HashMap::new(),
+        }
+    }
+
+    pub fn handle(&mut self, packet: &[u8], sts: &SharedTrackingState) -> Result<(), String> {
+        let mut bones = HashMap::new();
+        let mut apply = false;
+        for m in osc::decode(packet)? {
+            match m.addr.as_str() {
+                "/VMC/Ext/Bone/Pos" => {
+                    let f: Vec<f32> = m.args.iter().skip(1).filter_map(osc::Arg::as_f32).collect();
+                    let nm = m.args.first().and_then(osc::Arg::as_str).and_then(v0::bone_name);
+                    if let (Some(nm), 7) = (nm, f.len()) {
+                        let q = glam::Quat::from_xyzw(f[3], f[4], f[5], f[6]);
+                        bones.insert(nm.to_owned(), from_unity(q).normalize());
+                    }
+                },
+                "/VMC/Ext/Blend/Val" => {
+                    if let (Some(nm), Some(v)) = (m.args.first().and_then(osc::Arg::as_str), m.args.get(1).and_then(osc::Arg::as_f32)) {
+                        self.blends.insert(expression_name(nm), v.clamp(0.0, 1.0));
+                    }
+                },
+                "/VMC/Ext/Blend/Apply" => apply = true,
+                _ => {},
+            }
+        }
+        if !bones.is_empty() || apply {
+            let blends = if apply { std::mem::take(&mut self.blends) } else { HashMap::new() };
+            sts.lock().unwrap().apply_vmc(bones, blends);
+        }
+        Ok(())
+    }
+}
+
+pub struct Receiver {
+    socket: std::net::UdpSocket,
+    buf: Vec<u8>,
+    recorder: recording::Recorder,
+    decoder: Decoder,
+}
+
+impl Receiver {
+    pub fn bind(addr: &str, recorder: recording::Recorder) -> std::io::Result<Self> {
+        Ok(Self {
+            socket: std::net::UdpSocket::bind(addr)?,
+            buf: vec![0; 65535],
+            recorder,
+            decoder: Decoder::new(),
+        })
+    }
+}
+
+impl TrackingSource for Receiver {
+    fn name(&self) -> &str {
+        "VMC"
+    }
+
+    fn receive(&mut self, sts: &SharedTrackingState, _config: &config::Tracking) -> Result<(), String> {
+        let (len, _) = self.socket.recv_from(&mut self.buf).map_err(|e| e.to_string())?;
+        self.recorder.record(recording::Kind::Vmc, &self.buf[..len]);
+        self.decoder.handle(&self.buf[..len], sts)
+    }
+}
+
+/// Publishes the avatar's pose to another VMC receiver.
+pub struct Sender {
+    socket: std::net::UdpSocket,
+    start: std::time::Instant,
+}
+
+impl Sender {
+    pub fn connect(addr: &str) -> std::io::Result<Self> {
+        let socket = std::net::UdpSocket::bind("0.0.0.0:0")?;
+        socket.connect(addr)?;
+        Ok(Self {
+            socket,
+            start: std::time::Instant::now(),
+        })
+    }
+
+    /// Send the avatar's placement, its humanoid bones' pose relative to rest and the given expression
+    /// weights as one bundle. Bones use the same rest-relative convention the decoder applies on receipt.
+    pub fn send(&self, avatar: &avatar::Avatar, placement: &stage::Transform, expression_weights: &HashMap<String, f32>) {
+        let root = placement.rotation.mul_quat(glam::Quat::from_rotation_y(avatar.yaw));
+        let bones = avatar.scene.bone_node_indices.iter().map(|(nm, ni)| {
+            let rest = avatar.scene.nodes[*ni].transform;
+            (nm.as_str(), rest.inverse().mul_mat4(&avatar.nodes[*ni].transform))
+        });
+        let packet = encode_pose(self.start.elapsed().as_secs_f32(), placement.translation, root, bones, expression_weights);
+        if let Err(e) = self.socket.send(&packet) {
+            log::debug!("Failed to send VMC bundle: {}", e);
+        }
+    }
+}
+
+/// The bundle `Sender::send` publishes, from the root placement and each bone's transform relative to rest.
+fn encode_pose<'a>(
+    time: f32,
+    root_translation: glam::Vec3,
+    root_rotation: glam::Quat,
+    bones: impl Iterator<Item = (&'a str, glam::Mat4)>,
+    expression_weights: &HashMap<String, f32>,
+) -> Vec<u8> {
+    let msg = |addr: &str, args: Vec<osc::Arg>| osc::Message { addr: addr.to_owned(), args };
+    let pos = |name: String, t: glam::Vec3, r: glam::Quat| {
+        let r = from_unity(r);
+        vec![
+            osc::Arg::Str(name),
+            osc::Arg::Float(-t.x), osc::Arg::Float(t.y), osc::Arg::Float(t.z),
+            osc::Arg::Float(r.x), osc::Arg::Float(r.y), osc::Arg::Float(r.z), osc::Arg::Float(r.w),
+        ]
+    };
+    let mut ms = vec![
+        msg("/VMC/Ext/OK", vec![osc::Arg::Int(1)]),
+        msg("/VMC/Ext/T", vec![osc::Arg::Float(time)]),
+        msg("/VMC/Ext/Root/Pos", pos("root".to_owned(), root_translation, root_rotation)),
+    ];
+    for (nm, delta) in bones {
+        let unity_name = match v0::unity_bone_name(nm) {
+            Some(n) => n,
+            None => continue,
+        };
+        let (_, r, t) = delta.to_scale_rotation_translation();
+        ms.push(msg("/VMC/Ext/Bone/Pos", pos(unity_name, t, r)));
+    }
+    for (nm, w) in expression_weights {
+        ms.push(msg("/VMC/Ext/Blend/Val", vec![osc::Arg::Str(blend_name(nm)), osc::Arg::Float(*w)]));
+    }
+    ms.push(msg("/VMC/Ext/Blend/Apply", vec![]));
+    osc::encode_bundle(&ms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shared() -> SharedTrackingState {
+        std::sync::Arc::new(std::sync::Mutex::new(super::super::TrackingState::new()))
+    }
+
+    fn bone(name: &str, q: glam::Quat) -> osc::Message {
+        let mut args = vec![osc::Arg::Str(name.to_owned())];
+        args.extend([0.0, 0.0, 0.0, q.x, q.y, q.z, q.w].iter().map(|f| osc::Arg::Float(*f)));
+        osc::Message { addr: "/VMC/Ext/Bone/Pos".to_owned(), args }
+    }
+
+    fn blend(name: &str, v: f32) -> osc::Message {
+        osc::Message {
+            addr: "/VMC/Ext/Blend/Val".to_owned(),
+            args: vec![osc::Arg::Str(name.to_owned()), osc::Arg::Float(v)],
+        }
+    }
+
+    #[test]
+    fn bones_use_vrm1_names() {
+        let sts = shared();
+        let q = glam::Quat::from_rotation_z(0.4);
+        let packet = osc::encode_bundle(&[
+            bone("LeftThumbProximal", q),
+            bone("LeftThumbIntermediate", q),
+            bone("RightThumbDistal", q),
+            bone("Head", q),
+            bone("NotABone", q),
+        ]);
+        Decoder::new().handle(&packet, &sts).unwrap();
+        let bones = &sts.lock().unwrap().sample.bones;
+        let mut names: Vec<&str> = bones.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        // 0.x has no thumb metacarpal, so every thumb bone shifts down one
+        assert_eq!(names, vec!["head", "leftThumbMetacarpal", "leftThumbProximal", "rightThumbDistal"]);
+        assert!(bones["head"].abs_diff_eq(from_unity(q), 1e-6));
+    }
+
+    #[test]
+    fn blends_wait_for_apply() {
+        let sts = shared();
+        let mut decoder = Decoder::new();
+        decoder.handle(&osc::encode_bundle(&[blend("Joy", 0.7), blend("MyFace", 2.0)]), &sts).unwrap();
+        assert_eq!(sts.lock().unwrap().sample.expression_weights["happy"], 0.0);
+        assert!(sts.lock().unwrap().received.is_none());
+        let apply = osc::Message { addr: "/VMC/Ext/Blend/Apply".to_owned(), args: vec![] };
+        decoder.handle(&osc::encode(&apply), &sts).unwrap();
+        let weights = &sts.lock().unwrap().sample.expression_weights;
+        assert_eq!(weights["happy"], 0.7);
+        assert_eq!(weights["myFace"], 1.0);
+    }
+
+    #[test]
+    fn sent_pose_decodes_to_the_same_pose() {
+        let bones = [
+            ("head", glam::Quat::from_euler(glam::EulerRot::XYZ, 0.1, -0.4, 0.2)),
+            ("leftThumbMetacarpal", glam::Quat::from_rotation_z(0.3)),
+            ("rightUpperArm", glam::Quat::from_rotation_x(-0.6)),
+        ];
+        let root = glam::Vec3::new(0.5, 0.0, -1.25);
+        let weights = HashMap::from([("happy".to_owned(), 0.4), ("blink".to_owned(), 1.0)]);
+        let packet = encode_pose(
+            2.0,
+            root,
+            glam::Quat::from_rotation_y(0.3),
+            bones.iter().map(|(nm, q)| (*nm, glam::Mat4::from_quat(*q))),
+            &weights,
+        );
+
+        let sts = shared();
+        Decoder::new().handle(&packet, &sts).unwrap();
+        let st = sts.lock().unwrap();
+        assert_eq!(st.sample.bones.len(), bones.len());
+        for (nm, q) in &bones {
+            assert!(st.sample.bones[*nm].abs_diff_eq(*q, 1e-6), "{}", nm);
+        }
+        assert_eq!(st.sample.expression_weights["happy"], 0.4);
+        assert_eq!(st.sample.expression_weights["blink"], 1.0);
+
+        // the decoder has no use for the root, so check it on the wire: X is mirrored into Unity's space
+        let ms = osc::decode(&packet).unwrap();
+        let root_pos = ms.iter().find(|m| m.addr == "/VMC/Ext/Root/Pos").unwrap();
+        let f: Vec<f32> = root_pos.args.iter().skip(1).filter_map(osc::Arg::as_f32).collect();
+        assert_eq!(&f[..3], &[-root.x, root.y, root.z]);
+        let r = from_unity(glam::Quat::from_xyzw(f[3], f[4], f[5], f[6]));
+        assert!(r.abs_diff_eq(glam::Quat::from_rotation_y(0.3), 1e-6));
+    }
+}
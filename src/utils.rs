@@ -7,9 +7,80 @@ pub const ATTRIB_TEXCOORD: gl::types::GLuint = 2;
 pub const ATTRIB_JOINT: gl::types::GLuint = 3;
 pub const ATTRIB_WEIGHT: gl::types::GLuint = 4;
 
+/// Second UV set and second set of four joint influences.
+pub const ATTRIB_TEXCOORD1: gl::types::GLuint = 5;
+pub const ATTRIB_JOINT1: gl::types::GLuint = 6;
+pub const ATTRIB_WEIGHT1: gl::types::GLuint = 7;
+
+/// glTF TANGENT, xyz with the bitangent sign in w. Reads as zero for meshes without one, and the
+/// shader falls back to a tangent frame from screen-space derivatives.
+pub const ATTRIB_TANGENT: gl::types::GLuint = 8;
+
+/// Most morph targets blended into one draw; the strongest are kept when more are active.
+pub const MAX_MORPHS: usize = 32;
+
+/// Shader storage binding point of the morph target deltas.
+pub const SSBO_MORPHS: gl::types::GLuint = 0;
+
+/// Texture units of the material textures.
+pub const TEXUNIT_BASE_COLOR: gl::types::GLuint = 0;
+pub const TEXUNIT_EMISSIVE: gl::types::GLuint = 1;
+pub const TEXUNIT_SHADE_MULTIPLY: gl::types::GLuint = 2;
+pub const TEXUNIT_SHADING_SHIFT: gl::types::GLuint = 3;
+pub const TEXUNIT_MATCAP: gl::types::GLuint = 4;
+pub const TEXUNIT_RIM_MULTIPLY: gl::types::GLuint = 5;
+pub const TEXUNIT_OUTLINE_WIDTH: gl::types::GLuint = 6;
+pub const TEXUNIT_METALLIC_ROUGHNESS: gl::types::GLuint = 7;
+pub const TEXUNIT_NORMAL: gl::types::GLuint = 8;
+pub const TEXUNIT_OCCLUSION: gl::types::GLuint = 9;
+pub const TEXUNIT_COUNT: usize = 10;
+
 #[allow(dead_code)]
 #[derive(Clone, PartialEq, Debug)]
 pub struct Dimensions {
     pub w: f32,
     pub h: f32,
 }
+
+/// Counts of live GL objects, found by probing every name the driver has handed out so far.
+#[cfg(test)]
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct GlObjects {
+    pub buffers: usize,
+    pub vertex_arrays: usize,
+    pub textures: usize,
+    pub programs: usize,
+    pub framebuffers: usize,
+    pub renderbuffers: usize,
+}
+
+#[cfg(test)]
+impl GlObjects {
+    pub fn count() -> Self {
+        type Gen = unsafe fn(gl::types::GLsizei, *mut gl::types::GLuint);
+        type Delete = unsafe fn(gl::types::GLsizei, *const gl::types::GLuint);
+        type Is = unsafe fn(gl::types::GLuint) -> gl::types::GLboolean;
+        // drivers hand out names above the highest one in use, so a fresh name bounds the live ones
+        let count = |gen: Gen, delete: Delete, is: Is| {
+            let mut top = 0;
+            unsafe {
+                gen(1, &mut top);
+                delete(1, &top);
+            }
+            (1..top).filter(|n| unsafe { is(*n) } != 0).count()
+        };
+        let programs = unsafe {
+            let top = gl::CreateProgram();
+            gl::DeleteProgram(top);
+            (1..top).filter(|n| gl::IsProgram(*n) != 0).count()
+        };
+        Self {
+            buffers: count(gl::GenBuffers, gl::DeleteBuffers, gl::IsBuffer),
+            vertex_arrays: count(gl::GenVertexArrays, gl::DeleteVertexArrays, gl::IsVertexArray),
+            textures: count(gl::GenTextures, gl::DeleteTextures, gl::IsTexture),
+            programs,
+            framebuffers: count(gl::GenFramebuffers, gl::DeleteFramebuffers, gl::IsFramebuffer),
+            renderbuffers: count(gl::GenRenderbuffers, gl::DeleteRenderbuffers, gl::IsRenderbuffer),
+        }
+    }
+}
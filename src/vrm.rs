@@ -1,3 +1,5 @@
+pub mod v0;
+
 use crate::{gl, utils, context, shader};
 
 use std::{io::Read, collections::HashMap};
@@ -6,10 +8,321 @@ use std::{io::Read, collections::HashMap};
 pub struct Primitive {
     pub vao: gl::types::GLuint,
     pub mode: gl::types::GLenum,
+    /// Indices to draw, or vertices when the primitive isn't indexed.
     pub count: i32,
-    pub index_type: gl::types::GLenum,
-    pub index_offset: i32,
+    /// Type and byte offset of the indices in the bound element buffer.
+    pub indices: Option<(gl::types::GLenum, i32)>,
+    /// Primitives without a material use the glTF default one, kept after the document's materials.
+    pub material_index: usize,
+    /// Shader storage buffer of morph target deltas: for target t and vertex v, the position delta
+    /// is at vec4 index (t * vertex_count + v) * 2 and the normal delta right after it.
+    pub morph_buffer: Option<gl::types::GLuint>,
+    pub vertex_count: i32,
+    /// Middle of the bounding box, in the node's space; blended primitives are sorted by it.
+    pub center: glam::Vec3,
+}
+
+impl Primitive {
+    /// Draw with the vertex array already bound.
+    fn draw(&self) {
+        unsafe {
+            match self.indices {
+                Some((index_type, offset)) => gl::DrawElements(self.mode, self.count, index_type, offset as _),
+                None => gl::DrawArrays(self.mode, 0, self.count),
+            }
+        }
+    }
+}
+
+/// One morph target an expression moves, and how far at full weight.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct MorphBind {
+    pub mesh: usize,
+    pub target: usize,
+    pub weight: f32,
+}
+
+/// Which material color a `MaterialColorBind` moves.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MaterialColor {
+    Base,
+    Emission,
+    Shade,
+    Matcap,
+    Rim,
+    Outline,
+}
+
+impl MaterialColor {
+    pub fn from_name(nm: &str) -> Option<Self> {
+        match nm {
+            "color" => Some(MaterialColor::Base),
+            "emissionColor" => Some(MaterialColor::Emission),
+            "shadeColor" => Some(MaterialColor::Shade),
+            "matcapColor" => Some(MaterialColor::Matcap),
+            "rimColor" => Some(MaterialColor::Rim),
+            "outlineColor" => Some(MaterialColor::Outline),
+            _ => None,
+        }
+    }
+}
+
+/// One material color an expression moves toward `target` at full weight.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct MaterialColorBind {
+    pub material: usize,
+    pub color: MaterialColor,
+    pub target: glam::Vec4,
+}
+
+/// One material whose UVs an expression scales and offsets at full weight.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TextureTransformBind {
+    pub material: usize,
+    pub scale: glam::Vec2,
+    pub offset: glam::Vec2,
+}
+
+/// A material's animatable values after expression binds are applied.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct MaterialState {
+    pub base_color: glam::Vec4,
+    pub emission: glam::Vec3,
+    pub shade: glam::Vec3,
+    pub matcap: glam::Vec3,
+    pub rim: glam::Vec3,
+    pub outline: glam::Vec3,
+    pub uv_scale: glam::Vec2,
+    pub uv_offset: glam::Vec2,
+}
+
+pub const BLINK_EXPRESSIONS: [&str; 3] = ["blink", "blinkLeft", "blinkRight"];
+pub const LOOK_AT_EXPRESSIONS: [&str; 4] = ["lookUp", "lookDown", "lookLeft", "lookRight"];
+pub const MOUTH_EXPRESSIONS: [&str; 5] = ["aa", "ih", "ou", "ee", "oh"];
+
+/// What an active expression does to the blink, lookAt or mouth expressions.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Override {
+    None,
+    Block,
+    Blend,
+}
+
+impl Override {
+    pub fn from_name(nm: &str) -> Self {
+        match nm {
+            "block" => Override::Block,
+            "blend" => Override::Blend,
+            _ => Override::None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ExpressionInfo {
+    pub is_binary: bool,
+    pub override_blink: Override,
+    pub override_look_at: Override,
+    pub override_mouth: Override,
+}
+
+/// How much of the blink, lookAt and mouth expressions survives the other active expressions.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct OverrideWeights {
+    pub blink: f32,
+    pub look_at: f32,
+    pub mouth: f32,
+}
+
+/// Values shared by every draw in one `Scene::render`.
+struct Frame<'a> {
+    global_transforms: &'a Vec<glam::Mat4>,
+    morph_weights: HashMap<usize, HashMap<usize, f32>>,
+    materials: Vec<MaterialState>,
+    camera_pos: glam::Vec3,
+}
+
+/// Where GL reads an accessor's elements from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AccessorSource {
+    /// Straight from a glTF buffer, at a byte offset, with a byte stride (0 when tightly packed).
+    View { buffer: usize, offset: usize, stride: usize },
+    /// Tightly packed elements unpacked on the CPU, for sparse accessors and accessors without a view.
+    Packed(Vec<u8>),
+}
+
+/// An accessor as GL vertex attribute or index data.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccessorLayout {
+    pub source: AccessorSource,
+    pub data_type: gl::types::GLenum,
+    pub size: i32,
+    pub normalized: bool,
+}
+
+/// Everything needed to create a primitive's GL objects, worked out from the document alone.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrimitiveLayout {
+    pub mode: gl::types::GLenum,
+    pub attribs: Vec<(gl::types::GLuint, AccessorLayout)>,
+    /// Index count and data, for indexed primitives.
+    pub indices: Option<(usize, AccessorLayout)>,
+    pub vertex_count: usize,
     pub material_index: usize,
+    /// Morph target deltas laid out as in `Primitive::morph_buffer`; empty without targets.
+    pub morph_deltas: Vec<[f32; 4]>,
+    pub center: glam::Vec3,
+}
+
+/// A node's skinning matrices and active morphs, as `draw_primitive` takes them.
+type NodeDraw = (Vec<f32>, (Vec<i32>, Vec<f32>));
+
+impl PrimitiveLayout {
+    /// Lay out a primitive whose buffers' contents are `buffers`, in document order. Primitives
+    /// without a material get `default_material_index`; `legacy` models have their bounds flipped
+    /// like their buffers. `None` if an index or attribute accessor can't be read.
+    pub fn new(p: &gltf::Primitive, buffers: &[&[u8]], default_material_index: usize, legacy: bool) -> Option<Self> {
+        let mode = match p.mode() {
+            gltf::mesh::Mode::Points => gl::POINTS,
+            gltf::mesh::Mode::Lines => gl::LINES,
+            gltf::mesh::Mode::LineLoop => gl::LINE_LOOP,
+            gltf::mesh::Mode::LineStrip => gl::LINE_STRIP,
+            gltf::mesh::Mode::Triangles => gl::TRIANGLES,
+            gltf::mesh::Mode::TriangleStrip => gl::TRIANGLE_STRIP,
+            gltf::mesh::Mode::TriangleFan => gl::TRIANGLE_FAN,
+        };
+        let indices = match p.indices() {
+            Some(a) => Some((a.count(), AccessorLayout::new(&a, buffers)?)),
+            None => None,
+        };
+        let mut attribs = Vec::new();
+        for (semantic, accessor) in p.attributes() {
+            let attrib = match semantic {
+                gltf::Semantic::Positions => utils::ATTRIB_VERTEX,
+                gltf::Semantic::Normals => utils::ATTRIB_NORMAL,
+                gltf::Semantic::TexCoords(0) => utils::ATTRIB_TEXCOORD,
+                gltf::Semantic::Joints(0) => utils::ATTRIB_JOINT,
+                gltf::Semantic::Weights(0) => utils::ATTRIB_WEIGHT,
+                gltf::Semantic::TexCoords(1) => utils::ATTRIB_TEXCOORD1,
+                gltf::Semantic::Joints(1) => utils::ATTRIB_JOINT1,
+                gltf::Semantic::Weights(1) => utils::ATTRIB_WEIGHT1,
+                gltf::Semantic::Tangents => utils::ATTRIB_TANGENT,
+                _ => continue,
+            };
+            attribs.push((attrib, AccessorLayout::new(&accessor, buffers)?));
+        }
+
+        let positions = p.get(&gltf::Semantic::Positions);
+        let vertex_count = positions.as_ref().map_or(0, |a| a.count());
+        let bound = |v: Option<gltf::json::Value>| {
+            let v: Vec<f32> = v?.as_array()?.iter().filter_map(|x| x.as_f64()).map(|x| x as f32).collect();
+            Some(glam::Vec3::new(*v.get(0)?, *v.get(1)?, *v.get(2)?))
+        };
+        let center = match (bound(positions.as_ref().and_then(|a| a.min())), bound(positions.as_ref().and_then(|a| a.max()))) {
+            (Some(lo), Some(hi)) => (lo + hi) / 2.0,
+            _ => glam::Vec3::ZERO,
+        };
+        // bounds are not part of the flipped buffer data
+        let center = if legacy { center * glam::Vec3::new(-1.0, 1.0, -1.0) } else { center };
+
+        Some(Self {
+            mode,
+            attribs,
+            indices,
+            vertex_count,
+            material_index: p.material().index().unwrap_or(default_material_index),
+            morph_deltas: Self::morph_deltas(p, vertex_count, buffers),
+            center,
+        })
+    }
+
+    /// Every morph target's position and normal deltas, two vec4s per vertex per target.
+    fn morph_deltas(p: &gltf::Primitive, vertex_count: usize, buffers: &[&[u8]]) -> Vec<[f32; 4]> {
+        let reader = p.reader(|buffer: gltf::Buffer| buffers.get(buffer.index()).copied());
+        let mut deltas: Vec<[f32; 4]> = Vec::new();
+        for (positions, normals, _) in reader.read_morph_targets() {
+            let start = deltas.len();
+            deltas.resize(start + vertex_count * 2, [0.0; 4]);
+            for (v, [x, y, z]) in positions.into_iter().flatten().take(vertex_count).enumerate() {
+                deltas[start + v * 2] = [x, y, z, 0.0];
+            }
+            for (v, [x, y, z]) in normals.into_iter().flatten().take(vertex_count).enumerate() {
+                deltas[start + v * 2 + 1] = [x, y, z, 0.0];
+            }
+        }
+        deltas
+    }
+}
+
+impl AccessorLayout {
+    /// Accessors read straight from a buffer view point at it; sparse accessors and accessors
+    /// without a view are unpacked.
+    pub fn new(accessor: &gltf::Accessor, buffers: &[&[u8]]) -> Option<Self> {
+        let source = match accessor.view() {
+            Some(view) if accessor.sparse().is_none() => AccessorSource::View {
+                buffer: view.buffer().index(),
+                offset: view.offset() + accessor.offset(),
+                stride: view.stride().unwrap_or(0),
+            },
+            _ => AccessorSource::Packed(Self::pack_any(accessor, buffers)?),
+        };
+        Some(Self {
+            source,
+            data_type: match accessor.data_type() {
+                gltf::accessor::DataType::I8 => gl::BYTE,
+                gltf::accessor::DataType::U8 => gl::UNSIGNED_BYTE,
+                gltf::accessor::DataType::I16 => gl::SHORT,
+                gltf::accessor::DataType::U16 => gl::UNSIGNED_SHORT,
+                gltf::accessor::DataType::U32 => gl::UNSIGNED_INT,
+                gltf::accessor::DataType::F32 => gl::FLOAT,
+            },
+            size: accessor.dimensions().multiplicity() as _,
+            normalized: accessor.normalized(),
+        })
+    }
+
+    /// Read an accessor's elements as `T`, applying sparse substitutions (over zeros when there is no
+    /// buffer view), as tightly packed bytes.
+    fn pack<T: gltf::accessor::Item + Copy>(accessor: &gltf::Accessor, buffers: &[&[u8]]) -> Option<Vec<u8>> {
+        let elements: Vec<T> = if accessor.count() == 0 {
+            Vec::new()
+        } else if accessor.view().is_none() && accessor.sparse().is_none() {
+            (0..accessor.count()).map(|_| T::zero()).collect()
+        } else {
+            gltf::accessor::Iter::<T>::new(accessor.clone(), |buffer: gltf::Buffer| buffers.get(buffer.index()).copied())?
+                .collect()
+        };
+        let len = elements.len() * std::mem::size_of::<T>();
+        // T is a number or an array of numbers, with no padding
+        Some(unsafe { std::slice::from_raw_parts(elements.as_ptr() as *const u8, len) }.to_vec())
+    }
+
+    /// `pack` with the element type of the accessor's component type and dimensions.
+    fn pack_any(accessor: &gltf::Accessor, buffers: &[&[u8]]) -> Option<Vec<u8>> {
+        use gltf::accessor::{DataType, Dimensions};
+        macro_rules! dims {
+            ($t:ty) => {
+                match accessor.dimensions() {
+                    Dimensions::Scalar => Self::pack::<$t>(accessor, buffers),
+                    Dimensions::Vec2 => Self::pack::<[$t; 2]>(accessor, buffers),
+                    Dimensions::Vec3 => Self::pack::<[$t; 3]>(accessor, buffers),
+                    Dimensions::Vec4 => Self::pack::<[$t; 4]>(accessor, buffers),
+                    d => {
+                        log::warn!("accessor {}: {:?} elements can't be vertex data", accessor.index(), d);
+                        None
+                    },
+                }
+            };
+        }
+        match accessor.data_type() {
+            DataType::I8 => dims!(i8),
+            DataType::U8 => dims!(u8),
+            DataType::I16 => dims!(i16),
+            DataType::U16 => dims!(u16),
+            DataType::U32 => dims!(u32),
+            DataType::F32 => dims!(f32),
+        }
+    }
 }
 
 #[allow(dead_code)]
@@ -26,15 +339,69 @@ pub struct Skin {
 #[allow(dead_code)]
 pub struct Texture {
     pub tid: gl::types::GLuint,
+    /// The `TEXCOORD` set to sample with.
+    pub tex_coord: u32,
+    /// KHR_texture_transform, applied to the UV before sampling.
+    pub transform: glam::Mat3,
 }
 
 impl Texture {
     pub fn bind(&self, _ctx: &context::Context) {
+        self.bind_unit(utils::TEXUNIT_BASE_COLOR);
+    }
+
+    pub fn bind_unit(&self, unit: gl::types::GLuint) {
         unsafe {
-            gl::ActiveTexture(gl::TEXTURE0);
+            gl::ActiveTexture(gl::TEXTURE0 + unit);
             gl::BindTexture(gl::TEXTURE_2D, self.tid);
+            gl::ActiveTexture(gl::TEXTURE0);
         }
     }
+
+    /// Offset, then rotate counter-clockwise, then scale, as KHR_texture_transform specifies.
+    fn uv_transform(offset: [f32; 2], rotation: f32, scale: [f32; 2]) -> glam::Mat3 {
+        let (s, c) = rotation.sin_cos();
+        let translation = glam::Mat3::from_translation(glam::Vec2::from(offset));
+        let rotation = glam::Mat3::from_cols(
+            glam::Vec3::new(c, -s, 0.0),
+            glam::Vec3::new(s, c, 0.0),
+            glam::Vec3::Z,
+        );
+        translation * rotation * glam::Mat3::from_scale(glam::Vec2::from(scale))
+    }
+}
+
+pub use gltf::material::AlphaMode;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OutlineWidthMode {
+    None,
+    World,
+    Screen,
+}
+
+/// `VRMC_materials_mtoon` parameters. Missing textures are bound as white; factors are adjusted so
+/// that a missing texture contributes what the spec says it should.
+#[allow(dead_code)]
+pub struct MToon {
+    pub shade_multiply_texture: Option<Texture>,
+    pub shading_shift_factor: f32,
+    pub shading_shift_texture: Option<Texture>,
+    pub shading_shift_texture_scale: f32,
+    pub shading_toony_factor: f32,
+    pub gi_equalization_factor: f32,
+    pub matcap_factor: glam::Vec3,
+    pub matcap_texture: Option<Texture>,
+    pub parametric_rim_color_factor: glam::Vec3,
+    pub rim_multiply_texture: Option<Texture>,
+    pub rim_lighting_mix_factor: f32,
+    pub parametric_rim_fresnel_power_factor: f32,
+    pub parametric_rim_lift_factor: f32,
+    pub outline_width_mode: OutlineWidthMode,
+    pub outline_width_factor: f32,
+    pub outline_width_multiply_texture: Option<Texture>,
+    pub outline_color_factor: glam::Vec3,
+    pub outline_lighting_mix_factor: f32,
 }
 
 #[allow(dead_code)]
@@ -47,11 +414,21 @@ pub struct Material {
     pub metallic_roughness_texture: Option<Texture>,
 
     pub normal_texture: Option<Texture>,
+    pub normal_scale: f32,
 
     pub occlusion_texture: Option<Texture>,
+    pub occlusion_strength: f32,
+
+    pub alpha_mode: AlphaMode,
+    pub alpha_cutoff: f32,
+    pub double_sided: bool,
 
     pub emissive_factor: glam::Vec3,
     pub emissive_texture: Option<Texture>,
+
+    pub shade_color_factor: glam::Vec3,
+
+    pub mtoon: Option<MToon>,
 }
 
 #[allow(dead_code)]
@@ -64,82 +441,374 @@ pub struct Node {
     
 }
 
+/// What a `VRMC_node_constraint` constraint drives. Axes are unit vectors in the node's rest space.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ConstraintKind {
+    Roll(glam::Vec3),
+    Aim(glam::Vec3),
+    Rotation,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Constraint {
+    pub node: usize,
+    pub source: usize,
+    pub kind: ConstraintKind,
+    pub weight: f32,
+}
+
+impl Constraint {
+    fn from_json(node: usize, c: &gltf::json::extensions::scene::vrmc_node_constraint::Constraint) -> Option<Self> {
+        let axis = |nm: &str| match nm.trim_start_matches("Positive").trim_start_matches("Negative") {
+            "X" => Some(glam::Vec3::X),
+            "Y" => Some(glam::Vec3::Y),
+            "Z" => Some(glam::Vec3::Z),
+            _ => None,
+        };
+        let (source, kind, weight) = if let Some(r) = &c.roll {
+            (r.source, ConstraintKind::Roll(axis(&r.roll_axis)?), r.weight)
+        } else if let Some(a) = &c.aim {
+            let sign = if a.aim_axis.starts_with("Negative") { -1.0 } else { 1.0 };
+            (a.source, ConstraintKind::Aim(axis(&a.aim_axis)? * sign), a.weight)
+        } else if let Some(r) = &c.rotation {
+            (r.source, ConstraintKind::Rotation, r.weight)
+        } else {
+            return None;
+        };
+        Some(Self { node, source: source as _, kind, weight: weight.clamp(0.0, 1.0) })
+    }
+}
+
+/// A parsed and validated VRM file, not yet uploaded to the GPU. Safe to build off the render thread.
+pub struct Document {
+    pub gltf: gltf::Document,
+    pub buffers: Vec<gltf::buffer::Data>,
+    pub images: Vec<gltf::image::Data>,
+    pub vrm: gltf::json::extensions::root::VrmcVrm,
+    pub spring_bone: gltf::json::extensions::root::VrmcSpringBone,
+    /// Converted from VRM 0.x: node transforms and inverse bind matrices still need `v0::flip_matrix`.
+    pub legacy: bool,
+}
+
+impl Document {
+    pub fn open(path: &str) -> Result<Self, String> {
+        let file = std::fs::File::open(path).map_err(|e| format!("failed to open {}: {}", path, e))?;
+        let mut reader = std::io::BufReader::new(file);
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).map_err(|e| format!("failed to read {}: {}", path, e))?;
+        Self::from_slice(&bytes)
+    }
+
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, String> {
+        let (gltf, mut buffers, images) = gltf::import_slice(bytes).map_err(|e| format!("invalid glTF: {}", e))?;
+        let extensions = gltf.clone().into_json().extensions.unwrap_or_default();
+        let (vrm, spring_bone, legacy) = match (extensions.vrmc_vrm, extensions.vrm0) {
+            (Some(vrm), _) => (vrm, extensions.vrmc_spring_bone.unwrap_or_default(), false),
+            (None, Some(v0)) => {
+                log::info!("converting VRM {} model", v0.spec_version);
+                let (vrm, spring_bone) = v0::convert(&gltf, &v0);
+                v0::flip_buffers(&gltf, &mut buffers);
+                (vrm, spring_bone, true)
+            },
+            (None, None) => return Err("missing VRMC_vrm or VRM extension".to_owned()),
+        };
+        let doc = Self { gltf, buffers, images, vrm, spring_bone, legacy };
+        doc.validate()?;
+        Ok(doc)
+    }
+
+    /// Check everything `Scene::upload` relies on, so that uploading cannot panic.
+    fn validate(&self) -> Result<(), String> {
+        if !self.vrm.humanoid.human_bones.contains_key("head") {
+            return Err("humanoid has no head bone".to_owned());
+        }
+        for (_, b) in &self.vrm.humanoid.human_bones {
+            if self.gltf.nodes().nth(b.node as _).is_none() {
+                return Err(format!("humanoid bone refers to missing node {}", b.node));
+            }
+        }
+        if self.gltf.default_scene().is_none() {
+            return Err("no default scene".to_owned());
+        }
+        let node = |n: u32, what: &str| match self.gltf.nodes().nth(n as _) {
+            Some(_) => Ok(()),
+            None => Err(format!("{} refers to missing node {}", what, n)),
+        };
+        let sb = &self.spring_bone;
+        for c in &sb.colliders {
+            node(c.node, "spring bone collider")?;
+        }
+        for g in &sb.collider_groups {
+            if let Some(ci) = g.colliders.iter().find(|ci| **ci as usize >= sb.colliders.len()) {
+                return Err(format!("collider group {} refers to missing collider {}", g.name, ci));
+            }
+        }
+        for sp in &sb.springs {
+            for j in &sp.joints {
+                node(j.node, "spring joint")?;
+            }
+            if let Some(c) = sp.center {
+                node(c, "spring center")?;
+            }
+            if let Some(gi) = sp.collider_groups.iter().find(|gi| **gi as usize >= sb.collider_groups.len()) {
+                return Err(format!("spring {} refers to missing collider group {}", sp.name, gi));
+            }
+        }
+        for n in self.gltf.nodes() {
+            if let Some(c) = n.constraint() {
+                let source = c.roll.as_ref().map(|r| r.source)
+                    .or(c.aim.as_ref().map(|a| a.source))
+                    .or(c.rotation.as_ref().map(|r| r.source));
+                if let Some(source) = source {
+                    node(source, "node constraint")?;
+                }
+            }
+        }
+        let expressions = self.vrm.expressions.preset.iter().chain(self.vrm.expressions.custom.iter());
+        for (enm, e) in expressions {
+            for bind in &e.morph_target_binds {
+                let mesh = self.gltf.nodes().nth(bind.node as _).and_then(|n| n.mesh())
+                    .ok_or(format!("expression {} refers to a node without a mesh", enm))?;
+                for p in mesh.primitives() {
+                    if p.morph_targets().nth(bind.index as _).is_none() {
+                        return Err(format!("expression {} refers to missing morph target {}", enm, bind.index));
+                    }
+                }
+            }
+            let materials = e.material_color_binds.iter().map(|b| b.material)
+                .chain(e.texture_transform_binds.iter().map(|b| b.material));
+            for mi in materials {
+                if self.gltf.materials().nth(mi as _).is_none() {
+                    return Err(format!("expression {} refers to missing material {}", enm, mi));
+                }
+            }
+        }
+        for m in self.gltf.meshes() {
+            for p in m.primitives() {
+                let bad_indices = p.indices().map_or(false, |a| {
+                    a.dimensions() != gltf::accessor::Dimensions::Scalar
+                        || !matches!(a.data_type(), gltf::accessor::DataType::U8 | gltf::accessor::DataType::U16 | gltf::accessor::DataType::U32)
+                });
+                if bad_indices {
+                    return Err(format!("mesh {} has a primitive with indices that aren't unsigned scalars", m.index()));
+                }
+            }
+        }
+        for s in self.gltf.skins() {
+            let get_buffer_data = |buffer: gltf::Buffer| self.buffers.get(buffer.index()).map(|x| &*x.0);
+            if s.reader(get_buffer_data).read_inverse_bind_matrices().is_none() {
+                return Err(format!("skin {} has no inverse bind matrices", s.index()));
+            }
+        }
+        Ok(())
+    }
+}
+
 #[allow(dead_code)]
 pub struct Scene {
     pub meshes: Vec<Mesh>,
     pub skins: Vec<Skin>,
     pub materials: Vec<Material>,
     pub nodes: Vec<Node>,
+    pub parent_indices: Vec<Option<usize>>,
+    /// Node constraints, ordered so that each comes after every constraint it depends on.
+    pub constraints: Vec<Constraint>,
     pub bone_node_indices: HashMap<String, usize>,
     pub scene_node_indices: Vec<usize>,
+    pub look_at: gltf::json::extensions::root::VrmLookAt,
+    pub spring_bone: gltf::json::extensions::root::VrmcSpringBone,
+    pub expressions: HashMap<String, Vec<MorphBind>>,
+    pub expression_info: HashMap<String, ExpressionInfo>,
+    pub material_color_binds: HashMap<String, Vec<MaterialColorBind>>,
+    pub texture_transform_binds: HashMap<String, Vec<TextureTransformBind>>,
+    pub buffers: Vec<gl::types::GLuint>,
+    pub textures: Vec<gl::types::GLuint>,
+    /// Bound in place of missing material textures.
+    pub white_texture: Texture,
+}
+
+impl Drop for Scene {
+    fn drop(&mut self) {
+        unsafe {
+            for m in &self.meshes {
+                for p in &m.primitives {
+                    gl::DeleteVertexArrays(1, &p.vao);
+                }
+            }
+            gl::DeleteBuffers(self.buffers.len() as _, self.buffers.as_ptr());
+            gl::DeleteTextures(self.textures.len() as _, self.textures.as_ptr());
+        }
+    }
 }
 
 impl Scene {
-    fn initialize_attrib(attrib: gl::types::GLuint, accessor: &gltf::Accessor, bufs: &Vec<(gl::types::GLuint, &gltf::buffer::Data)> ) {
-        let data_type = match accessor.data_type() {
-            gltf::accessor::DataType::I8 => gl::BYTE,
-            gltf::accessor::DataType::U8 => gl::UNSIGNED_BYTE,
-            gltf::accessor::DataType::I16 => gl::SHORT,
-            gltf::accessor::DataType::U16 => gl::UNSIGNED_SHORT,
-            gltf::accessor::DataType::U32 => gl::UNSIGNED_INT,
-            gltf::accessor::DataType::F32 => gl::FLOAT,
+    /// Copy `data` into a new buffer, left bound to `target`.
+    fn upload_buffer<T>(target: gl::types::GLenum, data: &[T]) -> gl::types::GLuint {
+        unsafe {
+            let mut buf: gl::types::GLuint = 0;
+            gl::GenBuffers(1, &mut buf as *mut gl::types::GLuint);
+            gl::BindBuffer(target, buf);
+            gl::BufferData(
+                target,
+                (data.len() * std::mem::size_of::<T>()) as _,
+                data.as_ptr() as *const std::ffi::c_void,
+                gl::STATIC_DRAW,
+            );
+            buf
+        }
+    }
+
+    /// Bind an accessor's data to `target`, returning the byte offset of its first element and the
+    /// GL buffer created for it if it was unpacked.
+    fn bind_accessor(
+        target: gl::types::GLenum,
+        layout: &AccessorLayout,
+        bufs: &Vec<(gl::types::GLuint, &gltf::buffer::Data)>,
+    ) -> Option<(usize, Option<gl::types::GLuint>)> {
+        match &layout.source {
+            AccessorSource::View { buffer, offset, .. } => {
+                unsafe {
+                    gl::BindBuffer(target, bufs.get(*buffer)?.0);
+                }
+                Some((*offset, None))
+            },
+            AccessorSource::Packed(data) => Some((0, Some(Self::upload_buffer(target, data)))),
+        }
+    }
+
+    /// Set up a vertex attribute, returning the GL buffer created for it if the accessor needed its own.
+    fn initialize_attrib(
+        attrib: gl::types::GLuint,
+        layout: &AccessorLayout,
+        bufs: &Vec<(gl::types::GLuint, &gltf::buffer::Data)>,
+    ) -> Option<Option<gl::types::GLuint>> {
+        let (offset, created) = Self::bind_accessor(gl::ARRAY_BUFFER, layout, bufs)?;
+        let stride = match layout.source {
+            AccessorSource::View { stride, .. } => stride,
+            AccessorSource::Packed(_) => 0,
         };
-        if let Some(_sparse) = accessor.sparse() {
-            let get_buffer_data = |buffer: gltf::Buffer| bufs.get(buffer.index()).map(|x| &*x.1.0);
-            let iter = gltf::accessor::Iter::<[f32; 3]>::new(accessor.clone(), get_buffer_data).unwrap();
-            let mut b: Vec<[f32; 3]> = Vec::new();
-            for item in iter {
-                b.push(item);
+        unsafe {
+            gl::VertexAttribPointer(attrib, layout.size, layout.data_type, layout.normalized as _, stride as _, offset as _);
+            gl::EnableVertexAttribArray(attrib);
+        }
+        Some(created)
+    }
+
+    /// Morph target weights per mesh for a set of expression weights.
+    pub fn morph_weights(&self, expression_weights: &HashMap<String, f32>) -> HashMap<usize, HashMap<usize, f32>> {
+        let mut out: HashMap<usize, HashMap<usize, f32>> = HashMap::new();
+        for (enm, w) in expression_weights {
+            if *w == 0.0 { continue; }
+            for b in self.expressions.get(enm).into_iter().flatten() {
+                *out.entry(b.mesh).or_default().entry(b.target).or_insert(0.0) += w * b.weight;
             }
-            
-            log::info!("attrib {}: length {}", attrib, (b.len() * accessor.dimensions().multiplicity() * accessor.data_type().size()));
-            unsafe {
-                let mut buf: gl::types::GLuint = 0;
-                gl::GenBuffers(1, &mut buf as *mut gl::types::GLuint);
-                gl::BindBuffer(gl::ARRAY_BUFFER, buf);
-                gl::BufferData(
-                    gl::ARRAY_BUFFER,
-                    (b.len() * accessor.dimensions().multiplicity() * accessor.data_type().size()) as _,
-                    b.as_ptr() as *const std::ffi::c_void,
-                    gl::STATIC_DRAW,
-                );
-                gl::VertexAttribPointer(
-                    attrib,
-                    accessor.dimensions().multiplicity() as _,
-                    data_type,
-                    accessor.normalized() as _,
-                    0,
-                    0 as _,
-                );
-                gl::EnableVertexAttribArray(attrib);
+        }
+        out
+    }
+
+    /// Weight an expression actually contributes: clamped, and rounded for binary expressions.
+    fn effective_weight(&self, enm: &str, w: f32) -> f32 {
+        match self.expression_info.get(enm) {
+            Some(info) if info.is_binary => if w > 0.5 { 1.0 } else { 0.0 },
+            _ => w.clamp(0.0, 1.0),
+        }
+    }
+
+    /// Multipliers for the blink, lookAt and mouth groups: any active expression that blocks a group
+    /// silences it, and one that blends it leaves at most (1 - weight) of it.
+    pub fn override_weights(&self, expression_weights: &HashMap<String, f32>) -> OverrideWeights {
+        let mut out = OverrideWeights { blink: 1.0, look_at: 1.0, mouth: 1.0 };
+        let apply = |m: &mut f32, o: Override, w: f32| match o {
+            Override::Block => *m = 0.0,
+            Override::Blend => *m = m.min(1.0 - w),
+            Override::None => {},
+        };
+        for (enm, w) in expression_weights {
+            let grouped = BLINK_EXPRESSIONS.iter().chain(LOOK_AT_EXPRESSIONS.iter()).chain(MOUTH_EXPRESSIONS.iter())
+                .any(|g| g == enm);
+            let w = self.effective_weight(enm, *w);
+            if grouped || w <= 0.0 { continue; }
+            if let Some(info) = self.expression_info.get(enm) {
+                apply(&mut out.blink, info.override_blink, w);
+                apply(&mut out.look_at, info.override_look_at, w);
+                apply(&mut out.mouth, info.override_mouth, w);
             }
-        } else {
-            let view = accessor.view().unwrap();
-            let buf = bufs.get(view.buffer().index()).unwrap().0;
-            log::info!("attrib {}: length {}", attrib, view.length());
-            unsafe {
-                gl::BindBuffer(gl::ARRAY_BUFFER, buf);
-                gl::VertexAttribPointer(
-                    attrib,
-                    accessor.dimensions().multiplicity() as _,
-                    data_type,
-                    accessor.normalized() as _,
-                    match view.stride() { Some(s) => s, _ => 0 } as _,
-                    view.offset() as _
-                );
-                gl::EnableVertexAttribArray(attrib);
+        }
+        out
+    }
+
+    /// Resolve raw expression weights into the ones to render, applying isBinary and the overrides.
+    /// `look_weights` come from lookAt and replace any tracked weight of the same name.
+    pub fn mix_expressions(
+        &self,
+        expression_weights: &HashMap<String, f32>,
+        look_weights: &HashMap<String, f32>,
+    ) -> HashMap<String, f32> {
+        // lookAt expressions never override, so only the tracked weights matter here
+        let o = self.override_weights(expression_weights);
+        let tracked = expression_weights.iter().filter(|(enm, _)| !look_weights.contains_key(*enm));
+        tracked.chain(look_weights.iter()).map(|(enm, w)| {
+            let mut w = self.effective_weight(enm, *w);
+            if BLINK_EXPRESSIONS.contains(&enm.as_str()) {
+                w *= o.blink;
+            } else if LOOK_AT_EXPRESSIONS.contains(&enm.as_str()) {
+                w *= o.look_at;
+            } else if MOUTH_EXPRESSIONS.contains(&enm.as_str()) {
+                w *= o.mouth;
+            }
+            (enm.clone(), w)
+        }).collect()
+    }
+
+    /// Every material's values with the expression binds applied. Colors blend from the material's
+    /// own value toward each target; UV transforms blend from the identity.
+    pub fn material_states(&self, expression_weights: &HashMap<String, f32>) -> Vec<MaterialState> {
+        let mut out: Vec<MaterialState> = self.materials.iter().map(|m| MaterialState {
+            base_color: m.base_color_factor,
+            emission: m.emissive_factor,
+            shade: m.shade_color_factor,
+            matcap: m.mtoon.as_ref().map_or(glam::Vec3::ZERO, |t| t.matcap_factor),
+            rim: m.mtoon.as_ref().map_or(glam::Vec3::ZERO, |t| t.parametric_rim_color_factor),
+            outline: m.mtoon.as_ref().map_or(glam::Vec3::ZERO, |t| t.outline_color_factor),
+            uv_scale: glam::Vec2::ONE,
+            uv_offset: glam::Vec2::ZERO,
+        }).collect();
+        for (enm, w) in expression_weights {
+            if *w == 0.0 { continue; }
+            for b in self.material_color_binds.get(enm).into_iter().flatten() {
+                let (m, st) = match (self.materials.get(b.material), out.get_mut(b.material)) {
+                    (Some(m), Some(st)) => (m, st),
+                    _ => continue,
+                };
+                let mtoon = |f: fn(&MToon) -> glam::Vec3| m.mtoon.as_ref().map_or(glam::Vec3::ZERO, f);
+                let target = b.target.truncate();
+                match b.color {
+                    MaterialColor::Base => st.base_color += (b.target - m.base_color_factor) * *w,
+                    MaterialColor::Emission => st.emission += (target - m.emissive_factor) * *w,
+                    MaterialColor::Shade => st.shade += (target - m.shade_color_factor) * *w,
+                    MaterialColor::Matcap => st.matcap += (target - mtoon(|t| t.matcap_factor)) * *w,
+                    MaterialColor::Rim => st.rim += (target - mtoon(|t| t.parametric_rim_color_factor)) * *w,
+                    MaterialColor::Outline => st.outline += (target - mtoon(|t| t.outline_color_factor)) * *w,
+                }
+            }
+            for b in self.texture_transform_binds.get(enm).into_iter().flatten() {
+                if let Some(st) = out.get_mut(b.material) {
+                    st.uv_scale += (b.scale - glam::Vec2::ONE) * *w;
+                    st.uv_offset += b.offset * *w;
+                }
             }
         }
+        out
     }
 
     pub fn new(ctx: &context::Context, path: &str) -> Self {
-        let file = std::fs::File::open(path).unwrap();
-        let mut reader = std::io::BufReader::new(file);
-        let mut bytes = Vec::new();
-        reader.read_to_end(&mut bytes).unwrap();
-        let (gltf, buffers, images) = gltf::import_slice(bytes.as_slice()).unwrap();
-        let json = gltf.clone().into_json();
-        let vrm = json.extensions.unwrap().vrmc_vrm.unwrap();
+        let doc = Document::open(path).unwrap();
+        Self::upload(ctx, &doc)
+    }
+
+    pub fn upload(_ctx: &context::Context, doc: &Document) -> Self {
+        let Document { gltf, buffers, images, vrm, spring_bone, legacy } = doc;
+        let flip = |m: glam::Mat4| if *legacy { v0::flip_matrix(&m) } else { m };
 
         log::info!("specVersion: {}", vrm.spec_version);
         let mut max: i32 = 0;
@@ -148,10 +817,61 @@ impl Scene {
         }
         log::info!("max: {}", max);
 
-        let expressions: HashMap<String, (gltf::json::extensions::root::MorphTargetBind, gltf::Mesh, gl::types::GLuint)> =
-            ctx.attrib_expressions.iter().map(|(enm, attrib)| {
-                let expr = &vrm.expressions.preset.get(enm).unwrap().morph_target_binds[0];
-                (enm.clone(), (expr.clone(), gltf.nodes().nth(expr.node as _).unwrap().mesh().unwrap(), *attrib))
+        let expressions: HashMap<String, Vec<MorphBind>> = vrm.expressions.preset.iter()
+            .chain(vrm.expressions.custom.iter())
+            .map(|(enm, e)| {
+                let binds = e.morph_target_binds.iter().filter_map(|b| {
+                    Some(MorphBind {
+                        mesh: gltf.nodes().nth(b.node as _)?.mesh()?.index(),
+                        target: b.index as _,
+                        weight: b.weight,
+                    })
+                }).collect();
+                (enm.clone(), binds)
+            }).collect();
+
+        let expression_info: HashMap<String, ExpressionInfo> = vrm.expressions.preset.iter()
+            .chain(vrm.expressions.custom.iter())
+            .map(|(enm, e)| (enm.clone(), ExpressionInfo {
+                is_binary: e.is_binary,
+                override_blink: Override::from_name(&e.override_blink),
+                override_look_at: Override::from_name(&e.override_look_at),
+                override_mouth: Override::from_name(&e.override_mouth),
+            })).collect();
+
+        let material_color_binds: HashMap<String, Vec<MaterialColorBind>> = vrm.expressions.preset.iter()
+            .chain(vrm.expressions.custom.iter())
+            .map(|(enm, e)| {
+                let binds = e.material_color_binds.iter().filter_map(|b| {
+                    let color = MaterialColor::from_name(&b.type_);
+                    if color.is_none() {
+                        log::warn!("expression {}: unsupported material color {}", enm, b.type_);
+                    }
+                    let t = |i: usize, d: f32| b.target_value.get(i).copied().unwrap_or(d);
+                    Some(MaterialColorBind {
+                        material: b.material as _,
+                        color: color?,
+                        target: glam::Vec4::new(t(0, 0.0), t(1, 0.0), t(2, 0.0), t(3, 1.0)),
+                    })
+                }).collect();
+                (enm.clone(), binds)
+            }).collect();
+
+        let texture_transform_binds: HashMap<String, Vec<TextureTransformBind>> = vrm.expressions.preset.iter()
+            .chain(vrm.expressions.custom.iter())
+            .map(|(enm, e)| {
+                let binds = e.texture_transform_binds.iter().map(|b| {
+                    let v = |xs: &Vec<f32>, d: f32| glam::Vec2::new(
+                        xs.get(0).copied().unwrap_or(d),
+                        xs.get(1).copied().unwrap_or(d),
+                    );
+                    TextureTransformBind {
+                        material: b.material as _,
+                        scale: v(&b.scale, 1.0),
+                        offset: v(&b.offset, 0.0),
+                    }
+                }).collect();
+                (enm.clone(), binds)
             }).collect();
 
         let bufs: Vec<(gl::types::GLuint, &gltf::buffer::Data)> = buffers.iter().map(|b| {
@@ -169,6 +889,8 @@ impl Scene {
             }
         }).collect();
 
+        let mut attrib_bufs = Vec::new();
+
         let tids: Vec<gl::types::GLuint> = images.iter().map(|i| {
             unsafe {
                 let mut texture: gl::types::GLuint = 0;
@@ -212,63 +934,58 @@ impl Scene {
             }
         }).collect();
 
+        // primitives without a material use the glTF default one, kept after the document's materials
+        let default_material_index = gltf.materials().len();
+        let default_material = gltf.meshes().flat_map(|m| m.primitives()).map(|p| p.material()).find(|m| m.index().is_none());
+        let buffer_data: Vec<&[u8]> = buffers.iter().map(|b| &*b.0).collect();
         let meshes = gltf.meshes().map(|m| {
             let primitives = m.primitives().filter_map(|p| {
                 log::info!("begin primitive");
-                let mode = match p.mode() {
-                    gltf::mesh::Mode::Points => gl::POINTS,
-                    gltf::mesh::Mode::Lines => gl::LINES,
-                    gltf::mesh::Mode::LineLoop => gl::LINE_LOOP,
-                    gltf::mesh::Mode::LineStrip => gl::LINE_STRIP,
-                    gltf::mesh::Mode::Triangles => gl::TRIANGLES,
-                    gltf::mesh::Mode::TriangleStrip => gl::TRIANGLE_STRIP,
-                    gltf::mesh::Mode::TriangleFan => gl::TRIANGLE_FAN,
+                let layout = match PrimitiveLayout::new(&p, &buffer_data, default_material_index, *legacy) {
+                    Some(l) => l,
+                    None => {
+                        log::warn!(
+                            "mesh {} {:?} primitive {}: an index or attribute accessor can't be read, skipping it",
+                            m.index(), m.name(), p.index(),
+                        );
+                        return None;
+                    },
                 };
                 unsafe {
                     let mut vao: gl::types::GLuint = 0;
                     gl::GenVertexArrays(1, &mut vao as *mut gl::types::GLuint);
                     gl::BindVertexArray(vao);
 
-                    let indices_accessor = p.indices()?;
-                    let indices_view = indices_accessor.view()?;
-                    let indices_buf = bufs.get(indices_view.buffer().index())?.0;
-                    gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, indices_buf);
-
-                    for (semantic, accessor) in p.attributes() {
-                        let mattrib = match semantic {
-                            gltf::Semantic::Positions => Some(utils::ATTRIB_VERTEX),
-                            gltf::Semantic::Normals => Some(utils::ATTRIB_NORMAL),
-                            gltf::Semantic::TexCoords(0) => Some(utils::ATTRIB_TEXCOORD),
-                            gltf::Semantic::Joints(0) => Some(utils::ATTRIB_JOINT),
-                            gltf::Semantic::Weights(0) => Some(utils::ATTRIB_WEIGHT),
-                            _ => None,
-                        };
-                        if let Some(attrib) = mattrib {
-                            Self::initialize_attrib(attrib, &accessor, &bufs);
-                        }
-                    }
-
-                    for (_enm, (expr, mesh, attrib)) in &expressions {
-                        if m.index() == mesh.index() {
-                            let target = p.morph_targets().nth(expr.index as _).unwrap();
-                            Self::initialize_attrib(*attrib, &target.positions().unwrap(), &bufs);
-                        }
+                    // (count, type, byte offset) of the indices, if the primitive has any
+                    let indices = match &layout.indices {
+                        Some((count, a)) => {
+                            let (offset, created) = Self::bind_accessor(gl::ELEMENT_ARRAY_BUFFER, a, &bufs)?;
+                            attrib_bufs.extend(created);
+                            Some((*count, a.data_type, offset))
+                        },
+                        None => None,
+                    };
+                    for (attrib, a) in &layout.attribs {
+                        attrib_bufs.extend(Self::initialize_attrib(*attrib, a, &bufs)?);
                     }
+                    let morph_buffer = if layout.morph_deltas.is_empty() {
+                        None
+                    } else {
+                        let buf = Self::upload_buffer(gl::SHADER_STORAGE_BUFFER, &layout.morph_deltas);
+                        gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, 0);
+                        Some(buf)
+                    };
+                    attrib_bufs.extend(morph_buffer);
 
                     Some(Primitive {
                         vao,
-                        mode,
-                        count: indices_accessor.count() as _,
-                        index_type: match indices_accessor.data_type() {
-                            gltf::accessor::DataType::I8 => gl::BYTE,
-                            gltf::accessor::DataType::U8 => gl::UNSIGNED_BYTE,
-                            gltf::accessor::DataType::I16 => gl::SHORT,
-                            gltf::accessor::DataType::U16 => gl::UNSIGNED_SHORT,
-                            gltf::accessor::DataType::U32 => gl::UNSIGNED_INT,
-                            gltf::accessor::DataType::F32 => gl::FLOAT,
-                        },
-                        index_offset: indices_view.offset() as _,
-                        material_index: p.material().index().unwrap(),
+                        mode: layout.mode,
+                        count: indices.map_or(layout.vertex_count, |(c, _, _)| c) as _,
+                        indices: indices.map(|(_, t, o)| (t, o as _)),
+                        material_index: layout.material_index,
+                        morph_buffer,
+                        vertex_count: layout.vertex_count as _,
+                        center: layout.center,
                     })
                 }
             }).collect();
@@ -280,55 +997,156 @@ impl Scene {
         let skins = gltf.skins().map(|s| {
             let get_buffer_data = |buffer: gltf::Buffer| bufs.get(buffer.index()).map(|x| &*x.1.0);
             Skin {
-                inverse_bind_matrices: s.reader(get_buffer_data).read_inverse_bind_matrices().unwrap().map(|m| glam::Mat4::from_cols_array_2d(&m)).collect(),
+                inverse_bind_matrices: s.reader(get_buffer_data).read_inverse_bind_matrices().unwrap()
+                    .map(|m| flip(glam::Mat4::from_cols_array_2d(&m)))
+                    .collect(),
                 joints: s.joints().map(|j| j.index()).collect(),
             }
         }).collect();
 
-        let materials = gltf.materials().map(|m| {
+        let white_texture = unsafe {
+            let mut tid: gl::types::GLuint = 0;
+            gl::GenTextures(1, &mut tid as *mut gl::types::GLuint);
+            gl::BindTexture(gl::TEXTURE_2D, tid);
+            let pixel: [u8; 4] = [255, 255, 255, 255];
+            gl::TexImage2D(
+                gl::TEXTURE_2D, 0, gl::RGBA as _, 1, 1, 0, gl::RGBA, gl::UNSIGNED_BYTE,
+                pixel.as_ptr() as *const std::ffi::c_void,
+            );
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
+            tid
+        };
+
+        let texture = |t: gltf::Texture, tex_coord: u32, transform: glam::Mat3| {
+            let sampler = t.sampler();
+            let tid = *tids.get(t.source().index())?;
+            unsafe {
+                gl::BindTexture(gl::TEXTURE_2D, tid);
+                gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, sampler.wrap_s().as_gl_enum() as i32);
+                gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, sampler.wrap_t().as_gl_enum() as i32);
+                if let Some(min_filter) = sampler.min_filter() {
+                    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, min_filter.as_gl_enum() as i32);
+                }
+                if let Some(mag_filter) = sampler.mag_filter() {
+                    gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, mag_filter.as_gl_enum() as i32);
+                }
+            }
+            Some(Texture {
+                tid,
+                tex_coord,
+                transform,
+            })
+        };
+        // the texCoord and matrix a KHR_texture_transform asks for, given the texture info's own texCoord
+        let transformed = |tt: Option<gltf::texture::TextureTransform>, tex_coord: u32| match tt {
+            Some(tt) => (tt.tex_coord().unwrap_or(tex_coord), Texture::uv_transform(tt.offset(), tt.rotation(), tt.scale())),
+            None => (tex_coord, glam::Mat3::IDENTITY),
+        };
+        let json_transformed = |tt: Option<&gltf::json::extensions::texture::TextureTransform>, tex_coord: u32| match tt {
+            Some(tt) => (tt.tex_coord.unwrap_or(tex_coord), Texture::uv_transform(tt.offset.0, tt.rotation.0, tt.scale.0)),
+            None => (tex_coord, glam::Mat3::IDENTITY),
+        };
+        let info_texture = |i: gltf::texture::Info| {
+            let (tex_coord, transform) = transformed(i.texture_transform(), i.tex_coord());
+            texture(i.texture(), tex_coord, transform)
+        };
+        let json_texture = |i: &gltf::json::texture::Info| {
+            let tt = i.extensions.as_ref().and_then(|e| e.texture_transform.as_ref());
+            let (tex_coord, transform) = json_transformed(tt, i.tex_coord);
+            gltf.textures().nth(i.index.value()).and_then(|t| texture(t, tex_coord, transform))
+        };
+
+        let materials = gltf.materials().chain(default_material).map(|m| {
             let pbr = m.pbr_metallic_roughness();
             let [bcr, bcg, bcb, bca] = pbr.base_color_factor();
             let [emx, emy, emz] = m.emissive_factor();
+            let mtoon = m.mtoon().map(|t| {
+                // the shading shift texture is a texture info with a scale, which json_texture doesn't need
+                let shading_shift_texture = t.shading_shift_texture.as_ref().and_then(|i| json_texture(&gltf::json::texture::Info {
+                    index: i.index,
+                    tex_coord: i.tex_coord,
+                    extensions: i.extensions.clone(),
+                    extras: Default::default(),
+                }));
+                let matcap_texture = t.matcap_texture.as_ref().and_then(json_texture);
+                MToon {
+                    shade_multiply_texture: t.shade_multiply_texture.as_ref().and_then(json_texture),
+                    shading_shift_factor: t.shading_shift_factor,
+                    shading_shift_texture_scale: match &shading_shift_texture {
+                        Some(_) => t.shading_shift_texture.as_ref().map_or(0.0, |i| i.scale),
+                        None => 0.0,
+                    },
+                    shading_shift_texture,
+                    shading_toony_factor: t.shading_toony_factor,
+                    gi_equalization_factor: t.gi_equalization_factor,
+                    // without a matcap texture there is no matcap at all
+                    matcap_factor: if matcap_texture.is_some() { glam::Vec3::from(t.matcap_factor) } else { glam::Vec3::ZERO },
+                    matcap_texture,
+                    parametric_rim_color_factor: glam::Vec3::from(t.parametric_rim_color_factor),
+                    rim_multiply_texture: t.rim_multiply_texture.as_ref().and_then(json_texture),
+                    rim_lighting_mix_factor: t.rim_lighting_mix_factor,
+                    parametric_rim_fresnel_power_factor: t.parametric_rim_fresnel_power_factor,
+                    parametric_rim_lift_factor: t.parametric_rim_lift_factor,
+                    outline_width_mode: match t.outline_width_mode.as_str() {
+                        "worldCoordinates" => OutlineWidthMode::World,
+                        "screenCoordinates" => OutlineWidthMode::Screen,
+                        _ => OutlineWidthMode::None,
+                    },
+                    outline_width_factor: t.outline_width_factor,
+                    outline_width_multiply_texture: t.outline_width_multiply_texture.as_ref().and_then(json_texture),
+                    outline_color_factor: glam::Vec3::from(t.outline_color_factor),
+                    outline_lighting_mix_factor: t.outline_lighting_mix_factor,
+                }
+            });
             Material {
                 base_color_factor: glam::Vec4::new(bcr, bcg, bcb, bca),
-                base_color_texture: pbr.base_color_texture().and_then(|t| {
-                    let sampler = t.texture().sampler();
-                    let tid = *tids.get(t.texture().source().index())?;
-                    unsafe {
-                        gl::BindTexture(gl::TEXTURE_2D, tid);
-                        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, sampler.wrap_s().as_gl_enum() as i32);
-                        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, sampler.wrap_t().as_gl_enum() as i32);
-                        if let Some(min_filter) = sampler.min_filter() {
-                            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, min_filter.as_gl_enum() as i32);
-                        }
-                        if let Some(mag_filter) = sampler.mag_filter() {
-                            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, mag_filter.as_gl_enum() as i32);
-                        }
-                    }
-                    Some(Texture {
-                        tid,
-                    })
-                }),
+                base_color_texture: pbr.base_color_texture().and_then(info_texture),
                 metallic_factor: pbr.metallic_factor(),
                 roughness_factor: pbr.roughness_factor(),
-                metallic_roughness_texture: None,
-                normal_texture: None,
-                occlusion_texture: None,
+                metallic_roughness_texture: pbr.metallic_roughness_texture().and_then(info_texture),
+                normal_texture: m.normal_texture().and_then(|t| {
+                    let (tex_coord, transform) = transformed(t.texture_transform(), t.tex_coord());
+                    texture(t.texture(), tex_coord, transform)
+                }),
+                normal_scale: m.normal_texture().map_or(1.0, |t| t.scale()),
+                occlusion_texture: m.occlusion_texture().and_then(|t| {
+                    let (tex_coord, transform) = transformed(t.texture_transform(), t.tex_coord());
+                    texture(t.texture(), tex_coord, transform)
+                }),
+                occlusion_strength: m.occlusion_texture().map_or(1.0, |t| t.strength()),
+                alpha_mode: m.alpha_mode(),
+                alpha_cutoff: m.alpha_cutoff().unwrap_or(0.5),
+                double_sided: m.double_sided(),
                 emissive_factor: glam::Vec3::new(emx, emy, emz),
-                emissive_texture: None,
+                emissive_texture: m.emissive_texture().and_then(info_texture),
+                shade_color_factor: m.mtoon().map_or(glam::Vec3::ZERO, |t| glam::Vec3::from(t.shade_color_factor)),
+                mtoon,
             }
         }).collect();
 
-        let nodes = gltf.nodes().map(|n| {
+        let nodes: Vec<Node> = gltf.nodes().map(|n| {
             log::info!("node {} {:?}: {:?}", n.index(), n.name(), n.skin());
             Node {
                 child_indices: n.children().map(|c| c.index()).collect(),
                 mesh_index: n.mesh().map(|m| m.index()),
                 skin_index: n.skin().map(|s| s.index()),
-                transform: glam::Mat4::from_cols_array_2d(&n.transform().matrix()),
+                transform: flip(glam::Mat4::from_cols_array_2d(&n.transform().matrix())),
             }
         }).collect();
 
+        let mut parent_indices = vec![None; nodes.len()];
+        for (i, n) in nodes.iter().enumerate() {
+            for ci in &n.child_indices {
+                parent_indices[*ci] = Some(i);
+            }
+        }
+
+        let constraints = Self::order_constraints(
+            gltf.nodes().filter_map(|n| Constraint::from_json(n.index(), n.constraint()?)).collect(),
+            &parent_indices,
+        );
+
         let bone_node_indices = vrm.humanoid.human_bones.iter().map(|(nm, b)| (nm.clone(), b.node as _)).collect();
 
         let scene_node_indices = gltf.default_scene().unwrap().nodes().map(|n| n.index()).collect();
@@ -338,11 +1156,53 @@ impl Scene {
             skins,
             materials,
             nodes,
+            parent_indices,
+            constraints,
             bone_node_indices,
             scene_node_indices,
+            look_at: vrm.look_at.clone().unwrap_or_default(),
+            spring_bone: spring_bone.clone(),
+            expressions,
+            expression_info,
+            material_color_binds,
+            texture_transform_binds,
+            buffers: bufs.iter().map(|(b, _)| *b).chain(attrib_bufs).collect(),
+            textures: tids.into_iter().chain(std::iter::once(white_texture)).collect(),
+            white_texture: Texture { tid: white_texture, tex_coord: 0, transform: glam::Mat3::IDENTITY },
         }
     }
 
+    /// Bare nodes with no meshes, materials or extensions, for testing posing without a GL context.
+    /// The scene owns no GL objects, but dropping it would still call into GL, so it never is.
+    #[cfg(test)]
+    pub fn skeleton(nodes: Vec<Node>) -> std::mem::ManuallyDrop<Self> {
+        let mut parent_indices = vec![None; nodes.len()];
+        for (i, n) in nodes.iter().enumerate() {
+            for ci in &n.child_indices {
+                parent_indices[*ci] = Some(i);
+            }
+        }
+        std::mem::ManuallyDrop::new(Self {
+            meshes: Vec::new(),
+            skins: Vec::new(),
+            materials: Vec::new(),
+            scene_node_indices: (0..nodes.len()).filter(|i| parent_indices[*i].is_none()).collect(),
+            nodes,
+            parent_indices,
+            constraints: Vec::new(),
+            bone_node_indices: HashMap::new(),
+            look_at: Default::default(),
+            spring_bone: Default::default(),
+            expressions: HashMap::new(),
+            expression_info: HashMap::new(),
+            material_color_binds: HashMap::new(),
+            texture_transform_binds: HashMap::new(),
+            buffers: Vec::new(),
+            textures: Vec::new(),
+            white_texture: Texture { tid: 0, tex_coord: 0, transform: glam::Mat3::IDENTITY },
+        })
+    }
+
     fn compute_global_transforms_from(&self, transforms: &mut Vec<glam::Mat4>, nodes: &Vec<Node>, mat: &glam::Mat4, node_index: usize) {
         let node = &nodes[node_index];
         let newmat = mat.mul_mat4(&node.transform);
@@ -357,12 +1217,206 @@ impl Scene {
         for ni in &self.scene_node_indices {
             self.compute_global_transforms_from(&mut global_transforms, &nodes, &mat, *ni);
         }
+        // constrained local rotations so far, read back when a later constraint uses one as its source
+        let mut constrained: HashMap<usize, glam::Quat> = HashMap::with_capacity(self.constraints.len());
+        for c in &self.constraints {
+            let parent = self.parent_indices[c.node].map_or(*mat, |p| global_transforms[p]);
+            let (scale, rotation, translation) = nodes[c.node].transform.to_scale_rotation_translation();
+            let local_rotation = |i: usize| constrained.get(&i).copied()
+                .unwrap_or_else(|| nodes[i].transform.to_scale_rotation_translation().1);
+            let rotation = self.evaluate_constraint(c, &local_rotation, nodes, &global_transforms, &parent).unwrap_or(rotation);
+            constrained.insert(c.node, rotation);
+            // constraints inside this subtree come later, so recomputing it from the unconstrained locals loses nothing
+            let global = parent.mul_mat4(&glam::Mat4::from_scale_rotation_translation(scale, rotation, translation));
+            global_transforms[c.node] = global;
+            for ci in &nodes[c.node].child_indices {
+                self.compute_global_transforms_from(&mut global_transforms, nodes, &global, *ci);
+            }
+        }
         global_transforms
     }
 
-    pub fn render_node(&self, ctx: &context::Context, shader: &shader::Shader, global_transforms: &Vec<glam::Mat4>, node_index: usize) {
-        let node = &self.nodes[node_index];
-        let transform = &global_transforms[node_index];
+    /// The constrained node's new local rotation, following the formulas in the VRMC_node_constraint spec.
+    fn evaluate_constraint(
+        &self,
+        c: &Constraint,
+        local_rotation: &dyn Fn(usize) -> glam::Quat,
+        nodes: &Vec<Node>,
+        global_transforms: &Vec<glam::Mat4>,
+        parent: &glam::Mat4,
+    ) -> Option<glam::Quat> {
+        let rest = |i: usize| self.nodes[i].transform.to_scale_rotation_translation().1;
+        let (dst_rest, src_rest) = (rest(c.node), rest(c.source));
+        let src = local_rotation(c.source);
+        let target = match c.kind {
+            ConstraintKind::Rotation => dst_rest * src_rest.inverse() * src,
+            ConstraintKind::Roll(axis) => {
+                // the source's change from rest, seen from the destination's rest frame
+                let delta = dst_rest.inverse() * src * src_rest.inverse() * dst_rest;
+                let to = delta * axis;
+                dst_rest * glam::Quat::from_rotation_arc(axis, to.normalize()).inverse() * delta
+            },
+            ConstraintKind::Aim(axis) => {
+                let (_, parent_rotation, _) = parent.to_scale_rotation_translation();
+                let from = parent_rotation * dst_rest * axis;
+                let dst_pos = parent.transform_point3(nodes[c.node].transform.w_axis.truncate());
+                let to = (global_transforms[c.source].w_axis.truncate() - dst_pos).try_normalize()?;
+                parent_rotation.inverse() * glam::Quat::from_rotation_arc(from.normalize(), to) * parent_rotation * dst_rest
+            },
+        };
+        Some(dst_rest.slerp(target.normalize(), c.weight))
+    }
+
+    /// Sort constraints so that sources, and the ancestors of sources and destinations, are
+    /// constrained first. Constraints in a dependency cycle are dropped, and so is every constraint
+    /// that depends on a dropped one, whichever constraint the walk starts from.
+    fn order_constraints(constraints: Vec<Constraint>, parent_indices: &Vec<Option<usize>>) -> Vec<Constraint> {
+        let by_node: HashMap<usize, usize> = constraints.iter().enumerate().map(|(i, c)| (c.node, i)).collect();
+        let deps = |c: &Constraint| {
+            let mut out = Vec::new();
+            let mut walk = |mut n: Option<usize>| {
+                while let Some(i) = n {
+                    out.extend(by_node.get(&i).copied());
+                    n = parent_indices[i];
+                }
+            };
+            walk(Some(c.source));
+            walk(parent_indices[c.node]);
+            out
+        };
+        #[derive(Clone, Copy, PartialEq)]
+        enum State { Unvisited, OnStack, InCycle, Done, Dropped }
+        struct Walk<'a> {
+            cs: &'a Vec<Constraint>,
+            deps: &'a dyn Fn(&Constraint) -> Vec<usize>,
+            state: Vec<State>,
+            stack: Vec<usize>,
+            out: Vec<Constraint>,
+        }
+        // returns whether constraint i is kept
+        fn visit(w: &mut Walk, i: usize) -> bool {
+            match w.state[i] {
+                State::Done => return true,
+                State::Dropped => return false,
+                State::OnStack | State::InCycle => {
+                    // everything pushed since i depends on i, and i on it
+                    let start = w.stack.iter().position(|s| *s == i).unwrap_or(0);
+                    for s in &w.stack[start..] {
+                        w.state[*s] = State::InCycle;
+                    }
+                    return false;
+                },
+                State::Unvisited => {},
+            }
+            w.state[i] = State::OnStack;
+            w.stack.push(i);
+            let mut failed = None;
+            for d in (w.deps)(&w.cs[i]) {
+                if d != i && !visit(w, d) {
+                    failed = Some(d);
+                    break;
+                }
+            }
+            w.stack.pop();
+            match failed {
+                None => {
+                    w.state[i] = State::Done;
+                    w.out.push(w.cs[i]);
+                    true
+                },
+                Some(d) => {
+                    if w.state[i] == State::InCycle {
+                        log::warn!("node constraint on node {} is part of a cycle, ignoring it", w.cs[i].node);
+                    } else {
+                        log::warn!(
+                            "node constraint on node {} depends on the ignored constraint on node {}, ignoring it",
+                            w.cs[i].node, w.cs[d].node,
+                        );
+                    }
+                    w.state[i] = State::Dropped;
+                    false
+                },
+            }
+        }
+        let mut w = Walk {
+            cs: &constraints,
+            deps: &deps,
+            state: vec![State::Unvisited; constraints.len()],
+            stack: Vec::new(),
+            out: Vec::new(),
+        };
+        for i in 0..constraints.len() {
+            visit(&mut w, i);
+        }
+        w.out
+    }
+
+    /// Bind a material's textures with their UV sets and transforms, and its MToon parameters.
+    fn bind_material(&self, shader: &shader::Shader, m: &Material) {
+        let none = None;
+        let mtoon = |f: fn(&MToon) -> &Option<Texture>| m.mtoon.as_ref().map_or(&none, f);
+        let textures = [
+            (utils::TEXUNIT_BASE_COLOR, &m.base_color_texture),
+            (utils::TEXUNIT_EMISSIVE, &m.emissive_texture),
+            (utils::TEXUNIT_SHADE_MULTIPLY, mtoon(|t| &t.shade_multiply_texture)),
+            (utils::TEXUNIT_SHADING_SHIFT, mtoon(|t| &t.shading_shift_texture)),
+            (utils::TEXUNIT_MATCAP, mtoon(|t| &t.matcap_texture)),
+            (utils::TEXUNIT_RIM_MULTIPLY, mtoon(|t| &t.rim_multiply_texture)),
+            (utils::TEXUNIT_OUTLINE_WIDTH, mtoon(|t| &t.outline_width_multiply_texture)),
+            (utils::TEXUNIT_METALLIC_ROUGHNESS, &m.metallic_roughness_texture),
+            (utils::TEXUNIT_NORMAL, &m.normal_texture),
+            (utils::TEXUNIT_OCCLUSION, &m.occlusion_texture),
+        ];
+        let mut texcoord_sets = [0; utils::TEXUNIT_COUNT];
+        let mut texture_transforms = [0.0; 9 * utils::TEXUNIT_COUNT];
+        for (unit, t) in textures.iter() {
+            let t = t.as_ref().unwrap_or(&self.white_texture);
+            t.bind_unit(*unit);
+            let u = *unit as usize;
+            texcoord_sets[u] = t.tex_coord as i32;
+            texture_transforms[u * 9..u * 9 + 9].copy_from_slice(&t.transform.to_cols_array());
+        }
+        unsafe {
+            gl::Uniform1iv(shader.uniform_texcoord_sets, utils::TEXUNIT_COUNT as _, texcoord_sets.as_ptr());
+            gl::UniformMatrix3fv(shader.uniform_texture_transforms, utils::TEXUNIT_COUNT as _, false as u8, texture_transforms.as_ptr());
+        }
+        unsafe {
+            gl::Uniform1f(shader.uniform_metallic, m.metallic_factor);
+            gl::Uniform1f(shader.uniform_roughness, m.roughness_factor);
+            // a white normal map with zero scale leaves normals as they are
+            gl::Uniform1f(shader.uniform_normal_scale, if m.normal_texture.is_some() { m.normal_scale } else { 0.0 });
+            gl::Uniform1f(shader.uniform_occlusion_strength, m.occlusion_strength);
+            gl::Uniform1i(shader.uniform_alpha_mode, match m.alpha_mode {
+                AlphaMode::Opaque => 0,
+                AlphaMode::Mask => 1,
+                AlphaMode::Blend => 2,
+            });
+            gl::Uniform1f(shader.uniform_alpha_cutoff, m.alpha_cutoff);
+            match &m.mtoon {
+                Some(t) => {
+                    gl::Uniform1i(shader.uniform_mtoon, 1);
+                    gl::Uniform1f(shader.uniform_shading_shift, t.shading_shift_factor);
+                    gl::Uniform1f(shader.uniform_shading_shift_scale, t.shading_shift_texture_scale);
+                    gl::Uniform1f(shader.uniform_shading_toony, t.shading_toony_factor);
+                    gl::Uniform1f(shader.uniform_gi_equalization, t.gi_equalization_factor);
+                    gl::Uniform1f(shader.uniform_rim_lighting_mix, t.rim_lighting_mix_factor);
+                    gl::Uniform1f(shader.uniform_rim_fresnel_power, t.parametric_rim_fresnel_power_factor);
+                    gl::Uniform1f(shader.uniform_rim_lift, t.parametric_rim_lift_factor);
+                    gl::Uniform1i(shader.uniform_outline_width_mode, match t.outline_width_mode {
+                        OutlineWidthMode::None => 0,
+                        OutlineWidthMode::World => 1,
+                        OutlineWidthMode::Screen => 2,
+                    });
+                    gl::Uniform1f(shader.uniform_outline_width, t.outline_width_factor);
+                    gl::Uniform1f(shader.uniform_outline_lighting_mix, t.outline_lighting_mix_factor);
+                },
+                None => gl::Uniform1i(shader.uniform_mtoon, 0),
+            }
+        }
+    }
+
+    /// Skinning matrices for a node's skin, or identities.
+    fn joint_matrices(&self, global_transforms: &Vec<glam::Mat4>, node: &Node) -> Vec<f32> {
         let mut joint_matrices = vec![glam::Mat4::IDENTITY; 256];
         if let Some(skin) = node.skin_index.and_then(|i| self.skins.get(i)) {
             for (idx, ni) in skin.joints.iter().enumerate() {
@@ -371,29 +1425,410 @@ impl Scene {
                 joint_matrices[idx] = jointnode_transform.mul_mat4(&ibm);
             }
         }
-        if let Some(m) = node.mesh_index.and_then(|i| self.meshes.get(i)) {
-            for p in &m.primitives {
-                if let Some(tex) = self.materials.get(p.material_index).and_then(|m| m.base_color_texture.as_ref()) {
-                    tex.bind(ctx);
-                }
-                unsafe {
-                    gl::UniformMatrix4fv(shader.uniform_position, 1, false as u8, transform.to_cols_array().as_ptr());
-                    gl::UniformMatrix4fv(
-                        shader.uniform_joint_matrices, 256, false as u8,
-                        joint_matrices.iter().map(|m| m.to_cols_array()).flatten().collect::<Vec<f32>>().as_ptr());
-                    gl::BindVertexArray(p.vao);
-                    gl::DrawElements(p.mode, p.count, p.index_type, p.index_offset as _);
+        joint_matrices.iter().map(|m| m.to_cols_array()).flatten().collect()
+    }
+
+    /// The morph targets to apply to a mesh, strongest first so the ones dropped past MAX_MORPHS matter least.
+    fn active_morphs(morph_weights: &HashMap<usize, HashMap<usize, f32>>, mesh_index: usize) -> (Vec<i32>, Vec<f32>) {
+        let mut active: Vec<(usize, f32)> = morph_weights.get(&mesh_index)
+            .map(|ws| ws.iter().map(|(t, w)| (*t, *w)).filter(|(_, w)| w.abs() > 0.001).collect())
+            .unwrap_or_default();
+        active.sort_by(|a, b| b.1.abs().partial_cmp(&a.1.abs()).unwrap_or(std::cmp::Ordering::Equal));
+        active.truncate(utils::MAX_MORPHS);
+        (active.iter().map(|(t, _)| *t as i32).collect(), active.iter().map(|(_, w)| *w).collect())
+    }
+
+    fn is_blended(&self, p: &Primitive) -> bool {
+        self.materials.get(p.material_index).map_or(false, |m| m.alpha_mode == AlphaMode::Blend)
+    }
+
+    /// Draw a node's opaque and masked primitives, and those of its children. Blended primitives are
+    /// collected into `blended` as (distance from camera, node, mesh, primitive) to be drawn last, and
+    /// their nodes' joint matrices and morphs into `deferred` so they aren't computed again.
+    fn render_node(
+        &self,
+        ctx: &context::Context,
+        shader: &shader::Shader,
+        frame: &Frame,
+        blended: &mut Vec<(f32, usize, usize, usize)>,
+        deferred: &mut HashMap<usize, NodeDraw>,
+        node_index: usize,
+    ) {
+        let node = &self.nodes[node_index];
+        if let Some((mi, m)) = node.mesh_index.and_then(|i| Some((i, self.meshes.get(i)?))) {
+            let joint_matrices = self.joint_matrices(frame.global_transforms, node);
+            let morphs = Self::active_morphs(&frame.morph_weights, mi);
+            // Skinned meshes ignore their node's transform. Their sort center follows the skin's first
+            // joint through its bind pose, which is only an approximation for vertices other joints move.
+            let to_world = match node.skin_index.and_then(|i| self.skins.get(i)).filter(|s| !s.joints.is_empty()) {
+                Some(s) => frame.global_transforms[s.joints[0]].mul_mat4(&s.inverse_bind_matrices[0]),
+                None => frame.global_transforms[node_index],
+            };
+            let mut any_blended = false;
+            for (pi, p) in m.primitives.iter().enumerate() {
+                if self.is_blended(p) {
+                    let center = to_world.transform_point3(p.center);
+                    blended.push((center.distance(frame.camera_pos), node_index, mi, pi));
+                    any_blended = true;
+                } else {
+                    self.draw_primitive(ctx, shader, frame, node_index, &joint_matrices, &morphs, p);
                 }
             }
+            if any_blended {
+                deferred.insert(node_index, (joint_matrices, morphs));
+            }
         }
         for ci in &node.child_indices {
-            self.render_node(ctx, shader, global_transforms, *ci);
+            self.render_node(ctx, shader, frame, blended, deferred, *ci);
         }
     }
 
-    pub fn render(&self, ctx: &context::Context, shader: &shader::Shader, global_transforms: &Vec<glam::Mat4>) {
+    fn draw_primitive(
+        &self,
+        ctx: &context::Context,
+        shader: &shader::Shader,
+        frame: &Frame,
+        node_index: usize,
+        joint_matrices: &Vec<f32>,
+        (indices, weights): &(Vec<i32>, Vec<f32>),
+        p: &Primitive,
+    ) {
+        // skinned meshes ignore their node's transform; the joint matrices place them
+        let transform = match self.nodes[node_index].skin_index {
+            Some(_) => glam::Mat4::IDENTITY,
+            None => frame.global_transforms[node_index],
+        };
+        let material = self.materials.get(p.material_index);
+        material.and_then(|m| m.base_color_texture.as_ref()).unwrap_or(&self.white_texture).bind(ctx);
+        if let Some(m) = material {
+            self.bind_material(shader, m);
+        }
+        let mtoon = material.and_then(|m| m.mtoon.as_ref());
+        unsafe {
+            gl::UniformMatrix4fv(shader.uniform_position, 1, false as u8, transform.to_cols_array().as_ptr());
+            gl::UniformMatrix4fv(shader.uniform_joint_matrices, 256, false as u8, joint_matrices.as_ptr());
+            if let Some(st) = frame.materials.get(p.material_index) {
+                gl::Uniform4fv(shader.uniform_base_color, 1, st.base_color.to_array().as_ptr());
+                gl::Uniform3fv(shader.uniform_emission, 1, st.emission.to_array().as_ptr());
+                gl::Uniform3fv(shader.uniform_shade_color, 1, st.shade.to_array().as_ptr());
+                gl::Uniform2fv(shader.uniform_uv_scale, 1, st.uv_scale.to_array().as_ptr());
+                gl::Uniform2fv(shader.uniform_uv_offset, 1, st.uv_offset.to_array().as_ptr());
+                gl::Uniform3fv(shader.uniform_matcap_color, 1, st.matcap.to_array().as_ptr());
+                gl::Uniform3fv(shader.uniform_rim_color, 1, st.rim.to_array().as_ptr());
+                gl::Uniform3fv(shader.uniform_outline_color, 1, st.outline.to_array().as_ptr());
+            }
+            match p.morph_buffer {
+                Some(buf) => {
+                    gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, utils::SSBO_MORPHS, buf);
+                    gl::Uniform1i(shader.uniform_morph_count, indices.len() as _);
+                    gl::Uniform1i(shader.uniform_morph_vertex_count, p.vertex_count);
+                    gl::Uniform1iv(shader.uniform_morph_indices, indices.len() as _, indices.as_ptr());
+                    gl::Uniform1fv(shader.uniform_morph_weights, weights.len() as _, weights.as_ptr());
+                },
+                None => gl::Uniform1i(shader.uniform_morph_count, 0),
+            }
+            let double_sided = material.map_or(false, |m| m.double_sided);
+            if double_sided {
+                gl::Disable(gl::CULL_FACE);
+            } else {
+                gl::Enable(gl::CULL_FACE);
+                gl::CullFace(gl::BACK);
+            }
+            gl::BindVertexArray(p.vao);
+            p.draw();
+            let outlined = mtoon.map_or(false, |t| {
+                t.outline_width_mode != OutlineWidthMode::None && t.outline_width_factor > 0.0
+            });
+            if outlined {
+                gl::Uniform1i(shader.uniform_outline, 1);
+                gl::Enable(gl::CULL_FACE);
+                gl::CullFace(gl::FRONT);
+                p.draw();
+                gl::CullFace(gl::BACK);
+                gl::Uniform1i(shader.uniform_outline, 0);
+            }
+        }
+    }
+
+    pub fn render(
+        &self,
+        ctx: &context::Context,
+        shader: &shader::Shader,
+        global_transforms: &Vec<glam::Mat4>,
+        expression_weights: &HashMap<String, f32>,
+        look_weights: &HashMap<String, f32>,
+        camera_pos: &glam::Vec3,
+    ) {
+        let mixed = self.mix_expressions(expression_weights, look_weights);
+        let frame = Frame {
+            global_transforms,
+            morph_weights: self.morph_weights(&mixed),
+            materials: self.material_states(&mixed),
+            camera_pos: *camera_pos,
+        };
+        unsafe {
+            // missing WEIGHTS_1 and TANGENT must read as zero, not the default (0, 0, 0, 1)
+            gl::VertexAttrib4f(utils::ATTRIB_WEIGHT1, 0.0, 0.0, 0.0, 0.0);
+            gl::VertexAttrib4f(utils::ATTRIB_TANGENT, 0.0, 0.0, 0.0, 0.0);
+        }
+        let mut blended = Vec::new();
+        let mut deferred = HashMap::new();
         for ni in &self.scene_node_indices {
-            self.render_node(ctx, shader, global_transforms, *ni);
+            self.render_node(ctx, shader, &frame, &mut blended, &mut deferred, *ni);
+        }
+        // back to front, without depth writes so that blended surfaces don't hide each other
+        blended.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+        unsafe { gl::DepthMask(gl::FALSE); }
+        for (_, ni, mi, pi) in blended {
+            let (joint_matrices, morphs) = &deferred[&ni];
+            self.draw_primitive(ctx, shader, &frame, ni, joint_matrices, morphs, &self.meshes[mi].primitives[pi]);
+        }
+        unsafe {
+            gl::DepthMask(gl::TRUE);
+            // culling has to stay off outside of model rendering, libmpv breaks otherwise
+            gl::Disable(gl::CULL_FACE);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Needs a display for the hidden GL context: `cargo test -- --ignored`.
+    #[test]
+    #[ignore]
+    fn upload_and_drop_leaves_no_gl_objects() {
+        let ctx = context::Context::hidden();
+        // box_sparse.glb has no VRM extension or default scene, so give it what validation asks for
+        let (gltf, buffers, images) = fixture_with_images("box_sparse.glb");
+        let mut json = gltf.into_json();
+        json.scene = Some(gltf::json::Index::new(0));
+        let gltf = gltf::Document::from_json(json).unwrap();
+        let mut vrm = gltf::json::extensions::root::VrmcVrm::default();
+        vrm.humanoid.human_bones.insert("head".to_owned(), gltf::json::extensions::root::HumanBone { node: 0 });
+        let doc = Document { gltf, buffers, images, vrm, spring_bone: Default::default(), legacy: false };
+        doc.validate().unwrap();
+        let before = utils::GlObjects::count();
+        for _ in 0..8 {
+            drop(Scene::upload(&ctx, &doc));
+        }
+        assert_eq!(before, utils::GlObjects::count());
+    }
+
+    fn rotation(node: usize, source: usize) -> Constraint {
+        Constraint { node, source, kind: ConstraintKind::Rotation, weight: 1.0 }
+    }
+
+    #[test]
+    fn orders_sources_first_and_drops_cycles_with_their_dependents() {
+        // node 6 is the parent of 3; everything else is a root
+        let mut parent_indices = vec![None; 8];
+        parent_indices[3] = Some(6);
+        let constraints = vec![
+            rotation(1, 2), // cycle with the next one
+            rotation(2, 1),
+            rotation(6, 1), // depends on the cycle through its source
+            rotation(3, 7), // depends on the cycle through its parent
+            rotation(4, 5), // needs the next one first
+            rotation(5, 0),
+        ];
+        let expected = vec![rotation(5, 0), rotation(4, 5)];
+        assert_eq!(Scene::order_constraints(constraints.clone(), &parent_indices), expected);
+        let reversed = constraints.into_iter().rev().collect();
+        assert_eq!(Scene::order_constraints(reversed, &parent_indices), expected);
+    }
+
+    /// Node 0 is a source at the origin and node 1 a destination one unit along the X axis of its
+    /// rotated parent, node 3; node 2 is an aim target straight above the origin.
+    fn constrained_nodes() -> Vec<Node> {
+        let node = |child_indices: Vec<usize>, rotation: glam::Quat, translation: glam::Vec3| Node {
+            child_indices,
+            mesh_index: None,
+            skin_index: None,
+            transform: glam::Mat4::from_rotation_translation(rotation, translation),
+        };
+        vec![
+            node(vec![], glam::Quat::from_rotation_z(0.3), glam::Vec3::ZERO),
+            node(vec![], glam::Quat::from_rotation_x(0.2), glam::Vec3::X),
+            node(vec![], glam::Quat::IDENTITY, glam::Vec3::new(0.0, 2.0, 0.0)),
+            node(vec![1], glam::Quat::from_rotation_z(0.7), glam::Vec3::ZERO),
+        ]
+    }
+
+    /// The destination's constrained local rotation with node 0 posed to `source`.
+    fn evaluate(kind: ConstraintKind, weight: f32, source: glam::Quat) -> glam::Quat {
+        let scene = Scene::skeleton(constrained_nodes());
+        let mut nodes = scene.nodes.clone();
+        nodes[0].transform = glam::Mat4::from_quat(source);
+        let c = Constraint { node: 1, source: if matches!(kind, ConstraintKind::Aim(_)) { 2 } else { 0 }, kind, weight };
+        let globals = scene.compute_global_transforms(&nodes, &glam::Mat4::IDENTITY);
+        let local = |i: usize| nodes[i].transform.to_scale_rotation_translation().1;
+        scene.evaluate_constraint(&c, &local, &nodes, &globals, &globals[3]).unwrap()
+    }
+
+    #[test]
+    fn rotation_constraint_copies_change_from_rest() {
+        let (src_rest, dst_rest) = (glam::Quat::from_rotation_z(0.3), glam::Quat::from_rotation_x(0.2));
+        let change = glam::Quat::from_rotation_y(0.5);
+        let q = evaluate(ConstraintKind::Rotation, 1.0, src_rest * change);
+        assert!(q.abs_diff_eq(dst_rest * change, 1e-5));
+        let half = evaluate(ConstraintKind::Rotation, 0.5, src_rest * change);
+        assert!(half.abs_diff_eq(dst_rest * glam::Quat::from_rotation_y(0.25), 1e-5));
+        // an unposed source leaves the destination at rest
+        assert!(evaluate(ConstraintKind::Rotation, 1.0, src_rest).abs_diff_eq(dst_rest, 1e-5));
+    }
+
+    #[test]
+    fn roll_constraint_copies_only_the_twist() {
+        let (src_rest, dst_rest) = (glam::Quat::from_rotation_z(0.3), glam::Quat::from_rotation_x(0.2));
+        // swing about X then twist about Y, expressed in the destination's rest frame
+        let change = glam::Quat::from_rotation_x(0.4) * glam::Quat::from_rotation_y(0.5);
+        let source = dst_rest * change * dst_rest.inverse() * src_rest;
+        let q = evaluate(ConstraintKind::Roll(glam::Vec3::Y), 1.0, source);
+        assert!(q.abs_diff_eq(dst_rest * glam::Quat::from_rotation_y(0.5), 1e-5));
+    }
+
+    #[test]
+    fn aim_constraint_points_axis_at_source() {
+        for axis in [glam::Vec3::X, glam::Vec3::NEG_Y, glam::Vec3::Z] {
+            let q = evaluate(ConstraintKind::Aim(axis), 1.0, glam::Quat::IDENTITY);
+            let parent = glam::Quat::from_rotation_z(0.7);
+            let dst = parent * glam::Vec3::X;
+            let to = (glam::Vec3::new(0.0, 2.0, 0.0) - dst).normalize();
+            assert!((parent * q * axis).abs_diff_eq(to, 1e-5), "{}", axis);
+        }
+
+        // and the same through the global transforms, with the constraint in the scene
+        let mut scene = Scene::skeleton(constrained_nodes());
+        scene.constraints = vec![Constraint { node: 1, source: 2, kind: ConstraintKind::Aim(glam::Vec3::X), weight: 1.0 }];
+        let globals = scene.compute_global_transforms(&scene.nodes, &glam::Mat4::IDENTITY);
+        let aimed = globals[1].transform_vector3(glam::Vec3::X).normalize();
+        let to = (globals[2].w_axis - globals[1].w_axis).truncate().normalize();
+        assert!(aimed.abs_diff_eq(to, 1e-5));
+    }
+
+    fn fixture_with_images(name: &str) -> (gltf::Document, Vec<gltf::buffer::Data>, Vec<gltf::image::Data>) {
+        let path = format!("{}/deps/gltf-vrm/tests/{}", env!("CARGO_MANIFEST_DIR"), name);
+        gltf::import(path).unwrap()
+    }
+
+    fn fixture(name: &str) -> (gltf::Document, Vec<gltf::buffer::Data>) {
+        let (document, buffers, _) = fixture_with_images(name);
+        (document, buffers)
+    }
+
+    fn floats(bytes: &[u8]) -> Vec<f32> {
+        bytes.chunks(4).map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]])).collect()
+    }
+
+    fn first_primitive(document: &gltf::Document, buffers: &[&[u8]], legacy: bool) -> PrimitiveLayout {
+        let p = document.meshes().next().unwrap().primitives().next().unwrap();
+        PrimitiveLayout::new(&p, buffers, document.materials().len(), legacy).unwrap()
+    }
+
+    #[test]
+    fn lays_out_indexed_primitive_with_default_material_and_morph_target() {
+        for name in &["box_sparse.gltf", "box_sparse.glb"] {
+            let (document, buffers) = fixture(name);
+            let data: Vec<&[u8]> = buffers.iter().map(|b| &*b.0).collect();
+            let layout = first_primitive(&document, &data, false);
+            assert_eq!(layout.mode, gl::TRIANGLES);
+            let (count, indices) = layout.indices.as_ref().unwrap();
+            assert_eq!(*count, 36);
+            assert_eq!((indices.data_type, indices.size), (gl::UNSIGNED_INT, 1));
+            assert!(matches!(indices.source, AccessorSource::View { buffer: 0, stride: 0, .. }));
+            assert_eq!(layout.attribs.len(), 1);
+            let (attrib, positions) = &layout.attribs[0];
+            assert_eq!(*attrib, utils::ATTRIB_VERTEX);
+            assert_eq!((positions.data_type, positions.size, positions.normalized), (gl::FLOAT, 3, false));
+            assert!(matches!(positions.source, AccessorSource::View { buffer: 0, stride: 12, .. }));
+            assert_eq!(layout.vertex_count, 8);
+            // no materials in the document, so the default one comes right after them
+            assert_eq!(layout.material_index, 0);
+            assert_eq!(layout.center, glam::Vec3::ZERO);
+            // one target, two vec4s per vertex, no normal deltas
+            assert_eq!(layout.morph_deltas.len(), 16);
+            assert!(layout.morph_deltas.iter().skip(1).step_by(2).all(|d| *d == [0.0; 4]));
+            assert!(layout.morph_deltas.iter().step_by(2).any(|d| *d != [0.0; 4]));
+        }
+    }
+
+    #[test]
+    fn packs_sparse_scalar_accessor_without_view() {
+        let (document, buffers) = fixture("box_sparse.gltf");
+        let data: Vec<&[u8]> = buffers.iter().map(|b| &*b.0).collect();
+        let accessor = document.accessors().nth(2).unwrap();
+        assert!(accessor.view().is_none() && accessor.sparse().is_some());
+        let layout = AccessorLayout::new(&accessor, &data).unwrap();
+        assert_eq!((layout.data_type, layout.size, layout.normalized), (gl::FLOAT, 1, false));
+        match &layout.source {
+            AccessorSource::Packed(bytes) => assert_eq!(floats(bytes), vec![0.0, 1.0]),
+            s => panic!("expected packed data, got {:?}", s),
+        }
+    }
+
+    #[test]
+    fn lays_out_short_indices_and_bounds_center() {
+        let (document, buffers) = fixture("minimal_accessor_min_max.gltf");
+        let data: Vec<&[u8]> = buffers.iter().map(|b| &*b.0).collect();
+        let layout = first_primitive(&document, &data, false);
+        let (count, indices) = layout.indices.as_ref().unwrap();
+        assert_eq!(*count, 3);
+        assert_eq!(indices.data_type, gl::UNSIGNED_SHORT);
+        assert_eq!(indices.source, AccessorSource::View { buffer: 0, offset: 0, stride: 0 });
+        assert_eq!(layout.attribs[0].1.source, AccessorSource::View { buffer: 0, offset: 8, stride: 0 });
+        assert_eq!(layout.material_index, 0);
+        assert!(layout.morph_deltas.is_empty());
+        assert!(layout.center.abs_diff_eq(glam::Vec3::new(0.485, 0.485, -0.015), 1e-6));
+        let legacy = first_primitive(&document, &data, true);
+        assert!(legacy.center.abs_diff_eq(glam::Vec3::new(-0.485, 0.485, 0.015), 1e-6));
+    }
+
+    #[test]
+    fn lays_out_non_indexed_primitive_with_sparse_texcoords() {
+        // three positions, then a sparse TEXCOORD_0 without a view that sets only vertex 2
+        let json = br#"{
+            "asset": { "version": "2.0" },
+            "materials": [ {}, {} ],
+            "meshes": [ { "primitives": [ {
+                "attributes": { "POSITION": 0, "TEXCOORD_0": 1 },
+                "material": 1
+            } ] } ],
+            "buffers": [ { "byteLength": 48 } ],
+            "bufferViews": [
+                { "buffer": 0, "byteOffset": 0, "byteLength": 36 },
+                { "buffer": 0, "byteOffset": 36, "byteLength": 1 },
+                { "buffer": 0, "byteOffset": 40, "byteLength": 8 }
+            ],
+            "accessors": [
+                { "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                  "min": [ 0, 0, 0 ], "max": [ 1, 1, 0 ] },
+                { "componentType": 5126, "count": 3, "type": "VEC2",
+                  "sparse": { "count": 1,
+                              "indices": { "bufferView": 1, "componentType": 5121 },
+                              "values": { "bufferView": 2 } } }
+            ]
+        }"#;
+        let document = gltf::Gltf::from_slice(json).unwrap().document;
+        let mut buffer = Vec::new();
+        for f in &[0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0] {
+            buffer.extend_from_slice(&f.to_le_bytes());
+        }
+        buffer.extend_from_slice(&[2, 0, 0, 0]);
+        for f in &[0.25f32, 0.75] {
+            buffer.extend_from_slice(&f.to_le_bytes());
+        }
+        let layout = first_primitive(&document, &[&buffer], false);
+        assert!(layout.indices.is_none());
+        assert_eq!(layout.vertex_count, 3);
+        assert_eq!(layout.material_index, 1);
+        let (_, texcoords) = layout.attribs.iter().find(|(a, _)| *a == utils::ATTRIB_TEXCOORD).unwrap();
+        assert_eq!((texcoords.data_type, texcoords.size, texcoords.normalized), (gl::FLOAT, 2, false));
+        match &texcoords.source {
+            AccessorSource::Packed(bytes) => assert_eq!(floats(bytes), vec![0.0, 0.0, 0.0, 0.0, 0.25, 0.75]),
+            s => panic!("expected packed data, got {:?}", s),
         }
+        assert!(layout.center.abs_diff_eq(glam::Vec3::new(0.5, 0.5, 0.0), 1e-6));
     }
 }
@@ -0,0 +1,424 @@
+// VRM 0.x compatibility: convert the `VRM` extension into the VRMC_vrm and VRMC_springBone models.
+//
+// VRM 0.x models face -Z and write their vectors in Unity's left-handed coordinates, while VRM 1.0
+// models face +Z. Like UniVRM's migration, we turn the whole model half a turn about Y: vertex data is
+// flipped in the buffers, node transforms and inverse bind matrices are conjugated by the flip, and
+// Unity vectors are converted straight into the flipped space.
+
+use std::collections::HashMap;
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use gltf::json::extensions::root::{
+    HumanBone, MaterialValueBind, MorphTargetBind, SpringCollider, SpringColliderGroup, SpringColliderShape,
+    SpringJoint, SpringSphere, TextureTransformBind, Spring, Vrm0, Vrm0BoneGroup, Vrm0DegreeMap, Vrm0Vec3,
+    VrmExpression, VrmExpressions, VrmHumanoid, VrmLookAt, VrmLookAtRangeMap, VrmcSpringBone, VrmcVrm,
+};
+
+/// VRM 0.x preset names and the VRM 1.0 expressions they became.
+const PRESETS: [(&str, &str); 17] = [
+    ("a", "aa"),
+    ("i", "ih"),
+    ("u", "ou"),
+    ("e", "ee"),
+    ("o", "oh"),
+    ("blink", "blink"),
+    ("blink_l", "blinkLeft"),
+    ("blink_r", "blinkRight"),
+    ("joy", "happy"),
+    ("angry", "angry"),
+    ("sorrow", "sad"),
+    ("fun", "relaxed"),
+    ("surprised", "surprised"),
+    ("lookup", "lookUp"),
+    ("lookdown", "lookDown"),
+    ("lookleft", "lookLeft"),
+    ("lookright", "lookRight"),
+];
+
+/// The VRM 1.0 expression a VRM 0.x preset name became, ignoring case.
+fn preset_name(nm: &str) -> Option<&'static str> {
+    PRESETS.iter().find(|(old, _)| nm.eq_ignore_ascii_case(old)).map(|(_, new)| *new)
+}
+
+/// Half a turn about Y. It is its own inverse.
+pub fn flip_matrix(m: &glam::Mat4) -> glam::Mat4 {
+    let f = glam::Mat4::from_scale(glam::Vec3::new(-1.0, 1.0, -1.0));
+    f.mul_mat4(m).mul_mat4(&f)
+}
+
+/// A Unity vector as a VRM 1.0 one: mirror X into glTF, then turn half about Y.
+fn from_unity(v: &Vrm0Vec3) -> [f32; 3] {
+    [v.x, v.y, -v.z]
+}
+
+/// VRM 0.x humanoid bone names, which are Unity's HumanBodyBones with a lowercase first letter, and
+/// their VRM 1.0 names. Only the thumbs were renamed: 0.x has no metacarpal, so its proximal became
+/// 1.0's metacarpal and its intermediate became 1.0's proximal.
+const BONE_NAMES: [(&str, &str); 55] = [
+    ("hips", "hips"),
+    ("spine", "spine"),
+    ("chest", "chest"),
+    ("upperChest", "upperChest"),
+    ("neck", "neck"),
+    ("head", "head"),
+    ("leftEye", "leftEye"),
+    ("rightEye", "rightEye"),
+    ("jaw", "jaw"),
+    ("leftUpperLeg", "leftUpperLeg"),
+    ("leftLowerLeg", "leftLowerLeg"),
+    ("leftFoot", "leftFoot"),
+    ("leftToes", "leftToes"),
+    ("leftShoulder", "leftShoulder"),
+    ("leftUpperArm", "leftUpperArm"),
+    ("leftLowerArm", "leftLowerArm"),
+    ("leftHand", "leftHand"),
+    ("leftThumbProximal", "leftThumbMetacarpal"),
+    ("leftThumbIntermediate", "leftThumbProximal"),
+    ("leftThumbDistal", "leftThumbDistal"),
+    ("leftIndexProximal", "leftIndexProximal"),
+    ("leftIndexIntermediate", "leftIndexIntermediate"),
+    ("leftIndexDistal", "leftIndexDistal"),
+    ("leftMiddleProximal", "leftMiddleProximal"),
+    ("leftMiddleIntermediate", "leftMiddleIntermediate"),
+    ("leftMiddleDistal", "leftMiddleDistal"),
+    ("leftRingProximal", "leftRingProximal"),
+    ("leftRingIntermediate", "leftRingIntermediate"),
+    ("leftRingDistal", "leftRingDistal"),
+    ("leftLittleProximal", "leftLittleProximal"),
+    ("leftLittleIntermediate", "leftLittleIntermediate"),
+    ("leftLittleDistal", "leftLittleDistal"),
+    ("rightUpperLeg", "rightUpperLeg"),
+    ("rightLowerLeg", "rightLowerLeg"),
+    ("rightFoot", "rightFoot"),
+    ("rightToes", "rightToes"),
+    ("rightShoulder", "rightShoulder"),
+    ("rightUpperArm", "rightUpperArm"),
+    ("rightLowerArm", "rightLowerArm"),
+    ("rightHand", "rightHand"),
+    ("rightThumbProximal", "rightThumbMetacarpal"),
+    ("rightThumbIntermediate", "rightThumbProximal"),
+    ("rightThumbDistal", "rightThumbDistal"),
+    ("rightIndexProximal", "rightIndexProximal"),
+    ("rightIndexIntermediate", "rightIndexIntermediate"),
+    ("rightIndexDistal", "rightIndexDistal"),
+    ("rightMiddleProximal", "rightMiddleProximal"),
+    ("rightMiddleIntermediate", "rightMiddleIntermediate"),
+    ("rightMiddleDistal", "rightMiddleDistal"),
+    ("rightRingProximal", "rightRingProximal"),
+    ("rightRingIntermediate", "rightRingIntermediate"),
+    ("rightRingDistal", "rightRingDistal"),
+    ("rightLittleProximal", "rightLittleProximal"),
+    ("rightLittleIntermediate", "rightLittleIntermediate"),
+    ("rightLittleDistal", "rightLittleDistal"),
+];
+
+/// The VRM 1.0 name of a VRM 0.x or Unity bone name.
+pub fn bone_name(nm: &str) -> Option<&'static str> {
+    BONE_NAMES.iter().find(|(v0, _)| v0.eq_ignore_ascii_case(nm)).map(|(_, v1)| *v1)
+}
+
+/// The Unity HumanBodyBones name of a VRM 1.0 bone name.
+pub fn unity_bone_name(nm: &str) -> Option<String> {
+    let (v0, _) = BONE_NAMES.iter().find(|(_, v1)| *v1 == nm)?;
+    let mut cs = v0.chars();
+    Some(cs.next()?.to_uppercase().chain(cs).collect())
+}
+
+fn range_map(m: &Vrm0DegreeMap) -> VrmLookAtRangeMap {
+    VrmLookAtRangeMap {
+        input_max_value: m.x_range,
+        output_scale: m.y_range,
+    }
+}
+
+/// A `_MainTex_ST` material value (scale then offset) as a texture transform bind. Unity's UV origin
+/// is the bottom left and glTF's the top left, so the V offset is measured from the other edge.
+fn texture_transform(material: u32, st: [f32; 4]) -> TextureTransformBind {
+    TextureTransformBind {
+        material,
+        scale: vec![st[0], st[1]],
+        offset: vec![st[2], 1.0 - st[3] - st[1]],
+    }
+}
+
+fn expressions(gltf: &gltf::Document, v0: &Vrm0) -> VrmExpressions {
+    let mut out = VrmExpressions::default();
+    for g in &v0.blend_shape_master.blend_shape_groups {
+        let morph_target_binds = g.binds.iter().filter_map(|b| {
+            let node = gltf.nodes().find(|n| n.mesh().map(|m| m.index()) == Some(b.mesh as usize));
+            if node.is_none() {
+                log::warn!("blend shape {} binds mesh {}, which no node uses", g.name, b.mesh);
+            }
+            Some(MorphTargetBind {
+                node: node?.index() as _,
+                index: b.index,
+                weight: b.weight / 100.0,
+            })
+        }).collect();
+        let mut material_color_binds = Vec::new();
+        let mut texture_transform_binds = Vec::new();
+        for mv in &g.material_values {
+            let material = match gltf.materials().position(|m| m.name() == Some(mv.material_name.as_str())) {
+                Some(m) => m as u32,
+                None => {
+                    log::warn!("blend shape {} refers to missing material {}", g.name, mv.material_name);
+                    continue;
+                },
+            };
+            let t = |i: usize| mv.target_value.get(i).copied().unwrap_or(0.0);
+            let type_ = match mv.property_name.as_str() {
+                "_Color" => "color",
+                "_EmissionColor" => "emissionColor",
+                "_ShadeColor" => "shadeColor",
+                "_RimColor" => "rimColor",
+                "_OutlineColor" => "outlineColor",
+                "_MainTex_ST" => {
+                    texture_transform_binds.push(texture_transform(material, [t(0), t(1), t(2), t(3)]));
+                    continue;
+                },
+                other => {
+                    log::warn!("blend shape {}: unsupported material property {}", g.name, other);
+                    continue;
+                },
+            };
+            material_color_binds.push(MaterialValueBind {
+                material,
+                type_: type_.to_owned(),
+                target_value: mv.target_value.clone(),
+            });
+        }
+        let e = VrmExpression {
+            is_binary: g.is_binary,
+            morph_target_binds,
+            material_color_binds,
+            texture_transform_binds,
+            ..VrmExpression::default()
+        };
+        match preset_name(&g.preset_name) {
+            Some(nm) => { out.preset.entry(nm.to_owned()).or_insert(e); },
+            None if !g.name.is_empty() => { out.custom.entry(g.name.clone()).or_insert(e); },
+            None => {},
+        }
+    }
+    out
+}
+
+/// VRM 0.x simulates every descendant of a group's root bones; VRM 1.0 springs are single chains.
+/// Follow the first child down, and start a new chain at every other child along the way. Chains
+/// end at a leaf, which swings too (see `LEAF_TAIL`), so a lone leaf is a chain of its own.
+fn chains(gltf: &gltf::Document, root: usize, out: &mut Vec<Vec<usize>>) {
+    let mut chain = vec![root];
+    let mut node = gltf.nodes().nth(root);
+    while let Some(n) = node {
+        let mut children = n.children();
+        node = children.next();
+        if let Some(c) = &node {
+            chain.push(c.index());
+        }
+        for c in children {
+            chains(gltf, c.index(), out);
+        }
+    }
+    out.push(chain);
+}
+
+/// How far past a leaf bone VRM 0.x puts its tail, as UniVRM does when it has no child to point at.
+const LEAF_TAIL: f32 = 0.07;
+
+fn springs(gltf: &gltf::Document, group: &Vrm0BoneGroup) -> Vec<Spring> {
+    let mut nodes = Vec::new();
+    for root in &group.bones {
+        chains(gltf, *root as _, &mut nodes);
+    }
+    nodes.into_iter().map(|chain| Spring {
+        name: group.comment.clone(),
+        joints: chain.into_iter().map(|node| SpringJoint {
+            node: node as _,
+            hit_radius: group.hit_radius,
+            stiffness: group.stiffness,
+            gravity_power: group.gravity_power,
+            gravity_dir: from_unity(&group.gravity_dir),
+            drag_force: group.drag_force,
+        }).collect(),
+        collider_groups: group.collider_groups.clone(),
+        center: if group.center >= 0 { Some(group.center as _) } else { None },
+        leaf_tail: Some(LEAF_TAIL),
+    }).collect()
+}
+
+fn spring_bone(gltf: &gltf::Document, v0: &Vrm0) -> VrmcSpringBone {
+    let sa = &v0.secondary_animation;
+    let mut colliders = Vec::new();
+    let collider_groups = sa.collider_groups.iter().map(|g| SpringColliderGroup {
+        name: String::new(),
+        colliders: g.colliders.iter().map(|c| {
+            colliders.push(SpringCollider {
+                node: g.node,
+                shape: SpringColliderShape {
+                    sphere: Some(SpringSphere { offset: from_unity(&c.offset), radius: c.radius }),
+                    capsule: None,
+                },
+            });
+            (colliders.len() - 1) as u32
+        }).collect(),
+    }).collect();
+    VrmcSpringBone {
+        spec_version: "0.0".to_owned(),
+        colliders,
+        collider_groups,
+        springs: sa.bone_groups.iter().flat_map(|g| springs(gltf, g)).collect(),
+    }
+}
+
+/// Convert a VRM 0.x extension into the VRM 1.0 models the renderer uses.
+pub fn convert(gltf: &gltf::Document, v0: &Vrm0) -> (VrmcVrm, VrmcSpringBone) {
+    let human_bones: HashMap<String, HumanBone> = v0.humanoid.human_bones.iter()
+        .filter_map(|b| match bone_name(&b.bone) {
+            Some(nm) => Some((nm.to_owned(), HumanBone { node: b.node })),
+            None => {
+                log::warn!("ignoring unknown VRM 0.x bone {}", b.bone);
+                None
+            },
+        })
+        .collect();
+    let fp = &v0.first_person;
+    let look_at = VrmLookAt {
+        offset_from_head_bone: from_unity(&fp.first_person_bone_offset),
+        type_: if fp.look_at_type_name == "BlendShape" { "expression" } else { "bone" }.to_owned(),
+        range_map_horizontal_inner: Some(range_map(&fp.look_at_horizontal_inner)),
+        range_map_horizontal_outer: Some(range_map(&fp.look_at_horizontal_outer)),
+        range_map_vertical_down: Some(range_map(&fp.look_at_vertical_down)),
+        range_map_vertical_up: Some(range_map(&fp.look_at_vertical_up)),
+    };
+    let vrm = VrmcVrm {
+        spec_version: v0.spec_version.clone(),
+        expressions: expressions(gltf, v0),
+        humanoid: VrmHumanoid { human_bones },
+        look_at: Some(look_at),
+    };
+    (vrm, spring_bone(gltf, v0))
+}
+
+/// Turn every float vec3 or vec4 element of an accessor half about Y, in place. `size` is the
+/// element size, the stride of tightly packed views.
+fn flip_elements(buffers: &mut Vec<gltf::buffer::Data>, view: &gltf::buffer::View, offset: usize, count: usize, size: usize) {
+    let stride = view.stride().unwrap_or(size);
+    let data = match buffers.get_mut(view.buffer().index()) {
+        Some(d) => &mut d.0,
+        None => return,
+    };
+    for i in 0..count {
+        let start = view.offset() + offset + i * stride;
+        if start + 12 > data.len() || offset + i * stride + 12 > view.length() {
+            break;
+        }
+        for c in [start, start + 8] {
+            let v = LittleEndian::read_f32(&data[c..c + 4]);
+            LittleEndian::write_f32(&mut data[c..c + 4], -v);
+        }
+    }
+}
+
+/// Flip positions, normals, tangents and morph target deltas in the buffers themselves, so that
+/// uploading them needs no special case. Tangent handedness (w) is kept, since a half turn is not a mirror.
+pub fn flip_buffers(gltf: &gltf::Document, buffers: &mut Vec<gltf::buffer::Data>) {
+    let mut seen = std::collections::HashSet::new();
+    for m in gltf.meshes() {
+        for p in m.primitives() {
+            let mut accessors: Vec<gltf::Accessor> = p.attributes()
+                .filter(|(s, _)| match s {
+                    gltf::Semantic::Positions | gltf::Semantic::Normals | gltf::Semantic::Tangents => true,
+                    _ => false,
+                })
+                .map(|(_, a)| a)
+                .collect();
+            for t in p.morph_targets() {
+                accessors.extend(t.positions());
+                accessors.extend(t.normals());
+                accessors.extend(t.tangents());
+            }
+            for a in accessors {
+                let vector = match a.dimensions() {
+                    gltf::accessor::Dimensions::Vec3 | gltf::accessor::Dimensions::Vec4 => true,
+                    _ => false,
+                };
+                if !vector || a.data_type() != gltf::accessor::DataType::F32 || !seen.insert(a.index()) {
+                    continue;
+                }
+                if let Some(view) = a.view() {
+                    flip_elements(buffers, &view, a.offset(), a.count(), a.size());
+                }
+                if let Some(sparse) = a.sparse() {
+                    let values = sparse.values();
+                    flip_elements(buffers, &values.view(), values.offset() as _, sparse.count() as _, a.size());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn thumbs_shift_down_one_bone() {
+        assert_eq!(bone_name("leftThumbProximal"), Some("leftThumbMetacarpal"));
+        assert_eq!(bone_name("LeftThumbIntermediate"), Some("leftThumbProximal"));
+        assert_eq!(bone_name("rightThumbDistal"), Some("rightThumbDistal"));
+        assert_eq!(bone_name("Hips"), Some("hips"));
+        assert_eq!(bone_name("leftThumbMetacarpal"), None);
+        assert_eq!(unity_bone_name("leftThumbMetacarpal").as_deref(), Some("LeftThumbProximal"));
+        assert_eq!(unity_bone_name("rightThumbProximal").as_deref(), Some("RightThumbIntermediate"));
+        assert_eq!(unity_bone_name("head").as_deref(), Some("Head"));
+        assert_eq!(unity_bone_name("tail"), None);
+    }
+
+    #[test]
+    fn main_tex_st_offset_is_measured_from_the_top() {
+        let bind = texture_transform(3, [0.5, 0.25, 0.1, 0.2]);
+        assert_eq!(bind.material, 3);
+        assert_eq!(bind.scale, vec![0.5, 0.25]);
+        assert_eq!(bind.offset, vec![0.1, 1.0 - 0.2 - 0.25]);
+        // the identity transform stays the identity
+        let identity = texture_transform(0, [1.0, 1.0, 0.0, 0.0]);
+        assert_eq!((identity.scale, identity.offset), (vec![1.0, 1.0], vec![0.0, 0.0]));
+    }
+
+    #[test]
+    fn splits_branching_groups_into_chains() {
+        // 0 -> 1 -> 2, with 1 also branching to 3 -> 4 and to the lone leaf 5
+        let json = br#"{
+            "asset": {"version": "2.0"},
+            "nodes": [{"children": [1]}, {"children": [2, 3, 5]}, {}, {"children": [4]}, {}, {}]
+        }"#;
+        let gltf = gltf::Gltf::from_slice(json).unwrap();
+        let mut out = Vec::new();
+        chains(&gltf.document, 0, &mut out);
+        out.sort();
+        assert_eq!(out, vec![vec![0, 1, 2], vec![3, 4], vec![5]]);
+    }
+
+    #[test]
+    fn maps_renamed_presets() {
+        assert_eq!(preset_name("joy"), Some("happy"));
+        assert_eq!(preset_name("Sorrow"), Some("sad"));
+        assert_eq!(preset_name("FUN"), Some("relaxed"));
+        assert_eq!(preset_name("blink_l"), Some("blinkLeft"));
+        assert_eq!(preset_name("unknown"), None);
+    }
+
+    #[test]
+    fn flip_is_its_own_inverse() {
+        let m = glam::Mat4::from_scale_rotation_translation(
+            glam::Vec3::new(1.0, 2.0, 0.5),
+            glam::Quat::from_euler(glam::EulerRot::XYZ, 0.3, -0.7, 1.1),
+            glam::Vec3::new(1.0, 2.0, 3.0),
+        );
+        assert!(flip_matrix(&flip_matrix(&m)).abs_diff_eq(m, 1e-6));
+        // a half turn about Y negates X and Z
+        let t = flip_matrix(&glam::Mat4::from_translation(glam::Vec3::new(1.0, 2.0, 3.0)));
+        assert_eq!(t.w_axis.truncate(), glam::Vec3::new(-1.0, 2.0, -3.0));
+    }
+}